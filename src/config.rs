@@ -0,0 +1,430 @@
+use std::fmt::Display;
+use std::path::{ Path, PathBuf };
+use json::JsonValue;
+
+use crate::math::FloatType as float;
+
+/// Config file loaded when no `--config` option is given. It is optional.
+pub const DEFAULT_CONFIG_PATH: &str = "hexapod.json";
+pub const DEFAULT_MONITOR_ADDR: &str = "127.0.0.1:8080";
+pub const DEFAULT_CONTROL_ADDR: &str = "127.0.0.1:8081";
+pub const DEFAULT_COMMAND_TIMEOUT: u32 = 1000;
+pub const DEFAULT_LEASE_TIMEOUT: u32 = 2000;
+
+pub const USAGE: &str = "\
+Usage: hexapod_control [OPTIONS]
+
+Options:
+    --config <PATH>     Config file to load (default: hexapod.json, if it exists)
+    --monitor <ADDR>    Address of the monitor endpoint (default: 127.0.0.1:8080)
+    --control <ADDR>    Address of the control endpoint (default: 127.0.0.1:8081)
+    --listen <ADDR>     Serve both endpoints on a single address, routed by the paths
+                        /monitor and /control
+    --udp <ADDR>        Also accept control messages as UDP datagrams on the given address
+    --http <ADDR>       Serve the HTTP API and the web control panel on the given
+                        address
+    --command-timeout <MS>
+                        Stop the robot if no control command was received for the given
+                        time, 0 disables the timeout (default: 1000)
+    --record <PATH>     Record the control session to the given file
+    --replay <PATH>     Replay a recorded session without the network and compare the leg
+                        positions with the recording
+    --csv <PATH>        Export the foot and knee positions, joint angles, gait phases and body
+                        pose of every tick as CSV, while running or replaying
+    --gait-diagram <PATH>
+                        Render the foot trajectories and the timing of a gait cycle as SVG
+                        and exit
+    --step <X,Y>        Step of the gait diagram, normalised like in control messages
+                        (default: 0,1)
+    --turn <TURN>       Turn of the gait diagram, from -1 to 1 (default: 0)
+    --lift-ratio <RATIO>
+                        Portion of the gait cycle a leg is lifted, between 0 and 1 exclusive
+                        (default: 0.3)
+    --help              Print this help";
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, std::io::Error),
+    Parse(json::Error),
+    Invalid(String)
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "cannot read config file {}: {}", path.display(), e),
+            ConfigError::Parse(e) => write!(f, "cannot parse config file: {}", e),
+            ConfigError::Invalid(s) => write!(f, "invalid config: {}", s)
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<json::Error> for ConfigError {
+    fn from(e: json::Error) -> Self {
+        ConfigError::Parse(e)
+    }
+}
+
+/// Addresses the servers bind to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Endpoints {
+    /// Monitor and control clients connect to separate addresses.
+    Separate { monitor: String, control: String },
+    /// Monitor and control clients connect to the same address and are routed by the request
+    /// path.
+    Shared(String)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetworkConfig {
+    pub endpoints: Endpoints,
+    /// Address of the UDP control endpoint. `None` disables it.
+    pub udp: Option<String>,
+    /// Address of the HTTP API. `None` disables it.
+    pub http: Option<String>
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        NetworkConfig {
+            endpoints: Endpoints::Separate {
+                monitor: DEFAULT_MONITOR_ADDR.to_string(),
+                control: DEFAULT_CONTROL_ADDR.to_string()
+            },
+            udp: None,
+            http: None
+        }
+    }
+}
+
+impl NetworkConfig {
+    /// Sets the monitor address. A shared endpoint is split up, with the control endpoint
+    /// falling back to its default address.
+    fn set_monitor(&mut self, addr: &str) {
+        let control = match &self.endpoints {
+            Endpoints::Separate { control, .. } => control.clone(),
+            Endpoints::Shared(_) => DEFAULT_CONTROL_ADDR.to_string()
+        };
+        self.endpoints = Endpoints::Separate { monitor: addr.to_string(), control };
+    }
+
+    /// Sets the control address. A shared endpoint is split up, with the monitor endpoint
+    /// falling back to its default address.
+    fn set_control(&mut self, addr: &str) {
+        let monitor = match &self.endpoints {
+            Endpoints::Separate { monitor, .. } => monitor.clone(),
+            Endpoints::Shared(_) => DEFAULT_MONITOR_ADDR.to_string()
+        };
+        self.endpoints = Endpoints::Separate { monitor, control: addr.to_string() };
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ControlConfig {
+    /// Time in ms without control commands after which the robot is stopped. `None` disables
+    /// the timeout.
+    pub command_timeout: Option<u32>,
+    /// Time in ms without commands after which the control lease of a client expires.
+    pub lease_timeout: u32,
+    /// Token that lets a client take over control at any time, e.g. a safety console.
+    pub privileged_token: Option<String>
+}
+
+impl Default for ControlConfig {
+    fn default() -> Self {
+        ControlConfig {
+            command_timeout: Some(DEFAULT_COMMAND_TIMEOUT),
+            lease_timeout: DEFAULT_LEASE_TIMEOUT,
+            privileged_token: None
+        }
+    }
+}
+
+fn timeout_from_ms(ms: u32) -> Option<u32> {
+    if ms > 0 { Some(ms) } else { None }
+}
+
+/// Application settings, read from the config file and the command line.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AppConfig {
+    pub network: NetworkConfig,
+    pub control: ControlConfig
+}
+
+/// Options given on the command line.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CliOptions {
+    pub config: Option<PathBuf>,
+    pub monitor: Option<String>,
+    pub control: Option<String>,
+    pub listen: Option<String>,
+    pub udp: Option<String>,
+    pub http: Option<String>,
+    pub command_timeout: Option<u32>,
+    pub record: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+    pub csv: Option<PathBuf>,
+    pub gait_diagram: Option<PathBuf>,
+    pub step: Option<[float; 2]>,
+    pub turn: Option<float>,
+    pub lift_ratio: Option<float>,
+    pub help: bool
+}
+
+impl CliOptions {
+    /// Parses the command line arguments, without the program name.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, ConfigError> {
+        let mut options = CliOptions::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next()
+                .ok_or_else(|| ConfigError::Invalid(format!("missing value for {}", name)));
+
+            match arg.as_str() {
+                "--config" => options.config = Some(PathBuf::from(value(&arg)?)),
+                "--monitor" => options.monitor = Some(value(&arg)?),
+                "--control" => options.control = Some(value(&arg)?),
+                "--listen" => options.listen = Some(value(&arg)?),
+                "--udp" => options.udp = Some(value(&arg)?),
+                "--http" => options.http = Some(value(&arg)?),
+                "--command-timeout" => {
+                    let ms = value(&arg)?;
+                    options.command_timeout = Some(ms.parse()
+                        .map_err(|_| ConfigError::Invalid(format!("invalid command timeout: {}", ms)))?);
+                },
+                "--record" => options.record = Some(PathBuf::from(value(&arg)?)),
+                "--replay" => options.replay = Some(PathBuf::from(value(&arg)?)),
+                "--csv" => options.csv = Some(PathBuf::from(value(&arg)?)),
+                "--gait-diagram" => options.gait_diagram = Some(PathBuf::from(value(&arg)?)),
+                "--step" => {
+                    let step = value(&arg)?;
+                    let invalid = || ConfigError::Invalid(format!("invalid step: {}", step));
+                    let (x, y) = step.split_once(',').ok_or_else(invalid)?;
+                    let step = [parse_float(x.trim()).ok_or_else(invalid)?, parse_float(y.trim()).ok_or_else(invalid)?];
+                    if step.iter().any(|v| v.abs() > 1.0) {
+                        return Err(invalid());
+                    }
+                    options.step = Some(step);
+                },
+                "--turn" => {
+                    let turn = value(&arg)?;
+                    options.turn = Some(parse_float(&turn).filter(|v| v.abs() <= 1.0)
+                        .ok_or_else(|| ConfigError::Invalid(format!("invalid turn: {}", turn)))?);
+                },
+                "--lift-ratio" => {
+                    let ratio = value(&arg)?;
+                    options.lift_ratio = Some(parse_float(&ratio).filter(|v| *v > 0.0 && *v < 1.0)
+                        .ok_or_else(|| ConfigError::Invalid(format!("invalid lift ratio: {}", ratio)))?);
+                },
+                "--help" | "-h" => options.help = true,
+                _ => return Err(ConfigError::Invalid(format!("unknown option: {}", arg)))
+            }
+        }
+
+        if options.listen.is_some() && (options.monitor.is_some() || options.control.is_some()) {
+            return Err(ConfigError::Invalid("--listen cannot be combined with --monitor or --control".to_string()));
+        }
+        if options.gait_diagram.is_none() && (options.step.is_some() || options.turn.is_some() || options.lift_ratio.is_some()) {
+            return Err(ConfigError::Invalid("--step, --turn and --lift-ratio require --gait-diagram".to_string()));
+        }
+        if options.record.is_some() && options.replay.is_some() {
+            return Err(ConfigError::Invalid("--record cannot be combined with --replay".to_string()));
+        }
+
+        Ok(options)
+    }
+}
+
+fn parse_float(s: &str) -> Option<float> {
+    s.parse::<float>().ok().filter(|v| v.is_finite())
+}
+
+fn parse_addr(data: &JsonValue, name: &str) -> Result<Option<String>, ConfigError> {
+    if data[name].is_null() {
+        return Ok(None);
+    }
+    match data[name].as_str() {
+        Some(addr) if !addr.is_empty() => Ok(Some(addr.to_string())),
+        _ => Err(ConfigError::Invalid(format!("network.{} must be an address string", name)))
+    }
+}
+
+impl AppConfig {
+    /// Parses a config file. Missing settings keep their default values.
+    ///
+    /// ```json
+    /// { "network": { "monitor": "0.0.0.0:8080", "control": "0.0.0.0:8081" } }
+    /// { "network": { "listen": "0.0.0.0:8080", "udp": "0.0.0.0:8082", "http": "0.0.0.0:8083" }, "control": { "command_timeout": 500, "lease_timeout": 2000, "privileged_token": "..." } }
+    /// ```
+    pub fn from_json(text: &str) -> Result<Self, ConfigError> {
+        let data = json::parse(text)?;
+        let mut config = AppConfig::default();
+
+        let network = &data["network"];
+        if !network.is_null() {
+            let listen = parse_addr(network, "listen")?;
+            let monitor = parse_addr(network, "monitor")?;
+            let control = parse_addr(network, "control")?;
+            config.network.udp = parse_addr(network, "udp")?;
+            config.network.http = parse_addr(network, "http")?;
+
+            if let Some(listen) = listen {
+                if monitor.is_some() || control.is_some() {
+                    return Err(ConfigError::Invalid("network.listen cannot be combined with network.monitor or network.control".to_string()));
+                }
+                config.network.endpoints = Endpoints::Shared(listen);
+            }
+            if let Some(monitor) = monitor {
+                config.network.set_monitor(&monitor);
+            }
+            if let Some(control) = control {
+                config.network.set_control(&control);
+            }
+        }
+
+        let control = &data["control"];
+        if !control["command_timeout"].is_null() {
+            let ms = control["command_timeout"].as_u32()
+                .ok_or_else(|| ConfigError::Invalid("control.command_timeout must be a non-negative integer".to_string()))?;
+            config.control.command_timeout = timeout_from_ms(ms);
+        }
+        if !control["lease_timeout"].is_null() {
+            config.control.lease_timeout = control["lease_timeout"].as_u32()
+                .filter(|&ms| ms > 0)
+                .ok_or_else(|| ConfigError::Invalid("control.lease_timeout must be a positive integer".to_string()))?;
+        }
+        if !control["privileged_token"].is_null() {
+            let token = control["privileged_token"].as_str()
+                .filter(|token| !token.is_empty())
+                .ok_or_else(|| ConfigError::Invalid("control.privileged_token must be a non-empty string".to_string()))?;
+            config.control.privileged_token = Some(token.to_string());
+        }
+
+        Ok(config)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let text = std::fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+        Self::from_json(&text)
+    }
+
+    /// Loads the config file selected by the command line options and applies the options on
+    /// top of it.
+    pub fn from_options(options: &CliOptions) -> Result<Self, ConfigError> {
+        let mut config = match &options.config {
+            Some(path) => AppConfig::load(path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => AppConfig::load(Path::new(DEFAULT_CONFIG_PATH))?,
+            None => AppConfig::default()
+        };
+
+        if let Some(listen) = &options.listen {
+            config.network.endpoints = Endpoints::Shared(listen.clone());
+        }
+        if let Some(monitor) = &options.monitor {
+            config.network.set_monitor(monitor);
+        }
+        if let Some(control) = &options.control {
+            config.network.set_control(control);
+        }
+        if let Some(udp) = &options.udp {
+            config.network.udp = Some(udp.clone());
+        }
+        if let Some(http) = &options.http {
+            config.network.http = Some(http.clone());
+        }
+        if let Some(ms) = options.command_timeout {
+            config.control.command_timeout = timeout_from_ms(ms);
+        }
+
+        Ok(config)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{ AppConfig, CliOptions, Endpoints };
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn cli_options() {
+        let options = CliOptions::parse(args(&["--monitor", "0.0.0.0:9000"])).unwrap();
+        let config = AppConfig::from_options(&options).unwrap();
+        assert_eq!(config.network.endpoints, Endpoints::Separate {
+            monitor: "0.0.0.0:9000".to_string(),
+            control: "127.0.0.1:8081".to_string()
+        });
+
+        let options = CliOptions::parse(args(&["--listen", "0.0.0.0:8080"])).unwrap();
+        assert_eq!(options.listen.as_deref(), Some("0.0.0.0:8080"));
+
+        let options = CliOptions::parse(args(&["--listen", "0.0.0.0:8080", "--udp", "0.0.0.0:8082", "--http", "0.0.0.0:8083"])).unwrap();
+        let config = AppConfig::from_options(&options).unwrap();
+        assert_eq!(config.network.udp.as_deref(), Some("0.0.0.0:8082"));
+        assert_eq!(config.network.http.as_deref(), Some("0.0.0.0:8083"));
+
+        assert!(CliOptions::parse(args(&["--monitor"])).is_err());
+        assert!(CliOptions::parse(args(&["--port", "80"])).is_err());
+        assert!(CliOptions::parse(args(&["--listen", "a:1", "--control", "b:2"])).is_err());
+        assert!(CliOptions::parse(args(&["--help"])).unwrap().help);
+
+        let options = CliOptions::parse(args(&["--record", "session.jsonl"])).unwrap();
+        assert_eq!(options.record.as_deref(), Some(std::path::Path::new("session.jsonl")));
+        assert!(CliOptions::parse(args(&["--record", "a.jsonl", "--replay", "b.jsonl"])).is_err());
+        let options = CliOptions::parse(args(&["--replay", "a.jsonl", "--csv", "a.csv"])).unwrap();
+        assert_eq!(options.csv.as_deref(), Some(std::path::Path::new("a.csv")));
+
+        let options = CliOptions::parse(args(&["--gait-diagram", "gait.svg", "--step", "0.5, -1", "--turn", "0.2", "--lift-ratio", "0.4"])).unwrap();
+        assert_eq!(options.step, Some([0.5, -1.0]));
+        assert_eq!(options.turn, Some(0.2));
+        assert_eq!(options.lift_ratio, Some(0.4));
+        assert!(CliOptions::parse(args(&["--gait-diagram", "gait.svg", "--step", "0.5"])).is_err());
+        assert!(CliOptions::parse(args(&["--gait-diagram", "gait.svg", "--step", "2,0"])).is_err());
+        assert!(CliOptions::parse(args(&["--gait-diagram", "gait.svg", "--lift-ratio", "1"])).is_err());
+        assert!(CliOptions::parse(args(&["--turn", "0.2"])).is_err());
+
+        let options = CliOptions::parse(args(&["--command-timeout", "0"])).unwrap();
+        assert_eq!(AppConfig::from_options(&options).unwrap().control.command_timeout, None);
+        assert!(CliOptions::parse(args(&["--command-timeout", "-5"])).is_err());
+    }
+
+    #[test]
+    fn config_file() {
+        let config = AppConfig::from_json(r#"{ "network": { "control": "0.0.0.0:8081" } }"#).unwrap();
+        assert_eq!(config.network.endpoints, Endpoints::Separate {
+            monitor: "127.0.0.1:8080".to_string(),
+            control: "0.0.0.0:8081".to_string()
+        });
+
+        let config = AppConfig::from_json(r#"{ "network": { "listen": "0.0.0.0:8080" } }"#).unwrap();
+        assert_eq!(config.network.endpoints, Endpoints::Shared("0.0.0.0:8080".to_string()));
+        assert_eq!(config.network.udp, None);
+
+        let config = AppConfig::from_json(r#"{ "network": { "udp": "0.0.0.0:8082", "http": "0.0.0.0:8083" } }"#).unwrap();
+        assert_eq!(config.network.udp.as_deref(), Some("0.0.0.0:8082"));
+        assert_eq!(config.network.http.as_deref(), Some("0.0.0.0:8083"));
+
+        let config = AppConfig::from_json(r#"{ "control": { "command_timeout": 250 } }"#).unwrap();
+        assert_eq!(config.control.command_timeout, Some(250));
+
+        let config = AppConfig::from_json(r#"{ "control": { "lease_timeout": 500, "privileged_token": "abc" } }"#).unwrap();
+        assert_eq!(config.control.lease_timeout, 500);
+        assert_eq!(config.control.privileged_token.as_deref(), Some("abc"));
+        assert!(AppConfig::from_json(r#"{ "control": { "lease_timeout": 0 } }"#).is_err());
+        assert!(AppConfig::from_json(r#"{ "control": { "privileged_token": "" } }"#).is_err());
+
+        let config = AppConfig::from_json("{}").unwrap();
+        assert_eq!(config, AppConfig::default());
+
+        assert!(AppConfig::from_json(r#"{ "network": { "listen": "a:1", "monitor": "b:2" } }"#).is_err());
+        assert!(AppConfig::from_json(r#"{ "network": { "monitor": 8080 } }"#).is_err());
+        assert!(AppConfig::from_json(r#"{ "control": { "command_timeout": "1s" } }"#).is_err());
+        assert!(AppConfig::from_json("{").is_err());
+    }
+}
@@ -0,0 +1,214 @@
+//! Request bodies of the HTTP API.
+//!
+//! | Request          | Body                                  | Reply                          |
+//! |------------------|---------------------------------------|--------------------------------|
+//! | `GET /state`     |                                       | `state` message                |
+//! | `GET /config`    |                                       | `config` message               |
+//! | `PUT /config`    | partial config, only the limits       | `config` message               |
+//! | `POST /command`  | `{ "action": "sit" }` or `{ "action": "stand" }` | `ack` message       |
+//! | `GET /metrics`   |                                       | metrics in the Prometheus text format |
+//!
+//! `GET /` serves a web control panel that drives the robot through the WebSocket endpoints.
+//!
+//! Replies and errors use the message format of the control protocol. The config contains the
+//! leg geometry, the lift ratio of the gait and the motion limits; only the limits can be
+//! changed, and only while the robot is standing still:
+//!
+//! ```json
+//! { "max_speed": 0.12, "max_body_offset": { "x": 0.02, "y": 0.02, "z": 0.03 } }
+//! ```
+//!
+//! `PUT /config` and `POST /command` are rejected with a `not_owner` error while a control
+//! client holds the lease.
+
+use json::JsonValue;
+
+use crate::math::{ FloatType as float, FloatModule, Vector3 };
+use crate::robot::HexapodConfig;
+use super::{ create_reply, vector3_to_json, ErrorCode, ProtocolError };
+use super::protocol::{ check_object, parse_float, parse_object, parse_optional, parse_str, parse_vector3, required_field };
+
+/// Largest length limit accepted in m.
+const MAX_LENGTH: float = 0.5;
+/// Largest speed limit accepted in m/s.
+const MAX_SPEED: float = 1.0;
+
+const LIMIT_FIELDS: [&str; 7] = [
+    "max_speed", "max_step_radius", "max_move_radius", "max_step_len", "max_turn_angle", "max_body_offset",
+    "max_body_rotation"
+];
+
+/// One-shot action of the HTTP API.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RobotCommand {
+    /// Stops walking and lowers the body as far as possible.
+    Sit,
+    /// Stops walking and returns to the neutral body pose.
+    Stand
+}
+
+impl RobotCommand {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "sit" => Some(RobotCommand::Sit),
+            "stand" => Some(RobotCommand::Stand),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RobotCommand::Sit => "sit",
+            RobotCommand::Stand => "stand"
+        }
+    }
+}
+
+/// Partial update of the motion limits. Fields that are `None` are left unchanged. The lift ratio
+/// is deliberately not part of the update, see [`crate::robot::Hexapod::set_limits`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConfigUpdate {
+    pub max_speed: Option<float>,
+    pub max_step_radius: Option<float>,
+    pub max_move_radius: Option<float>,
+    pub max_step_len: Option<float>,
+    pub max_turn_angle: Option<float>,
+    pub max_body_offset: Option<Vector3>,
+    pub max_body_rotation: Option<float>
+}
+
+impl ConfigUpdate {
+    /// Merges the update into `config`. Fails without changing `config` if the resulting limits
+    /// are inconsistent.
+    pub fn apply(&self, config: &mut HexapodConfig) -> Result<(), ProtocolError> {
+        let mut res = config.clone();
+        res.max_speed = self.max_speed.unwrap_or(res.max_speed);
+        res.max_step_radius = self.max_step_radius.unwrap_or(res.max_step_radius);
+        res.max_move_radius = self.max_move_radius.unwrap_or(res.max_move_radius);
+        res.max_step_len = self.max_step_len.unwrap_or(res.max_step_len);
+        res.max_turn_angle = self.max_turn_angle.unwrap_or(res.max_turn_angle);
+        res.max_body_offset = self.max_body_offset.clone().unwrap_or(res.max_body_offset);
+        res.max_body_rotation = self.max_body_rotation.unwrap_or(res.max_body_rotation);
+
+        if res.max_step_radius > res.max_move_radius {
+            return Err(ProtocolError::new(ErrorCode::OutOfRange, Some("max_step_radius"),
+                format!("max_step_radius {} exceeds max_move_radius {}", res.max_step_radius, res.max_move_radius)));
+        }
+
+        *config = res;
+        Ok(())
+    }
+}
+
+/// Request of the HTTP API that is handled by the robot thread.
+#[derive(Debug, Clone)]
+pub enum ApiRequest {
+    GetConfig,
+    SetConfig(ConfigUpdate),
+    Command(RobotCommand)
+}
+
+impl ApiRequest {
+    /// Returns true if the request changes the robot and is therefore reserved to the owner.
+    pub fn modifies(&self) -> bool {
+        !matches!(self, ApiRequest::GetConfig)
+    }
+}
+
+pub fn config_to_json(config: &HexapodConfig) -> JsonValue {
+    let vectors = |v: &[Vector3; 6]| JsonValue::Array(v.iter().map(vector3_to_json).collect());
+    json::object! {
+        "leg_len1": config.leg_len1,
+        "leg_len2": config.leg_len2,
+        "joint_offset": vectors(&config.joint_offset),
+        "legs_origin": vectors(&config.legs_origin),
+        "legs_end_pos": vectors(&config.legs_end_pos),
+        "max_speed": config.max_speed,
+        "max_step_radius": config.max_step_radius,
+        "max_move_radius": config.max_move_radius,
+        "max_step_len": config.max_step_len,
+        "max_turn_angle": config.max_turn_angle,
+        "max_body_offset": vector3_to_json(&config.max_body_offset),
+        "max_body_rotation": config.max_body_rotation,
+        "lift_ratio": config.lift_ratio
+    }
+}
+
+pub fn config_reply(config: &HexapodConfig) -> JsonValue {
+    let mut reply = create_reply("config", None);
+    reply["config"] = config_to_json(config);
+    reply
+}
+
+fn parse_limit(data: &JsonValue, path: &str, max: float) -> Result<float, ProtocolError> {
+    let value = parse_float(data, path, Some(0.0..=max))?;
+    if value == 0.0 {
+        return Err(ProtocolError::new(ErrorCode::OutOfRange, Some(path), "value must be positive".to_string()));
+    }
+    Ok(value)
+}
+
+/// Parses the body of a `PUT /config` request.
+pub fn parse_config_update(text: &str) -> Result<ConfigUpdate, ProtocolError> {
+    let data = parse_object(text)?;
+    check_object(&data, "", &LIMIT_FIELDS)?;
+
+    let max_angle = FloatModule::consts::FRAC_PI_2;
+    Ok(ConfigUpdate {
+        max_speed: parse_optional(&data, "max_speed", |v, p| parse_limit(v, p, MAX_SPEED))?,
+        max_step_radius: parse_optional(&data, "max_step_radius", |v, p| parse_limit(v, p, MAX_LENGTH))?,
+        max_move_radius: parse_optional(&data, "max_move_radius", |v, p| parse_limit(v, p, MAX_LENGTH))?,
+        max_step_len: parse_optional(&data, "max_step_len", |v, p| parse_limit(v, p, MAX_LENGTH))?,
+        max_turn_angle: parse_optional(&data, "max_turn_angle", |v, p| parse_limit(v, p, max_angle))?,
+        max_body_offset: parse_optional(&data, "max_body_offset", |v, p| parse_vector3(v, p, Some(0.0..=MAX_LENGTH)))?,
+        max_body_rotation: parse_optional(&data, "max_body_rotation", |v, p| parse_limit(v, p, max_angle))?
+    })
+}
+
+/// Parses the body of a `POST /command` request.
+pub fn parse_command(text: &str) -> Result<RobotCommand, ProtocolError> {
+    let data = parse_object(text)?;
+    check_object(&data, "", &["action"])?;
+    let (action, action_path) = required_field(&data, "", "action")?;
+    let action = parse_str(action, &action_path)?;
+    RobotCommand::from_name(action)
+        .ok_or_else(|| ProtocolError::new(ErrorCode::OutOfRange, Some(&action_path), format!("unknown action: {}", action)))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::math::Vector3;
+    use crate::robot::HexapodConfig;
+    use crate::control::ErrorCode;
+    use super::{ parse_command, parse_config_update, RobotCommand };
+
+    #[test]
+    fn config_update() {
+        let update = parse_config_update(r#"{ "max_speed": 0.1, "max_body_offset": { "x": 0.01, "y": 0.02, "z": 0.03 } }"#).unwrap();
+        let mut config = HexapodConfig::default();
+        update.apply(&mut config).unwrap();
+        assert_eq!(config.max_speed, 0.1);
+        assert_eq!(config.max_body_offset, Vector3::new(0.01, 0.02, 0.03));
+        assert_eq!(config.max_step_len, HexapodConfig::default().max_step_len);
+
+        // Inconsistent limits are rejected as a whole.
+        let update = parse_config_update(r#"{ "max_speed": 0.2, "max_step_radius": 0.2 }"#).unwrap();
+        assert_eq!(update.apply(&mut config).unwrap_err().code, ErrorCode::OutOfRange);
+        assert_eq!(config.max_speed, 0.1);
+
+        let e = parse_config_update(r#"{ "max_speed": 0 }"#).unwrap_err();
+        assert_eq!((e.code, e.field.as_deref()), (ErrorCode::OutOfRange, Some("max_speed")));
+        let e = parse_config_update(r#"{ "leg_len1": 0.1 }"#).unwrap_err();
+        assert_eq!((e.code, e.field.as_deref()), (ErrorCode::UnknownField, Some("leg_len1")));
+        assert_eq!(parse_config_update("[]").unwrap_err().code, ErrorCode::InvalidType);
+    }
+
+    #[test]
+    fn commands() {
+        assert_eq!(parse_command(r#"{ "action": "sit" }"#), Ok(RobotCommand::Sit));
+        assert_eq!(parse_command(r#"{ "action": "stand" }"#), Ok(RobotCommand::Stand));
+        assert_eq!(parse_command(r#"{ "action": "jump" }"#).unwrap_err().code, ErrorCode::OutOfRange);
+        assert_eq!(parse_command(r#"{}"#).unwrap_err().code, ErrorCode::MissingField);
+    }
+}
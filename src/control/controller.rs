@@ -0,0 +1,363 @@
+use std::time::{ Duration, Instant };
+use json::JsonValue;
+use log::{ info, warn };
+
+use crate::math::{ transform, FloatModule, Vector2, Vector3 };
+use crate::robot::{ Choreography, ChoreographyPlayer, GaitEvent, Hexapod, HexapodConfig, HexapodMode, Imu, ImuReading,
+    LevellingConfig, LevellingController, Odometry };
+use super::{ body_pose_result_to_json, config_reply, ApiRequest, ChoreographyCommand, ClientId, ControlMessage, ControlRequest, ControlState, ErrorCode,
+    ManipulationCommand, ProtocolError, RobotCommand, RobotState, Watchdog };
+
+const CHOREOGRAPHY_DIR: &str = "choreographies";
+
+fn load_choreography(name: &str) -> Result<Choreography, Box<dyn std::error::Error>> {
+    // The name has already been validated by the protocol parser.
+    let path = std::path::Path::new(CHOREOGRAPHY_DIR).join(name).with_extension("json");
+    Ok(Choreography::load(&path)?)
+}
+
+/// Runs the robot on the control loop thread. Everything that decides how the robot moves is
+/// kept here, apart from the network, so that a recorded session replays the same way.
+pub struct Controller {
+    h: Hexapod,
+    imu: Box<dyn Imu + Send>,
+    levelling: LevellingController,
+    control_state: ControlState,
+    watchdog: Watchdog,
+    command_timeout: Option<u32>,
+    choreography: Option<(String, ChoreographyPlayer)>,
+    odometry: Odometry,
+    /// Time in ms the robot walked with a step scaled down to stay within the limits.
+    step_scaled_time: u64
+}
+
+impl Controller {
+    /// Creates the controller. `command_timeout` is the time in ms without control commands
+    /// after which the robot is stopped. The body levelling reads the tilt of the body from `imu`.
+    pub fn new(config: HexapodConfig, command_timeout: Option<u32>, imu: Box<dyn Imu + Send>) -> Self {
+        Controller {
+            h: Hexapod::new(config),
+            imu,
+            levelling: LevellingController::new(LevellingConfig {
+                kp: 0.5,
+                ki: 4.0,
+                max_correction: FloatModule::consts::FRAC_PI_8
+            }),
+            control_state: ControlState::default(),
+            watchdog: Watchdog::new(command_timeout),
+            command_timeout,
+            choreography: None,
+            odometry: Odometry::default(),
+            step_scaled_time: 0
+        }
+    }
+
+    pub fn hexapod(&self) -> &Hexapod {
+        &self.h
+    }
+
+    pub fn odometry(&self) -> &Odometry {
+        &self.odometry
+    }
+
+    /// Returns the time in ms the robot walked with a step scaled down to stay within the limits.
+    pub fn step_scaled_time(&self) -> u64 {
+        self.step_scaled_time
+    }
+
+    /// Returns the gait events raised since the last call, including the ones caused by
+    /// requests.
+    pub fn take_events(&mut self) -> Vec<GaitEvent> {
+        self.h.take_events()
+    }
+
+    /// Reads the IMU and removes the requested body rotation from the reading, so that the
+    /// levelling only corrects the slope of the ground. The result is the tilt the body would
+    /// have with the current levelling correction and no requested rotation.
+    fn read_ground_tilt(&mut self) -> ImuReading {
+        let reading = self.imu.read();
+        let measured = transform::rotate_matrix3_rpy(reading.roll, reading.pitch, 0.0);
+        let (roll, pitch) = self.levelling.correction();
+        let levelled = measured * self.h.body_rotation().transpose() * transform::rotate_matrix3_rpy(roll, pitch, 0.0);
+        let (roll, pitch, _) = transform::rpy_from_matrix3(&levelled);
+        ImuReading { roll, pitch, yaw_rate: reading.yaw_rate }
+    }
+
+    fn robot_state(&self) -> RobotState {
+        RobotState::new(&self.h, self.choreography.as_ref().map(|(name, _)| name.as_str()), self.levelling.enabled())
+    }
+
+    /// Brings the robot to a halt with a neutral body pose.
+    fn stop(&mut self) {
+        let h = &mut self.h;
+        self.control_state.reset_motion();
+        self.choreography = None;
+        h.exit_manipulation();
+        for i in 0..6 {
+            h.set_leg_offset(i, &Vector3::zero());
+        }
+
+        let cs = &self.control_state;
+        let origin = h.body_rotation_pivot(&cs.body_rotation_pivot);
+        h.set_step(&cs.step, cs.turn_angle, cs.step_height_weight);
+        h.set_body_offset(&cs.body_offset);
+        h.set_body_rotation(cs.body_rotation_angle, &cs.body_rotation_axis, &origin);
+    }
+
+    /// Advances the robot by `time` ms. Returns the time spent in `Hexapod::update`, i.e. the
+    /// gait and the inverse kinematics, without the choreography, levelling and bookkeeping.
+    pub fn tick(&mut self, time: u32) -> Duration {
+        if let Some((_, player)) = &mut self.choreography {
+            let origin = self.h.body_rotation_pivot(&self.control_state.body_rotation_pivot);
+            player.advance(&mut self.h, &origin, time);
+            if player.has_finished() {
+                info!("Choreography finished");
+                self.choreography = None;
+            }
+        }
+
+        let update_start = Instant::now();
+        self.h.update(time);
+        let update_duration = update_start.elapsed();
+        self.odometry.update(&self.h);
+        if self.h.step_scale().is_some_and(|scale| scale < 1.0) {
+            self.step_scaled_time += time as u64;
+        }
+
+        self.imu.set_body_rotation(self.h.body_rotation());
+        if self.levelling.enabled() {
+            let reading = self.read_ground_tilt();
+            let (roll, pitch) = self.levelling.update(&reading, time);
+            self.h.set_body_levelling(roll, pitch);
+        }
+
+        if self.watchdog.update(time) {
+            warn!("No control command received for {} ms, stopping", self.command_timeout.unwrap_or_default());
+            self.stop();
+        }
+        update_duration
+    }
+
+    /// Returns true if the robot walks, plays a choreography or holds a leg up for manipulation.
+    fn moving(&self) -> bool {
+        self.h.mode() != HexapodMode::Standing || self.choreography.is_some()
+            || self.control_state.step != Vector2::zero() || self.control_state.turn_angle != 0.0
+    }
+
+    /// Stops the robot after the client owning it disconnected. A robot standing still keeps its
+    /// pose, e.g. after a `sit` command of the HTTP API.
+    pub fn disconnected(&mut self, peer: &str) {
+        self.watchdog.disarm();
+        if self.moving() {
+            warn!("Control client {} disconnected, stopping", peer);
+            self.stop();
+        }
+    }
+
+    /// Stops the robot after the lease of the client owning it ended, so that it does not keep
+    /// walking without an owner or with the step of the previous one.
+    pub fn lease_ended(&mut self, client: ClientId) {
+        self.watchdog.disarm();
+        if self.moving() {
+            warn!("Lease of control client {} ended, stopping", client);
+            self.stop();
+        }
+    }
+
+    /// Handles a message of a control client and returns the reply, if any.
+    pub fn handle_control(&mut self, request: &ControlRequest) -> Option<JsonValue> {
+        let cp = match &request.message {
+            ControlMessage::Control(cp) => cp,
+            ControlMessage::Query => return Some(self.robot_state().to_reply("state", request.id.as_ref())),
+            // Ownership is handled by the control server.
+            ControlMessage::Acquire { .. } | ControlMessage::Release => return None
+        };
+        let mut rejection = None;
+        let mut body_pose_result = None;
+        self.watchdog.feed();
+
+        let h = &mut self.h;
+        let changes = self.control_state.apply(cp);
+        let body_rotation_origin = h.body_rotation_pivot(&self.control_state.body_rotation_pivot);
+
+        match &cp.choreography {
+            Some(ChoreographyCommand::Play(name)) => {
+                match load_choreography(name) {
+                    Ok(c) => {
+                        info!("Playing choreography: {}", name);
+                        self.choreography = Some((name.clone(), ChoreographyPlayer::new(c)));
+                    },
+                    Err(e) => {
+                        warn!("Cannot play choreography {}: {}", name, e);
+                        rejection = Some(ProtocolError::new(ErrorCode::Rejected, Some("choreography.name"),
+                            format!("cannot play choreography {}: {}", name, e)));
+                    }
+                }
+            },
+            Some(ChoreographyCommand::Stop) => {
+                self.choreography = None;
+                for i in 0..6 {
+                    h.set_leg_offset(i, &Vector3::zero());
+                }
+            },
+            None => {}
+        }
+
+        match &cp.manipulation {
+            Some(ManipulationCommand::Enter(leg_id)) if !h.enter_manipulation(*leg_id) => {
+                warn!("Cannot start manipulation with leg {}", leg_id);
+                rejection = Some(ProtocolError::new(ErrorCode::Rejected, Some("manipulation.leg"),
+                    format!("cannot start manipulation with leg {} while walking or manipulating, or without a stable support polygon", leg_id)));
+            },
+            Some(ManipulationCommand::Move(target)) if h.set_manipulation_target(target).is_none() => {
+                rejection = Some(ProtocolError::new(ErrorCode::Rejected, Some("manipulation"),
+                    "no manipulation is active".to_string()));
+            },
+            Some(ManipulationCommand::Exit) => h.exit_manipulation(),
+            _ => {}
+        }
+
+        let cs = &self.control_state;
+        if changes.step {
+            h.set_step(&cs.step, cs.turn_angle, cs.step_height_weight);
+        }
+
+        // The body pose is driven by the choreography while it is playing.
+        if self.choreography.is_none() {
+            if changes.body_offset {
+                h.set_body_offset(&cs.body_offset);
+            }
+            if changes.body_rotation {
+                h.set_body_rotation(cs.body_rotation_angle, &cs.body_rotation_axis, &body_rotation_origin);
+            }
+            if let Some(body_pose) = &cp.body_pose {
+                body_pose_result = Some(h.set_body_pose(body_pose, &body_rotation_origin));
+            }
+        }
+        if let Some(body_sway) = cp.body_sway {
+            h.set_body_sway(body_sway);
+        }
+        if let Some((kp, ki)) = cp.levelling_gains {
+            self.levelling.set_gains(kp, ki);
+        }
+        if let Some(enabled) = cp.levelling {
+            self.levelling.set_enabled(enabled);
+            if !enabled {
+                h.set_body_levelling(0.0, 0.0);
+            }
+        }
+
+        match rejection {
+            Some(e) => Some(e.with_id(request.id.clone()).to_json()),
+            None => request.id.as_ref().map(|id| {
+                let mut reply = self.robot_state().to_reply("ack", Some(id));
+                if let Some(result) = &body_pose_result {
+                    reply["body_pose"] = body_pose_result_to_json(result);
+                }
+                reply
+            })
+        }
+    }
+
+    /// Executes a request of the HTTP API.
+    pub fn handle_api(&mut self, request: &ApiRequest) -> Result<JsonValue, ProtocolError> {
+        match request {
+            ApiRequest::GetConfig => Ok(config_reply(self.h.config())),
+            ApiRequest::SetConfig(update) => {
+                let mut config = self.h.config().clone();
+                update.apply(&mut config)?;
+                if self.choreography.is_some() || self.h.mode() != HexapodMode::Standing || !self.h.set_limits(&config) {
+                    return Err(ProtocolError::new(ErrorCode::Rejected, None,
+                        "the config can only be changed while standing still".to_string()));
+                }
+                info!("Config updated");
+                Ok(config_reply(self.h.config()))
+            },
+            ApiRequest::Command(command) => {
+                info!("Executing command: {}", command.as_str());
+                // One-shot commands leave the robot in a resting pose that the watchdog must not undo.
+                self.watchdog.disarm();
+                self.stop();
+                if *command == RobotCommand::Sit {
+                    self.control_state.body_offset = Vector3::new(0.0, 0.0, -1.0);
+                    self.h.set_body_offset(&self.control_state.body_offset);
+                }
+                Ok(self.robot_state().to_reply("ack", None))
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::{ math::{ transform, FloatType as float, FloatEq }, assert_float_eq, float_eq };
+    use crate::robot::{ HexapodConfig, HexapodMode, SimulatedImu };
+    use super::super::{ parse_message, ApiRequest, RobotCommand };
+    use super::Controller;
+
+    const TOL: float = 1e-3;
+
+    #[test]
+    fn levelling_keeps_requested_rotation() {
+        let (ground_roll, ground_pitch) = (0.1, -0.05);
+        let imu = SimulatedImu::new(ground_roll, ground_pitch);
+        let mut controller = Controller::new(HexapodConfig::default(), None, Box::new(imu));
+        controller.handle_control(&parse_message(r#"{ "levelling": true,
+            "body_pose": { "roll": 0.0, "pitch": 0.15, "yaw": 0.1, "offset": { "x": 0.0, "y": 0.0, "z": 0.0 } } }"#).unwrap());
+        for _ in 0..500 {
+            controller.tick(10);
+        }
+
+        // Only the slope is corrected, the body keeps the requested pitch relative to the horizon.
+        let rotation = transform::rotate_matrix3_rpy(ground_roll, ground_pitch, 0.0) * controller.hexapod().body_rotation();
+        let (roll, pitch, _) = transform::rpy_from_matrix3(&rotation);
+        assert_float_eq!(roll, 0.0, TOL, abs);
+        assert_float_eq!(pitch, 0.15, TOL, abs);
+    }
+
+    #[test]
+    fn body_pose_ack() {
+        let imu = SimulatedImu::new(0.0, 0.0);
+        let mut controller = Controller::new(HexapodConfig::default(), None, Box::new(imu));
+        let reply = controller.handle_control(&parse_message(r#"{ "id": 3,
+            "body_pose": { "roll": 0.0, "pitch": 0.1, "yaw": 0.0, "offset": { "x": 0.5, "y": 0.0, "z": 0.0 } } }"#).unwrap()).unwrap();
+        assert_eq!(reply["type"], "ack");
+        assert_eq!(reply["body_pose"]["offset_clamped"], true);
+        assert_eq!(reply["body_pose"]["rotation_clamped"], false);
+        assert_eq!(reply["body_pose"]["applied"]["offset"]["x"].as_f32(), Some(0.03));
+
+        let reply = controller.handle_control(&parse_message(r#"{ "id": 4, "step": { "x": 0.0, "y": 0.5 } }"#).unwrap()).unwrap();
+        assert!(reply["body_pose"].is_null());
+    }
+
+    #[test]
+    fn disconnect_stops_walking() {
+        let imu = SimulatedImu::new(0.0, 0.0);
+        let mut controller = Controller::new(HexapodConfig::default(), None, Box::new(imu));
+        controller.handle_control(&parse_message(r#"{ "step": { "x": 0.0, "y": 0.5 } }"#).unwrap());
+        for _ in 0..50 {
+            controller.tick(10);
+        }
+        assert_eq!(controller.hexapod().mode(), HexapodMode::Walking);
+
+        controller.disconnected("127.0.0.1:50123");
+        for _ in 0..200 {
+            controller.tick(10);
+        }
+        assert_eq!(controller.hexapod().mode(), HexapodMode::Standing);
+    }
+
+    #[test]
+    fn disconnect_keeps_resting_pose() {
+        let imu = SimulatedImu::new(0.0, 0.0);
+        let mut controller = Controller::new(HexapodConfig::default(), Some(100), Box::new(imu));
+        controller.handle_api(&ApiRequest::Command(RobotCommand::Sit)).unwrap();
+        let body_offset = controller.hexapod().body_pose_target().offset;
+        assert!(body_offset[2] < 0.0);
+
+        controller.disconnected("127.0.0.1:50123");
+        controller.tick(10);
+        assert_eq!(controller.hexapod().body_pose_target().offset, body_offset);
+    }
+}
@@ -0,0 +1,17 @@
+mod api;
+mod controller;
+mod ownership;
+mod protocol;
+mod recording;
+mod report;
+mod state;
+mod watchdog;
+
+pub use api::*;
+pub use controller::*;
+pub use ownership::*;
+pub use protocol::*;
+pub use recording::*;
+pub use report::*;
+pub use state::*;
+pub use watchdog::*;
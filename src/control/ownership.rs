@@ -0,0 +1,233 @@
+use std::collections::VecDeque;
+
+/// Identifier the server assigns to every control connection.
+pub type ClientId = u64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RevokeReason {
+    /// A privileged client took over.
+    Preempted,
+    /// The owner did not send any command within the lease timeout.
+    Expired
+}
+
+impl RevokeReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RevokeReason::Preempted => "preempted",
+            RevokeReason::Expired => "expired"
+        }
+    }
+}
+
+/// Change of ownership other clients have to be notified about.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LeaseEvent {
+    /// The client became the owner after waiting for a takeover.
+    Granted(ClientId),
+    Revoked(ClientId, RevokeReason),
+    /// `from` asked the owner to hand over control.
+    TakeoverRequested { owner: ClientId, from: ClientId }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AcquireResult {
+    Granted,
+    /// The request is queued until the owner releases control or its lease expires.
+    Pending
+}
+
+#[derive(Debug, Clone)]
+struct Lease {
+    client: ClientId,
+    privileged: bool,
+    /// Time the lease expires at in ms.
+    expires: u64
+}
+
+/// Decides which control client drives the robot.
+///
+/// Only the owner of the lease may send commands, other clients are observers. The lease is
+/// renewed by every command of the owner and expires after `lease_timeout` ms without one.
+/// Clients can request a takeover, which is granted once the owner releases control or its
+/// lease expires. Privileged clients take over immediately.
+///
+/// All times are given in ms.
+#[derive(Debug, Clone)]
+pub struct Arbiter {
+    lease_timeout: u64,
+    lease: Option<Lease>,
+    pending: VecDeque<ClientId>
+}
+
+impl Arbiter {
+    pub fn new(lease_timeout: u64) -> Self {
+        Arbiter { lease_timeout, lease: None, pending: VecDeque::new() }
+    }
+
+    pub fn owner(&self) -> Option<ClientId> {
+        self.lease.as_ref().map(|lease| lease.client)
+    }
+
+    fn grant(&mut self, client: ClientId, privileged: bool, now: u64) {
+        self.pending.retain(|&c| c != client);
+        self.lease = Some(Lease { client, privileged, expires: now + self.lease_timeout });
+    }
+
+    /// Hands the lease to the next pending client, if any.
+    fn grant_next(&mut self, now: u64, events: &mut Vec<LeaseEvent>) {
+        self.lease = None;
+        if let Some(next) = self.pending.pop_front() {
+            self.grant(next, false, now);
+            events.push(LeaseEvent::Granted(next));
+        }
+    }
+
+    /// Requests control for `client`. The returned events concern other clients.
+    pub fn acquire(&mut self, client: ClientId, privileged: bool, now: u64) -> (AcquireResult, Vec<LeaseEvent>) {
+        let mut events = self.expire(now);
+
+        match &self.lease {
+            None => self.grant(client, privileged, now),
+            Some(lease) if lease.client == client => {
+                let privileged = privileged || lease.privileged;
+                self.grant(client, privileged, now);
+            },
+            Some(lease) => {
+                if privileged {
+                    events.push(LeaseEvent::Revoked(lease.client, RevokeReason::Preempted));
+                    self.grant(client, privileged, now);
+                }
+                else {
+                    if !self.pending.contains(&client) {
+                        self.pending.push_back(client);
+                    }
+                    events.push(LeaseEvent::TakeoverRequested { owner: lease.client, from: client });
+                    return (AcquireResult::Pending, events);
+                }
+            }
+        }
+
+        (AcquireResult::Granted, events)
+    }
+
+    /// Renews the lease before a command of `client` is applied. A client without a lease
+    /// acquires it implicitly if it is free and nobody is waiting for it. Returns `None` if the
+    /// client is not allowed to send commands.
+    pub fn renew(&mut self, client: ClientId, now: u64) -> Option<Vec<LeaseEvent>> {
+        let events = self.expire(now);
+
+        match &mut self.lease {
+            Some(lease) if lease.client == client => {
+                lease.expires = now + self.lease_timeout;
+                Some(events)
+            },
+            None if self.pending.is_empty() => {
+                self.grant(client, false, now);
+                Some(events)
+            },
+            _ => None
+        }
+    }
+
+    /// Gives up control or a pending takeover request.
+    pub fn release(&mut self, client: ClientId, now: u64) -> Vec<LeaseEvent> {
+        let mut events = self.expire(now);
+        self.pending.retain(|&c| c != client);
+        if self.owner() == Some(client) {
+            self.grant_next(now, &mut events);
+        }
+        events
+    }
+
+    /// Revokes the lease if it expired.
+    pub fn expire(&mut self, now: u64) -> Vec<LeaseEvent> {
+        let mut events = Vec::new();
+        if let Some(lease) = &self.lease {
+            if now >= lease.expires {
+                events.push(LeaseEvent::Revoked(lease.client, RevokeReason::Expired));
+                self.grant_next(now, &mut events);
+            }
+        }
+        events
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{ AcquireResult, Arbiter, LeaseEvent, RevokeReason };
+
+    #[test]
+    fn implicit_lease() {
+        let mut arbiter = Arbiter::new(100);
+        assert_eq!(arbiter.renew(1, 0), Some(vec![]));
+        assert_eq!(arbiter.owner(), Some(1));
+
+        // Observers cannot send commands while the lease is held.
+        assert_eq!(arbiter.renew(2, 50), None);
+        assert_eq!(arbiter.renew(1, 90), Some(vec![]));
+        assert_eq!(arbiter.renew(2, 150), None);
+
+        // After the lease expired, anyone can take it.
+        assert_eq!(arbiter.renew(2, 200), Some(vec![LeaseEvent::Revoked(1, RevokeReason::Expired)]));
+        assert_eq!(arbiter.owner(), Some(2));
+    }
+
+    #[test]
+    fn takeover() {
+        let mut arbiter = Arbiter::new(100);
+        assert_eq!(arbiter.acquire(1, false, 0).0, AcquireResult::Granted);
+
+        let (res, events) = arbiter.acquire(2, false, 10);
+        assert_eq!(res, AcquireResult::Pending);
+        assert_eq!(events, vec![LeaseEvent::TakeoverRequested { owner: 1, from: 2 }]);
+        assert_eq!(arbiter.owner(), Some(1));
+
+        // A pending takeover blocks implicit leases of other clients.
+        assert_eq!(arbiter.renew(3, 20), None);
+
+        assert_eq!(arbiter.release(1, 30), vec![LeaseEvent::Granted(2)]);
+        assert_eq!(arbiter.owner(), Some(2));
+        assert_eq!(arbiter.renew(1, 40), None);
+    }
+
+    #[test]
+    fn takeover_after_expiry() {
+        let mut arbiter = Arbiter::new(100);
+        arbiter.acquire(1, false, 0);
+        arbiter.acquire(2, false, 10);
+
+        assert_eq!(arbiter.expire(99), vec![]);
+        assert_eq!(arbiter.expire(100), vec![LeaseEvent::Revoked(1, RevokeReason::Expired), LeaseEvent::Granted(2)]);
+        assert_eq!(arbiter.owner(), Some(2));
+    }
+
+    #[test]
+    fn privileged_preemption() {
+        let mut arbiter = Arbiter::new(100);
+        arbiter.acquire(1, false, 0);
+
+        let (res, events) = arbiter.acquire(9, true, 10);
+        assert_eq!(res, AcquireResult::Granted);
+        assert_eq!(events, vec![LeaseEvent::Revoked(1, RevokeReason::Preempted)]);
+
+        // Regular clients have to wait for the privileged client.
+        assert_eq!(arbiter.acquire(1, false, 20).0, AcquireResult::Pending);
+
+        // Another privileged client still takes over.
+        let (res, events) = arbiter.acquire(8, true, 30);
+        assert_eq!(res, AcquireResult::Granted);
+        assert_eq!(events, vec![LeaseEvent::Revoked(9, RevokeReason::Preempted)]);
+    }
+
+    #[test]
+    fn release_pending() {
+        let mut arbiter = Arbiter::new(100);
+        arbiter.acquire(1, false, 0);
+        arbiter.acquire(2, false, 10);
+        assert_eq!(arbiter.release(2, 20), vec![]);
+        assert_eq!(arbiter.release(1, 30), vec![]);
+        assert_eq!(arbiter.owner(), None);
+    }
+}
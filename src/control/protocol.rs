@@ -0,0 +1,807 @@
+//! Control message schema.
+//!
+//! Every message is a JSON object with a `version` and a `type` field. Messages without a
+//! `version` are treated as the current version and messages without a `type` are treated as
+//! `control` messages, so the packets of older clients are still accepted.
+//!
+//! Any message can carry an `id`, either a non-negative integer or a string. Control messages
+//! with an `id` are answered with an `ack` message once they have been applied, other control
+//! messages are not answered unless they are invalid. Replies always carry the `id` of the
+//! request they answer.
+//!
+//! A `query` message requests the current state of the robot and is answered with a `state`
+//! message. Both `ack` and `state` messages contain the state after the limits of the robot
+//! were applied:
+//!
+//! ```json
+//! { "version": 1, "type": "state", "id": 7, "state": { "mode": "walking", "gait": { ... }, ... } }
+//! ```
+//!
+//! The `ack` of a control message with a `body_pose` additionally reports the pose that was
+//! applied and whether its offset or rotation was clamped to the limits:
+//!
+//! ```json
+//! { "version": 1, "type": "ack", "id": 8, "state": { ... },
+//!   "body_pose": { "applied": { "roll", "pitch", "yaw", "offset" }, "offset_clamped": true, "rotation_clamped": false } }
+//! ```
+//!
+//! Only one client at a time drives the robot. A client becomes the owner by sending an
+//! `acquire` message, or implicitly with its first `control` message while nobody owns the
+//! robot. The lease is renewed by every command and expires after the lease timeout. While
+//! another client owns the robot, `acquire` queues a takeover request and the owner receives a
+//! `takeover_requested` message; control is handed over once the owner sends `release` or its
+//! lease expires. An `acquire` message with the privileged `token` from the config takes over
+//! immediately. Control messages of other clients are answered with a `not_owner` error.
+//! Whenever the lease of the owner ends or the owner disconnects, a moving robot is stopped, so
+//! that a new owner starts with a robot standing still.
+//!
+//! ```json
+//! { "version": 1, "type": "acquire", "id": 1, "token": "..." }
+//! { "version": 1, "type": "lease", "id": 1, "state": "granted" }
+//! { "version": 1, "type": "lease", "state": "revoked", "reason": "preempted" }
+//! { "version": 1, "type": "takeover_requested", "client": 4 }
+//! ```
+//!
+//! A `control` message is a partial update: every field is optional and only the fields that are
+//! present are changed on the robot.
+//!
+//! | Field                 | Format                                                     |
+//! |-----------------------|------------------------------------------------------------|
+//! | `step`                | `{ "x", "y" }`, each in [-1, 1]                            |
+//! | `step_height_weight`  | number in [0, 2]                                           |
+//! | `turn_angle`          | number in [-1, 1]                                          |
+//! | `body_offset`         | `{ "x", "y", "z" }`, each in [-1, 1]                       |
+//! | `body_rotation_angle` | number in [-1, 1]                                          |
+//! | `body_rotation_axis`  | `{ "x", "y", "z" }`                                        |
+//! | `body_rotation_pivot` | `"body_center"`, `"ground"`, `"front_legs"` or `{ "x", "y", "z" }` |
+//! | `body_pose`           | `{ "roll", "pitch", "yaw", "offset": { "x", "y", "z" } }`, angles in [-π, π] rad, offset in [-1, 1] m |
+//! | `body_sway`           | boolean                                                    |
+//! | `levelling`           | boolean                                                    |
+//! | `levelling_gains`     | `{ "kp", "ki" }`, each non-negative                        |
+//! | `choreography`        | `{ "action": "play", "name" }` or `{ "action": "stop" }`   |
+//! | `manipulation`        | `{ "action": "enter", "leg" }`, `{ "action": "move", "target": { "x", "y", "z" } }` or `{ "action": "exit" }` |
+//!
+//! Monitor clients may send a `subscribe` message to select the telemetry they receive. `rate`
+//! is given in Hz and `topics` is a subset of `legs`, `joint_angles`, `body_pose`, `gait`,
+//! `motion`, `phases` and `timing`. Clients that never subscribe receive the `legs` topic every control
+//! loop iteration. Every telemetry message carries the `time` in ms and the `tick` of the
+//! control loop iteration it was captured in.
+//! `encoding` is either `json` (default) or `binary`, which sends binary frames with a fixed
+//! layout of little-endian floats instead of JSON text (see `telemetry::binary`). `frame`
+//! selects the coordinate frame of positions and orientations: `robot` (X right, Y forward,
+//! Z up), `ros` (REP-103, X forward, Y left, Z up) or `viewer` (X left, Y up, Z forward), the
+//! default of the original viewer.
+//!
+//! ```json
+//! { "version": 1, "type": "subscribe", "rate": 20, "topics": ["legs", "body_pose"], "encoding": "binary", "frame": "ros" }
+//! ```
+//!
+//! With `"events": true`, the client additionally receives an `event` message for every change
+//! of the gait state, as JSON text regardless of `encoding` and `rate`. Events are buffered
+//! separately from the telemetry, so a client that falls behind skips telemetry messages but
+//! not events. `event` is one of `walk_started` (with the `step` in m and the `turn_angle` in
+//! rad after scaling), `stop_started`, `stop_finished`, `step_scaled` (with the `scale`),
+//! `leg_config_applied` (with the `leg` that switched to a changed gait) and `limit_clamped`
+//! (with the name of the config `limit` and the `leg`, if the limit applies to a single leg):
+//!
+//! ```json
+//! { "version": 1, "type": "event", "tick": 512, "time": 5120, "event": "step_scaled", "scale": 0.8 }
+//! ```
+//!
+//! Invalid messages are rejected as a whole and answered with an `error` message:
+//!
+//! ```json
+//! { "version": 1, "type": "error", "id": 7, "error": { "code": "out_of_range", "field": "step.x", "message": "..." } }
+//! ```
+//!
+//! Valid commands that cannot be executed in the current state of the robot, e.g. playing a
+//! choreography that does not exist, are answered with a `rejected` error instead of an `ack`.
+//!
+//! The optional UDP control endpoint accepts the same messages, one per datagram, with an
+//! additional `seq` field. `seq` is a non-negative integer the client increments with every
+//! datagram; datagrams that arrive with a `seq` not greater than the last one accepted from the
+//! same client are dropped without a reply. A client is disconnected after 5 s without accepted
+//! datagrams, so a client that restarts its sequence is accepted again after that time. Replies
+//! and notifications are sent back as datagrams to the address of the client.
+//!
+//! ```json
+//! { "seq": 1042, "step": { "x": 0.0, "y": 0.8 } }
+//! ```
+
+use std::ops::RangeInclusive;
+use json::JsonValue;
+
+use crate::math::{ FloatType as float, FloatModule, Vector2, Vector3 };
+use crate::robot::{ BodyPose, BodyRotationPivot };
+use crate::telemetry::{ Encoding, Frame, Subscription, Topic };
+
+pub const PROTOCOL_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorCode {
+    InvalidJson,
+    UnsupportedVersion,
+    UnknownType,
+    UnknownField,
+    MissingField,
+    InvalidType,
+    OutOfRange,
+    Rejected,
+    NotOwner
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::InvalidJson => "invalid_json",
+            ErrorCode::UnsupportedVersion => "unsupported_version",
+            ErrorCode::UnknownType => "unknown_type",
+            ErrorCode::UnknownField => "unknown_field",
+            ErrorCode::MissingField => "missing_field",
+            ErrorCode::InvalidType => "invalid_type",
+            ErrorCode::OutOfRange => "out_of_range",
+            ErrorCode::Rejected => "rejected",
+            ErrorCode::NotOwner => "not_owner"
+        }
+    }
+}
+
+/// Identifier chosen by the client to match replies with requests.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RequestId {
+    Number(u64),
+    Text(String)
+}
+
+impl RequestId {
+    pub fn to_json(&self) -> JsonValue {
+        match self {
+            RequestId::Number(n) => (*n).into(),
+            RequestId::Text(s) => s.as_str().into()
+        }
+    }
+}
+
+/// Creates a reply message of the given type, answering the request with the given id.
+pub fn create_reply(msg_type: &str, id: Option<&RequestId>) -> JsonValue {
+    let mut reply = json::object! {
+        "version": PROTOCOL_VERSION,
+        "type": msg_type
+    };
+    if let Some(id) = id {
+        reply["id"] = id.to_json();
+    }
+    reply
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProtocolError {
+    pub code: ErrorCode,
+    pub field: Option<String>,
+    pub message: String,
+    /// Id of the request that caused the error, if it is known.
+    pub id: Option<RequestId>
+}
+
+impl ProtocolError {
+    pub fn new(code: ErrorCode, field: Option<&str>, message: String) -> Self {
+        ProtocolError { code, field: field.map(|f| f.to_string()), message, id: None }
+    }
+
+    pub fn with_id(mut self, id: Option<RequestId>) -> Self {
+        self.id = id;
+        self
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let mut error = json::object! {
+            "code": self.code.as_str(),
+            "message": self.message.as_str()
+        };
+        if let Some(field) = &self.field {
+            error["field"] = field.as_str().into();
+        }
+
+        let mut reply = create_reply("error", self.id.as_ref());
+        reply["error"] = error;
+        reply
+    }
+}
+
+impl std::fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.field {
+            Some(field) => write!(f, "{} ({}): {}", self.code.as_str(), field, self.message),
+            None => write!(f, "{}: {}", self.code.as_str(), self.message)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChoreographyCommand {
+    Play(String),
+    Stop
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ManipulationCommand {
+    Enter(usize),
+    Move(Vector3),
+    Exit
+}
+
+/// Partial update of the control inputs. Fields that are `None` are left unchanged.
+#[derive(Debug, Clone, Default)]
+pub struct ControlPacket {
+    pub step: Option<Vector2>,
+    pub step_height_weight: Option<float>,
+    pub turn_angle: Option<float>,
+    pub body_offset: Option<Vector3>,
+    pub body_rotation_angle: Option<float>,
+    pub body_rotation_axis: Option<Vector3>,
+    pub body_rotation_pivot: Option<BodyRotationPivot>,
+    pub body_pose: Option<BodyPose>,
+    pub body_sway: Option<bool>,
+    pub levelling: Option<bool>,
+    pub levelling_gains: Option<(float, float)>,
+    pub choreography: Option<ChoreographyCommand>,
+    pub manipulation: Option<ManipulationCommand>
+}
+
+#[derive(Debug, Clone)]
+pub enum ControlMessage {
+    Control(ControlPacket),
+    Query,
+    /// Request to drive the robot, with the token of privileged clients.
+    Acquire { token: Option<String> },
+    Release
+}
+
+#[derive(Debug, Clone)]
+pub struct ControlRequest {
+    pub id: Option<RequestId>,
+    pub message: ControlMessage
+}
+
+/// Message received on the monitor channel.
+#[derive(Debug, Clone)]
+pub enum MonitorMessage {
+    Subscribe(Subscription)
+}
+
+#[derive(Debug, Clone)]
+pub struct MonitorRequest {
+    pub id: Option<RequestId>,
+    pub message: MonitorMessage
+}
+
+const MIN_MONITOR_RATE: float = 0.1;
+const MAX_MONITOR_RATE: float = 1000.0;
+
+const SUBSCRIBE_FIELDS: [&str; 8] = [ "version", "type", "id", "rate", "topics", "encoding", "frame", "events" ];
+
+const QUERY_FIELDS: [&str; 3] = [ "version", "type", "id" ];
+
+const ACQUIRE_FIELDS: [&str; 4] = [ "version", "type", "id", "token" ];
+
+const CONTROL_FIELDS: [&str; 16] = [
+    "version", "type", "id", "step", "step_height_weight", "turn_angle", "body_offset", "body_rotation_angle",
+    "body_rotation_axis", "body_rotation_pivot", "body_pose", "body_sway", "levelling", "levelling_gains",
+    "choreography", "manipulation"
+];
+
+pub(super) fn join_path(path: &str, name: &str) -> String {
+    if path.is_empty() { name.to_string() } else { format!("{}.{}", path, name) }
+}
+
+pub(super) fn check_object(data: &JsonValue, path: &str, allowed: &[&str]) -> Result<(), ProtocolError> {
+    if !data.is_object() {
+        return Err(ProtocolError::new(ErrorCode::InvalidType, Some(path), "expected an object".to_string()));
+    }
+    for (name, _) in data.entries() {
+        if !allowed.contains(&name) {
+            let field = join_path(path, name);
+            return Err(ProtocolError::new(ErrorCode::UnknownField, Some(&field), "unknown field".to_string()));
+        }
+    }
+    Ok(())
+}
+
+pub(super) fn field<'a>(data: &'a JsonValue, name: &str) -> Option<&'a JsonValue> {
+    if data.has_key(name) { Some(&data[name]) } else { None }
+}
+
+pub(super) fn parse_float(data: &JsonValue, path: &str, range: Option<RangeInclusive<float>>) -> Result<float, ProtocolError> {
+    let value = data.as_number()
+        .map(|n| f64::from(n) as float)
+        .filter(|v| v.is_finite())
+        .ok_or_else(|| ProtocolError::new(ErrorCode::InvalidType, Some(path), "expected a number".to_string()))?;
+
+    if let Some(range) = range {
+        if !range.contains(&value) {
+            return Err(ProtocolError::new(ErrorCode::OutOfRange, Some(path),
+                format!("value {} is outside of the [{}, {}] range", value, range.start(), range.end())));
+        }
+    }
+    Ok(value)
+}
+
+fn parse_bool(data: &JsonValue, path: &str) -> Result<bool, ProtocolError> {
+    data.as_bool()
+        .ok_or_else(|| ProtocolError::new(ErrorCode::InvalidType, Some(path), "expected a boolean".to_string()))
+}
+
+pub(super) fn parse_str<'a>(data: &'a JsonValue, path: &str) -> Result<&'a str, ProtocolError> {
+    data.as_str()
+        .ok_or_else(|| ProtocolError::new(ErrorCode::InvalidType, Some(path), "expected a string".to_string()))
+}
+
+pub(super) fn required_field<'a>(data: &'a JsonValue, path: &str, name: &str) -> Result<(&'a JsonValue, String), ProtocolError> {
+    let field_path = join_path(path, name);
+    match field(data, name) {
+        Some(value) => Ok((value, field_path)),
+        None => Err(ProtocolError::new(ErrorCode::MissingField, Some(&field_path), "missing field".to_string()))
+    }
+}
+
+pub(super) fn parse_float_field(data: &JsonValue, path: &str, name: &str, range: Option<RangeInclusive<float>>) -> Result<float, ProtocolError> {
+    let (value, field_path) = required_field(data, path, name)?;
+    parse_float(value, &field_path, range)
+}
+
+fn parse_vector2(data: &JsonValue, path: &str, range: Option<RangeInclusive<float>>) -> Result<Vector2, ProtocolError> {
+    check_object(data, path, &["x", "y"])?;
+    Ok(Vector2::new(
+        parse_float_field(data, path, "x", range.clone())?,
+        parse_float_field(data, path, "y", range)?
+    ))
+}
+
+pub(super) fn parse_vector3(data: &JsonValue, path: &str, range: Option<RangeInclusive<float>>) -> Result<Vector3, ProtocolError> {
+    check_object(data, path, &["x", "y", "z"])?;
+    Ok(Vector3::new(
+        parse_float_field(data, path, "x", range.clone())?,
+        parse_float_field(data, path, "y", range.clone())?,
+        parse_float_field(data, path, "z", range)?
+    ))
+}
+
+fn parse_body_pose(data: &JsonValue, path: &str) -> Result<BodyPose, ProtocolError> {
+    check_object(data, path, &["roll", "pitch", "yaw", "offset"])?;
+    let angle_range = -FloatModule::consts::PI..=FloatModule::consts::PI;
+    let offset = match field(data, "offset") {
+        Some(offset) => parse_vector3(offset, &join_path(path, "offset"), Some(-1.0..=1.0))?,
+        None => Vector3::zero()
+    };
+
+    Ok(BodyPose {
+        roll: field(data, "roll").map_or(Ok(0.0), |v| parse_float(v, &join_path(path, "roll"), Some(angle_range.clone())))?,
+        pitch: field(data, "pitch").map_or(Ok(0.0), |v| parse_float(v, &join_path(path, "pitch"), Some(angle_range.clone())))?,
+        yaw: field(data, "yaw").map_or(Ok(0.0), |v| parse_float(v, &join_path(path, "yaw"), Some(angle_range.clone())))?,
+        offset
+    })
+}
+
+fn parse_body_rotation_pivot(data: &JsonValue, path: &str) -> Result<BodyRotationPivot, ProtocolError> {
+    if let Some(name) = data.as_str() {
+        BodyRotationPivot::from_name(name)
+            .ok_or_else(|| ProtocolError::new(ErrorCode::OutOfRange, Some(path), format!("unknown pivot: {}", name)))
+    }
+    else {
+        Ok(BodyRotationPivot::Custom(parse_vector3(data, path, None)?))
+    }
+}
+
+fn parse_levelling_gains(data: &JsonValue, path: &str) -> Result<(float, float), ProtocolError> {
+    check_object(data, path, &["kp", "ki"])?;
+    Ok((
+        parse_float_field(data, path, "kp", Some(0.0..=float::MAX))?,
+        parse_float_field(data, path, "ki", Some(0.0..=float::MAX))?
+    ))
+}
+
+fn parse_choreography(data: &JsonValue, path: &str) -> Result<ChoreographyCommand, ProtocolError> {
+    let (action, action_path) = required_field(data, path, "action")?;
+    match parse_str(action, &action_path)? {
+        "play" => {
+            check_object(data, path, &["action", "name"])?;
+            let (name, name_path) = required_field(data, path, "name")?;
+            let name = parse_str(name, &name_path)?;
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+                return Err(ProtocolError::new(ErrorCode::OutOfRange, Some(&name_path),
+                    "name must only contain letters, digits, '_' and '-'".to_string()));
+            }
+            Ok(ChoreographyCommand::Play(name.to_string()))
+        },
+        "stop" => {
+            check_object(data, path, &["action"])?;
+            Ok(ChoreographyCommand::Stop)
+        },
+        action => Err(ProtocolError::new(ErrorCode::OutOfRange, Some(&action_path), format!("unknown action: {}", action)))
+    }
+}
+
+fn parse_manipulation(data: &JsonValue, path: &str) -> Result<ManipulationCommand, ProtocolError> {
+    let (action, action_path) = required_field(data, path, "action")?;
+    match parse_str(action, &action_path)? {
+        "enter" => {
+            check_object(data, path, &["action", "leg"])?;
+            let (leg, leg_path) = required_field(data, path, "leg")?;
+            let leg = leg.as_usize()
+                .ok_or_else(|| ProtocolError::new(ErrorCode::InvalidType, Some(&leg_path), "expected a leg index".to_string()))?;
+            if leg >= 6 {
+                return Err(ProtocolError::new(ErrorCode::OutOfRange, Some(&leg_path), format!("leg {} does not exist", leg)));
+            }
+            Ok(ManipulationCommand::Enter(leg))
+        },
+        "move" => {
+            check_object(data, path, &["action", "target"])?;
+            let (target, target_path) = required_field(data, path, "target")?;
+            Ok(ManipulationCommand::Move(parse_vector3(target, &target_path, Some(-1.0..=1.0))?))
+        },
+        "exit" => {
+            check_object(data, path, &["action"])?;
+            Ok(ManipulationCommand::Exit)
+        },
+        action => Err(ProtocolError::new(ErrorCode::OutOfRange, Some(&action_path), format!("unknown action: {}", action)))
+    }
+}
+
+pub(super) fn parse_optional<T>(data: &JsonValue, name: &str, f: impl Fn(&JsonValue, &str) -> Result<T, ProtocolError>) -> Result<Option<T>, ProtocolError> {
+    field(data, name).map(|value| f(value, name)).transpose()
+}
+
+fn parse_control_packet(data: &JsonValue) -> Result<ControlPacket, ProtocolError> {
+    check_object(data, "", &CONTROL_FIELDS)?;
+
+    Ok(ControlPacket {
+        step: parse_optional(data, "step", |v, p| parse_vector2(v, p, Some(-1.0..=1.0)))?,
+        step_height_weight: parse_optional(data, "step_height_weight", |v, p| parse_float(v, p, Some(0.0..=2.0)))?,
+        turn_angle: parse_optional(data, "turn_angle", |v, p| parse_float(v, p, Some(-1.0..=1.0)))?,
+        body_offset: parse_optional(data, "body_offset", |v, p| parse_vector3(v, p, Some(-1.0..=1.0)))?,
+        body_rotation_angle: parse_optional(data, "body_rotation_angle", |v, p| parse_float(v, p, Some(-1.0..=1.0)))?,
+        body_rotation_axis: parse_optional(data, "body_rotation_axis", |v, p| parse_vector3(v, p, None))?,
+        body_rotation_pivot: parse_optional(data, "body_rotation_pivot", parse_body_rotation_pivot)?,
+        body_pose: parse_optional(data, "body_pose", parse_body_pose)?,
+        body_sway: parse_optional(data, "body_sway", parse_bool)?,
+        levelling: parse_optional(data, "levelling", parse_bool)?,
+        levelling_gains: parse_optional(data, "levelling_gains", parse_levelling_gains)?,
+        choreography: parse_optional(data, "choreography", parse_choreography)?,
+        manipulation: parse_optional(data, "manipulation", parse_manipulation)?
+    })
+}
+
+fn parse_request_id(data: &JsonValue, path: &str) -> Result<RequestId, ProtocolError> {
+    if let Some(n) = data.as_u64() {
+        Ok(RequestId::Number(n))
+    }
+    else if let Some(s) = data.as_str() {
+        Ok(RequestId::Text(s.to_string()))
+    }
+    else {
+        Err(ProtocolError::new(ErrorCode::InvalidType, Some(path), "expected a non-negative integer or a string".to_string()))
+    }
+}
+
+/// Parses the common part of all messages and passes the message type to `parse_body`. Errors
+/// carry the id of the request if it could be parsed.
+fn parse_request<T>(text: &str, default_type: Option<&str>,
+        parse_body: impl Fn(&JsonValue, &str) -> Result<T, ProtocolError>) -> Result<(Option<RequestId>, T), ProtocolError> {
+    parse_request_data(&parse_object(text)?, default_type, parse_body)
+}
+
+pub(super) fn parse_object(text: &str) -> Result<JsonValue, ProtocolError> {
+    let data = json::parse(text)
+        .map_err(|e| ProtocolError::new(ErrorCode::InvalidJson, None, e.to_string()))?;
+    if !data.is_object() {
+        return Err(ProtocolError::new(ErrorCode::InvalidType, None, "expected an object".to_string()));
+    }
+    Ok(data)
+}
+
+fn parse_request_data<T>(data: &JsonValue, default_type: Option<&str>,
+        parse_body: impl Fn(&JsonValue, &str) -> Result<T, ProtocolError>) -> Result<(Option<RequestId>, T), ProtocolError> {
+    let id = parse_optional(data, "id", parse_request_id)?;
+    let parse = || {
+        if let Some(version) = field(data, "version") {
+            if version.as_u32() != Some(PROTOCOL_VERSION) {
+                return Err(ProtocolError::new(ErrorCode::UnsupportedVersion, Some("version"),
+                    format!("only version {} is supported", PROTOCOL_VERSION)));
+            }
+        }
+
+        let msg_type = match (field(data, "type"), default_type) {
+            (Some(msg_type), _) => parse_str(msg_type, "type")?,
+            (None, Some(default_type)) => default_type,
+            (None, None) => return Err(ProtocolError::new(ErrorCode::MissingField, Some("type"), "missing field".to_string()))
+        };
+
+        parse_body(data, msg_type)
+    };
+
+    match parse() {
+        Ok(message) => Ok((id, message)),
+        Err(e) => Err(e.with_id(id))
+    }
+}
+
+fn unknown_type(msg_type: &str) -> ProtocolError {
+    ProtocolError::new(ErrorCode::UnknownType, Some("type"), format!("unknown message type: {}", msg_type))
+}
+
+/// Parses and validates a message received on the control channel.
+pub fn parse_message(text: &str) -> Result<ControlRequest, ProtocolError> {
+    parse_request(text, Some("control"), parse_message_body)
+        .map(|(id, message)| ControlRequest { id, message })
+}
+
+/// Parses and validates a datagram received on the UDP control endpoint. Returns the sequence
+/// number of the datagram along with the request.
+pub fn parse_datagram(text: &str) -> Result<(u64, ControlRequest), ProtocolError> {
+    let mut data = parse_object(text)?;
+    let seq = data.remove("seq");
+    let (id, (seq, message)) = parse_request_data(&data, Some("control"), |data, msg_type| {
+        let seq = match &seq {
+            JsonValue::Null => return Err(ProtocolError::new(ErrorCode::MissingField, Some("seq"), "missing field".to_string())),
+            seq => seq.as_u64()
+                .ok_or_else(|| ProtocolError::new(ErrorCode::InvalidType, Some("seq"), "expected a non-negative integer".to_string()))?
+        };
+        Ok((seq, parse_message_body(data, msg_type)?))
+    })?;
+    Ok((seq, ControlRequest { id, message }))
+}
+
+/// Parses and validates a message received on the monitor channel.
+pub fn parse_monitor_message(text: &str) -> Result<MonitorRequest, ProtocolError> {
+    parse_request(text, None, parse_monitor_message_body)
+        .map(|(id, message)| MonitorRequest { id, message })
+}
+
+fn parse_topic(data: &JsonValue, path: &str) -> Result<Topic, ProtocolError> {
+    let name = parse_str(data, path)?;
+    Topic::from_name(name)
+        .ok_or_else(|| ProtocolError::new(ErrorCode::OutOfRange, Some(path), format!("unknown topic: {}", name)))
+}
+
+fn parse_topics(data: &JsonValue, path: &str) -> Result<Vec<Topic>, ProtocolError> {
+    if !data.is_array() {
+        return Err(ProtocolError::new(ErrorCode::InvalidType, Some(path), "expected an array".to_string()));
+    }
+    data.members()
+        .enumerate()
+        .map(|(i, topic)| parse_topic(topic, &join_path(path, &i.to_string())))
+        .collect()
+}
+
+fn parse_encoding(data: &JsonValue, path: &str) -> Result<Encoding, ProtocolError> {
+    let name = parse_str(data, path)?;
+    Encoding::from_name(name)
+        .ok_or_else(|| ProtocolError::new(ErrorCode::OutOfRange, Some(path), format!("unknown encoding: {}", name)))
+}
+
+fn parse_frame(data: &JsonValue, path: &str) -> Result<Frame, ProtocolError> {
+    let name = parse_str(data, path)?;
+    Frame::from_name(name)
+        .ok_or_else(|| ProtocolError::new(ErrorCode::OutOfRange, Some(path), format!("unknown frame: {}", name)))
+}
+
+fn parse_monitor_message_body(data: &JsonValue, msg_type: &str) -> Result<MonitorMessage, ProtocolError> {
+    match msg_type {
+        "subscribe" => {
+            check_object(data, "", &SUBSCRIBE_FIELDS)?;
+            let rate = parse_optional(data, "rate", |v, p| parse_float(v, p, Some(MIN_MONITOR_RATE..=MAX_MONITOR_RATE)))?;
+            let topics = parse_optional(data, "topics", parse_topics)?
+                .unwrap_or_else(|| Subscription::default().topics().to_vec());
+            let encoding = parse_optional(data, "encoding", parse_encoding)?.unwrap_or(Encoding::Json);
+            let mut subscription = Subscription::new(rate, topics, encoding);
+            if let Some(frame) = parse_optional(data, "frame", parse_frame)? {
+                subscription = subscription.with_frame(frame);
+            }
+            if let Some(events) = parse_optional(data, "events", parse_bool)? {
+                subscription = subscription.with_events(events);
+            }
+            Ok(MonitorMessage::Subscribe(subscription))
+        },
+        _ => Err(unknown_type(msg_type))
+    }
+}
+
+fn parse_message_body(data: &JsonValue, msg_type: &str) -> Result<ControlMessage, ProtocolError> {
+    match msg_type {
+        "control" => Ok(ControlMessage::Control(parse_control_packet(data)?)),
+        "query" => {
+            check_object(data, "", &QUERY_FIELDS)?;
+            Ok(ControlMessage::Query)
+        },
+        "acquire" => {
+            check_object(data, "", &ACQUIRE_FIELDS)?;
+            let token = parse_optional(data, "token", |v, p| parse_str(v, p).map(|token| token.to_string()))?;
+            Ok(ControlMessage::Acquire { token })
+        },
+        "release" => {
+            check_object(data, "", &QUERY_FIELDS)?;
+            Ok(ControlMessage::Release)
+        },
+        _ => Err(unknown_type(msg_type))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::math::{ Vector2, Vector3 };
+    use crate::robot::BodyRotationPivot;
+    use crate::telemetry::{ Encoding, Frame, Topic };
+    use super::{ parse_datagram, parse_message, parse_monitor_message, MonitorMessage, ControlMessage, ControlPacket, ErrorCode, ManipulationCommand, ProtocolError, RequestId };
+
+    fn parse_control(text: &str) -> Result<ControlPacket, ProtocolError> {
+        parse_message(text).map(|request| match request.message {
+            ControlMessage::Control(packet) => packet,
+            msg => panic!("unexpected message: {:?}", msg)
+        })
+    }
+
+    fn error_code(text: &str) -> (ErrorCode, Option<String>) {
+        let e = parse_control(text).unwrap_err();
+        (e.code, e.field)
+    }
+
+    #[test]
+    fn legacy_packet() {
+        let packet = parse_control(r#"{
+            "step": { "x": 0.5, "y": -0.5 },
+            "step_height_weight": 1.0,
+            "turn_angle": 0.0,
+            "body_offset": { "x": 0.0, "y": 0.0, "z": 0.1 },
+            "body_rotation_angle": 0.0,
+            "body_rotation_axis": { "x": 0.0, "y": 0.0, "z": 1.0 }
+        }"#).unwrap();
+
+        assert_eq!(packet.step, Some(Vector2::new(0.5, -0.5)));
+        assert_eq!(packet.step_height_weight, Some(1.0));
+        assert_eq!(packet.body_offset, Some(Vector3::new(0.0, 0.0, 0.1)));
+        assert_eq!(packet.body_sway, None);
+    }
+
+    #[test]
+    fn partial_update() {
+        let packet = parse_control(r#"{ "version": 1, "type": "control", "body_offset": { "x": 0.2, "y": 0.0, "z": 0.0 } }"#).unwrap();
+
+        assert_eq!(packet.body_offset, Some(Vector3::new(0.2, 0.0, 0.0)));
+        assert!(packet.step.is_none());
+        assert!(packet.turn_angle.is_none());
+        assert!(packet.body_rotation_angle.is_none());
+    }
+
+    #[test]
+    fn optional_fields() {
+        let packet = parse_control(r#"{
+            "body_rotation_pivot": "front_legs",
+            "body_pose": { "pitch": 0.1 },
+            "manipulation": { "action": "enter", "leg": 3 }
+        }"#).unwrap();
+
+        assert_eq!(packet.body_rotation_pivot, Some(BodyRotationPivot::FrontLegs));
+        assert_eq!(packet.body_pose.unwrap().pitch, 0.1);
+        assert_eq!(packet.manipulation, Some(ManipulationCommand::Enter(3)));
+    }
+
+    #[test]
+    fn invalid_messages() {
+        assert_eq!(error_code(r#"{ "step": "#).0, ErrorCode::InvalidJson);
+        assert_eq!(error_code(r#"[1, 2]"#).0, ErrorCode::InvalidType);
+        assert_eq!(error_code(r#"{ "version": 2 }"#), (ErrorCode::UnsupportedVersion, Some("version".to_string())));
+        assert_eq!(error_code(r#"{ "type": "dance" }"#), (ErrorCode::UnknownType, Some("type".to_string())));
+        assert_eq!(error_code(r#"{ "stepp": { "x": 0, "y": 0 } }"#), (ErrorCode::UnknownField, Some("stepp".to_string())));
+        assert_eq!(error_code(r#"{ "step": { "x": 0 } }"#), (ErrorCode::MissingField, Some("step.y".to_string())));
+        assert_eq!(error_code(r#"{ "step": { "x": "0", "y": 0 } }"#), (ErrorCode::InvalidType, Some("step.x".to_string())));
+        assert_eq!(error_code(r#"{ "step": { "x": 1.5, "y": 0 } }"#), (ErrorCode::OutOfRange, Some("step.x".to_string())));
+        assert_eq!(error_code(r#"{ "step_height_weight": -1 }"#), (ErrorCode::OutOfRange, Some("step_height_weight".to_string())));
+        assert_eq!(error_code(r#"{ "body_sway": 1 }"#), (ErrorCode::InvalidType, Some("body_sway".to_string())));
+        assert_eq!(error_code(r#"{ "body_rotation_pivot": "tail" }"#), (ErrorCode::OutOfRange, Some("body_rotation_pivot".to_string())));
+        assert_eq!(error_code(r#"{ "manipulation": { "action": "enter", "leg": 6 } }"#), (ErrorCode::OutOfRange, Some("manipulation.leg".to_string())));
+        assert_eq!(error_code(r#"{ "choreography": { "action": "play", "name": "../secret" } }"#), (ErrorCode::OutOfRange, Some("choreography.name".to_string())));
+    }
+
+    #[test]
+    fn error_reply() {
+        let e = parse_control(r#"{ "turn_angle": 2 }"#).unwrap_err();
+        let reply = e.to_json();
+
+        assert_eq!(reply["type"], "error");
+        assert_eq!(reply["version"], 1);
+        assert_eq!(reply["error"]["code"], "out_of_range");
+        assert_eq!(reply["error"]["field"], "turn_angle");
+    }
+
+    #[test]
+    fn request_ids() {
+        let request = parse_message(r#"{ "id": 7, "turn_angle": 0.5 }"#).unwrap();
+        assert_eq!(request.id, Some(RequestId::Number(7)));
+
+        let request = parse_message(r#"{ "id": "a1", "type": "query" }"#).unwrap();
+        assert_eq!(request.id, Some(RequestId::Text("a1".to_string())));
+        assert!(matches!(request.message, ControlMessage::Query));
+
+        let request = parse_message(r#"{ "type": "query" }"#).unwrap();
+        assert_eq!(request.id, None);
+
+        let e = parse_message(r#"{ "id": 3, "turn_angle": 2 }"#).unwrap_err();
+        assert_eq!(e.id, Some(RequestId::Number(3)));
+        assert_eq!(e.to_json()["id"], 3);
+
+        assert_eq!(error_code(r#"{ "id": -1 }"#), (ErrorCode::InvalidType, Some("id".to_string())));
+        assert_eq!(error_code(r#"{ "type": "query", "step": { "x": 0, "y": 0 } }"#), (ErrorCode::UnknownField, Some("step".to_string())));
+    }
+
+    #[test]
+    fn datagrams() {
+        let (seq, request) = parse_datagram(r#"{ "seq": 12, "id": 3, "turn_angle": 0.5 }"#).unwrap();
+        assert_eq!(seq, 12);
+        assert_eq!(request.id, Some(RequestId::Number(3)));
+        assert!(matches!(request.message, ControlMessage::Control(ControlPacket { turn_angle: Some(_), .. })));
+
+        let (_, request) = parse_datagram(r#"{ "seq": 0, "type": "acquire" }"#).unwrap();
+        assert!(matches!(request.message, ControlMessage::Acquire { token: None }));
+
+        let e = parse_datagram(r#"{ "id": 4, "turn_angle": 0.5 }"#).unwrap_err();
+        assert_eq!((e.code, e.field.as_deref(), e.id), (ErrorCode::MissingField, Some("seq"), Some(RequestId::Number(4))));
+        let e = parse_datagram(r#"{ "seq": -1 }"#).unwrap_err();
+        assert_eq!((e.code, e.field.as_deref()), (ErrorCode::InvalidType, Some("seq")));
+        let e = parse_datagram(r#"{ "seq": 1, "turn_angle": 2 }"#).unwrap_err();
+        assert_eq!(e.code, ErrorCode::OutOfRange);
+
+        // The sequence number is only accepted on the UDP endpoint.
+        assert_eq!(error_code(r#"{ "seq": 1 }"#), (ErrorCode::UnknownField, Some("seq".to_string())));
+    }
+
+    #[test]
+    fn subscribe() {
+        let request = parse_monitor_message(r#"{ "type": "subscribe", "rate": 20, "topics": ["legs", "gait"] }"#).unwrap();
+        let MonitorMessage::Subscribe(subscription) = request.message;
+        assert_eq!(subscription.rate(), Some(20.0));
+        assert_eq!(subscription.topics(), &[Topic::Legs, Topic::Gait]);
+        assert_eq!(subscription.encoding(), Encoding::Json);
+
+        let request = parse_monitor_message(r#"{ "type": "subscribe", "encoding": "binary" }"#).unwrap();
+        let MonitorMessage::Subscribe(subscription) = request.message;
+        assert_eq!(subscription.encoding(), Encoding::Binary);
+        assert_eq!(subscription.topics(), &[Topic::Legs]);
+        assert_eq!(subscription.frame(), Frame::Viewer);
+        assert!(!subscription.events());
+
+        let request = parse_monitor_message(r#"{ "type": "subscribe", "frame": "ros", "events": true }"#).unwrap();
+        let MonitorMessage::Subscribe(subscription) = request.message;
+        assert_eq!(subscription.frame(), Frame::Ros);
+        assert!(subscription.events());
+
+        let request = parse_monitor_message(r#"{ "type": "subscribe", "topics": [] }"#).unwrap();
+        let MonitorMessage::Subscribe(subscription) = request.message;
+        assert_eq!(subscription.rate(), None);
+        assert!(subscription.topics().is_empty());
+
+        let code = |text: &str| { let e = parse_monitor_message(text).unwrap_err(); (e.code, e.field) };
+        assert_eq!(code(r#"{ "rate": 20 }"#), (ErrorCode::MissingField, Some("type".to_string())));
+        assert_eq!(code(r#"{ "type": "control" }"#), (ErrorCode::UnknownType, Some("type".to_string())));
+        assert_eq!(code(r#"{ "type": "subscribe", "rate": 0 }"#), (ErrorCode::OutOfRange, Some("rate".to_string())));
+        assert_eq!(code(r#"{ "type": "subscribe", "encoding": "cbor" }"#), (ErrorCode::OutOfRange, Some("encoding".to_string())));
+        assert_eq!(code(r#"{ "type": "subscribe", "frame": "ned" }"#), (ErrorCode::OutOfRange, Some("frame".to_string())));
+        assert_eq!(code(r#"{ "type": "subscribe", "events": 1 }"#), (ErrorCode::InvalidType, Some("events".to_string())));
+        assert_eq!(code(r#"{ "type": "subscribe", "topics": "legs" }"#), (ErrorCode::InvalidType, Some("topics".to_string())));
+        assert_eq!(code(r#"{ "type": "subscribe", "topics": ["legs", "tail"] }"#), (ErrorCode::OutOfRange, Some("topics.1".to_string())));
+    }
+
+    #[test]
+    fn ownership_messages() {
+        let request = parse_message(r#"{ "type": "acquire", "token": "secret" }"#).unwrap();
+        assert!(matches!(request.message, ControlMessage::Acquire { token: Some(token) } if token == "secret"));
+
+        let request = parse_message(r#"{ "type": "acquire" }"#).unwrap();
+        assert!(matches!(request.message, ControlMessage::Acquire { token: None }));
+
+        let request = parse_message(r#"{ "type": "release" }"#).unwrap();
+        assert!(matches!(request.message, ControlMessage::Release));
+
+        assert_eq!(error_code(r#"{ "type": "acquire", "token": 5 }"#), (ErrorCode::InvalidType, Some("token".to_string())));
+    }
+}
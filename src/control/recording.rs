@@ -0,0 +1,351 @@
+//! Recording of control sessions and their deterministic replay.
+//!
+//! A recording is a JSON lines file. It starts with a header, followed by a line for every
+//! iteration of the control loop and every request that changed the robot, in the order the
+//! control loop processed them:
+//!
+//! ```json
+//! { "type": "recording", "version": 1, "command_timeout": 1000 }
+//! { "type": "tick", "tick": 0, "time": 0, "dt": 10, "legs": "4f1c2a9e0d7b3c55" }
+//! { "type": "control", "tick": 0, "time": 4, "text": "{ \"step\": { \"x\": 0, \"y\": 1 } }" }
+//! { "type": "config", "tick": 0, "time": 4, "text": "{ \"max_speed\": 0.1 }" }
+//! { "type": "command", "tick": 0, "time": 4, "text": "{ \"action\": \"sit\" }" }
+//! { "type": "disconnected", "tick": 0, "time": 4, "peer": "127.0.0.1:50123" }
+//! { "type": "lease_ended", "tick": 0, "time": 4, "client": 2 }
+//! ```
+//!
+//! `time` is the wall clock time in ms since the recording started, `dt` the time the robot was
+//! advanced by and `legs` a digest of the leg positions after the tick. Requests are stored as
+//! the text received from the client and parsed again on replay. Choreographies are loaded from
+//! disk again as well, so they must not have changed since the recording.
+
+use std::fs::File;
+use std::io::{ BufRead, BufReader, BufWriter, Write };
+use std::fmt::Display;
+use std::path::Path;
+use std::time::Instant;
+use json::JsonValue;
+use log::warn;
+
+use crate::robot::{ Hexapod, HexapodConfig, SimulatedImu };
+use super::{ parse_command, parse_config_update, parse_datagram, parse_message, ApiRequest, ClientId, Controller };
+
+pub const RECORDING_VERSION: u32 = 1;
+
+/// Returns a digest of the leg positions, which changes with any bit of the positions.
+pub fn legs_digest(h: &Hexapod) -> u64 {
+    // FNV-1a
+    let mut digest: u64 = 0xcbf29ce484222325;
+    for i in 0..6 {
+        let position = h.leg(i).position();
+        for v in [position[0], position[1], position[2]] {
+            for byte in v.to_bits().to_le_bytes() {
+                digest ^= byte as u64;
+                digest = digest.wrapping_mul(0x100000001b3);
+            }
+        }
+    }
+    digest
+}
+
+/// Writes the session of the control loop to a recording. Write errors are logged and end the
+/// recording, without affecting the robot.
+pub struct Recorder<W: Write> {
+    out: Option<W>,
+    start: Instant,
+    tick: u64
+}
+
+impl Recorder<BufWriter<File>> {
+    pub fn create(path: &Path, command_timeout: Option<u32>) -> std::io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?), command_timeout)
+    }
+}
+
+impl<W: Write> Recorder<W> {
+    /// Starts a recording by writing the header. `command_timeout` is the setting of the
+    /// watchdog, which the replay has to use as well.
+    pub fn new(mut out: W, command_timeout: Option<u32>) -> std::io::Result<Self> {
+        let header = json::object! {
+            type: "recording",
+            version: RECORDING_VERSION,
+            command_timeout: command_timeout
+        };
+        writeln!(out, "{}", json::stringify(header))?;
+        Ok(Recorder { out: Some(out), start: Instant::now(), tick: 0 })
+    }
+
+    fn event(&self, event_type: &str) -> JsonValue {
+        json::object! {
+            type: event_type,
+            tick: self.tick,
+            time: self.start.elapsed().as_millis() as u64
+        }
+    }
+
+    fn write(&mut self, line: JsonValue, flush: bool) {
+        if let Some(out) = &mut self.out {
+            let res = writeln!(out, "{}", json::stringify(line)).and_then(|_| if flush { out.flush() } else { Ok(()) });
+            if let Err(e) = res {
+                warn!("Cannot write recording, recording stopped: {}", e);
+                self.out = None;
+            }
+        }
+    }
+
+    /// Records an iteration of the control loop that advanced the robot by `dt` ms.
+    pub fn record_tick(&mut self, tick: u64, dt: u32, h: &Hexapod) {
+        self.tick = tick;
+        let mut line = self.event("tick");
+        line["dt"] = dt.into();
+        line["legs"] = format!("{:016x}", legs_digest(h)).into();
+        self.write(line, true);
+    }
+
+    /// Records a control message or datagram, given as the text received from the client.
+    pub fn record_control(&mut self, text: &str) {
+        let mut line = self.event("control");
+        line["text"] = text.into();
+        self.write(line, false);
+    }
+
+    /// Records a request of the HTTP API with the request body. Requests that do not change the
+    /// robot are skipped.
+    pub fn record_api(&mut self, request: &ApiRequest, text: &str) {
+        let event_type = match request {
+            ApiRequest::GetConfig => return,
+            ApiRequest::SetConfig(_) => "config",
+            ApiRequest::Command(_) => "command"
+        };
+        let mut line = self.event(event_type);
+        line["text"] = text.into();
+        self.write(line, false);
+    }
+
+    /// Records that the client owning the robot disconnected.
+    pub fn record_disconnected(&mut self, peer: &str) {
+        let mut line = self.event("disconnected");
+        line["peer"] = peer.into();
+        self.write(line, false);
+    }
+
+    /// Records that the lease of the client owning the robot ended.
+    pub fn record_lease_ended(&mut self, client: ClientId) {
+        let mut line = self.event("lease_ended");
+        line["client"] = client.into();
+        self.write(line, false);
+    }
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(std::io::Error),
+    Invalid { line: usize, message: String }
+}
+
+impl Display for ReplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplayError::Io(e) => write!(f, "cannot read recording: {}", e),
+            ReplayError::Invalid { line, message } => write!(f, "invalid recording, line {}: {}", line, message)
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<std::io::Error> for ReplayError {
+    fn from(e: std::io::Error) -> Self {
+        ReplayError::Io(e)
+    }
+}
+
+/// Outcome of a replay.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplaySummary {
+    pub ticks: u64,
+    /// Time the robot was advanced by in ms.
+    pub time: u64,
+    pub requests: u64,
+    /// Number of ticks whose leg positions differ from the recording.
+    pub mismatches: u64,
+    /// First tick whose leg positions differ from the recording.
+    pub first_mismatch: Option<u64>
+}
+
+fn string_field<'a>(data: &'a JsonValue, name: &str) -> Result<&'a str, String> {
+    data[name].as_str().ok_or_else(|| format!("{} must be a string", name))
+}
+
+fn replay_line(controller: &mut Controller, data: &JsonValue, summary: &mut ReplaySummary,
+        on_tick: &mut impl FnMut(&Hexapod, u64, u64)) -> Result<(), String> {
+    match data["type"].as_str() {
+        Some("tick") => {
+            let tick = data["tick"].as_u64().ok_or("tick must be a non-negative integer")?;
+            let dt = data["dt"].as_u32().ok_or("dt must be a non-negative integer")?;
+            let digest = u64::from_str_radix(string_field(data, "legs")?, 16).map_err(|_| "legs must be a hex digest")?;
+
+            controller.tick(dt);
+            summary.ticks += 1;
+            on_tick(controller.hexapod(), tick, summary.time);
+            summary.time += dt as u64;
+            if legs_digest(controller.hexapod()) != digest {
+                summary.mismatches += 1;
+                summary.first_mismatch.get_or_insert(tick);
+            }
+        },
+        Some("control") => {
+            let text = string_field(data, "text")?;
+            // Datagrams carry a sequence number, which plain control messages must not contain.
+            let request = parse_message(text)
+                .or_else(|_| parse_datagram(text).map(|(_, request)| request))
+                .map_err(|e| format!("cannot parse control message: {}", e))?;
+            controller.handle_control(&request);
+            summary.requests += 1;
+        },
+        Some(event_type @ ("config" | "command")) => {
+            let text = string_field(data, "text")?;
+            let request = match event_type {
+                "config" => parse_config_update(text).map(ApiRequest::SetConfig),
+                _ => parse_command(text).map(ApiRequest::Command)
+            }.map_err(|e| format!("cannot parse {} request: {}", event_type, e))?;
+            // Rejections are part of the recorded session.
+            let _ = controller.handle_api(&request);
+            summary.requests += 1;
+        },
+        Some("disconnected") => {
+            controller.disconnected(string_field(data, "peer")?);
+            summary.requests += 1;
+        },
+        Some("lease_ended") => {
+            controller.lease_ended(data["client"].as_u64().ok_or("client must be a non-negative integer")?);
+            summary.requests += 1;
+        },
+        Some(event_type) => return Err(format!("unknown line type: {}", event_type)),
+        None => return Err("missing line type".to_string())
+    }
+    Ok(())
+}
+
+/// Replays a recording on a robot with the given config, as fast as possible and without the
+/// network. `on_tick` is called after every tick with the robot, the tick and the time of the
+/// tick in ms, counted like in the telemetry.
+pub fn replay(input: impl BufRead, config: HexapodConfig,
+        mut on_tick: impl FnMut(&Hexapod, u64, u64)) -> Result<ReplaySummary, ReplayError> {
+    let mut lines = input.lines().enumerate();
+    let invalid = |line: usize, message: String| ReplayError::Invalid { line: line + 1, message };
+
+    let header = match lines.next() {
+        Some((i, line)) => json::parse(&line?).map_err(|e| invalid(i, e.to_string()))?,
+        None => return Err(invalid(0, "empty recording".to_string()))
+    };
+    if header["type"].as_str() != Some("recording") {
+        return Err(invalid(0, "missing header".to_string()));
+    }
+    if header["version"].as_u32() != Some(RECORDING_VERSION) {
+        return Err(invalid(0, format!("unsupported version: {}", header["version"])));
+    }
+    let command_timeout = match &header["command_timeout"] {
+        JsonValue::Null => None,
+        ms => Some(ms.as_u32().ok_or_else(|| invalid(0, "command_timeout must be a non-negative integer".to_string()))?)
+    };
+
+    // Recordings are replayed on level ground.
+    let mut controller = Controller::new(config, command_timeout, Box::new(SimulatedImu::new(0.0, 0.0)));
+    let mut summary = ReplaySummary { ticks: 0, time: 0, requests: 0, mismatches: 0, first_mismatch: None };
+    for (i, line) in lines {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let data = json::parse(&line).map_err(|e| invalid(i, e.to_string()))?;
+        replay_line(&mut controller, &data, &mut summary, &mut on_tick).map_err(|message| invalid(i, message))?;
+    }
+
+    Ok(summary)
+}
+
+/// Replays a recording file, see [`replay`].
+pub fn replay_file(path: &Path, config: HexapodConfig,
+        on_tick: impl FnMut(&Hexapod, u64, u64)) -> Result<ReplaySummary, ReplayError> {
+    replay(BufReader::new(File::open(path)?), config, on_tick)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::control::{ parse_command, parse_message, ApiRequest, Controller };
+    use crate::robot::{ HexapodConfig, SimulatedImu };
+    use super::{ replay, Recorder, ReplayError };
+
+    const DT: u32 = 10;
+
+    /// Runs a short walk with the control loop of the server and returns the recording.
+    fn record_session(messages: &[(u64, &str)]) -> Vec<u8> {
+        let mut controller = Controller::new(HexapodConfig::default(), Some(200), Box::new(SimulatedImu::new(0.0, 0.0)));
+        let mut recorder = Recorder::new(Vec::new(), Some(200)).unwrap();
+        for tick in 0..150 {
+            controller.tick(DT);
+            recorder.record_tick(tick, DT, controller.hexapod());
+            for (_, text) in messages.iter().filter(|(t, _)| *t == tick) {
+                if text.contains("action") {
+                    let request = ApiRequest::Command(parse_command(text).unwrap());
+                    recorder.record_api(&request, text);
+                    controller.handle_api(&request).unwrap();
+                } else {
+                    recorder.record_control(text);
+                    controller.handle_control(&parse_message(text).unwrap());
+                }
+            }
+        }
+        recorder.out.take().unwrap()
+    }
+
+    const SESSION: [(u64, &str); 4] = [
+        (5, r#"{ "step": { "x": 0.0, "y": 1.0 }, "turn_angle": 0.1 }"#),
+        (40, r#"{ "step": { "x": 0.5, "y": 0.5 }, "body_offset": { "x": 0.0, "y": 0.0, "z": 0.3 } }"#),
+        (90, r#"{ "action": "stand" }"#),
+        (100, r#"{ "body_rotation_angle": 0.2 }"#)
+    ];
+
+    #[test]
+    fn replay_is_identical() {
+        let recording = record_session(&SESSION);
+        let mut ticks = Vec::new();
+        let summary = replay(recording.as_slice(), HexapodConfig::default(), |_, tick, time| ticks.push((tick, time))).unwrap();
+        assert_eq!(summary.ticks, 150);
+        assert_eq!(summary.time, 1500);
+        assert_eq!(ticks[149], (149, 1490));
+        assert_eq!(summary.requests, 4);
+        assert_eq!(summary.first_mismatch, None);
+
+        // The watchdog stops the robot between the messages, so its setting is part of the
+        // recording.
+        let text = String::from_utf8(recording).unwrap();
+        assert!(text.lines().next().unwrap().contains("\"command_timeout\":200"));
+    }
+
+    #[test]
+    fn replay_detects_divergence() {
+        let recording = String::from_utf8(record_session(&SESSION)).unwrap();
+        let tampered = recording.replacen(r#"\"turn_angle\": 0.1"#, r#"\"turn_angle\": 0.2"#, 1);
+        assert_ne!(recording, tampered);
+
+        let summary = replay(tampered.as_bytes(), HexapodConfig::default(), |_, _, _| {}).unwrap();
+        assert!(summary.mismatches > 0);
+        assert!(summary.first_mismatch.unwrap() >= 6);
+    }
+
+    #[test]
+    fn invalid_recordings() {
+        let e = replay("".as_bytes(), HexapodConfig::default(), |_, _, _| {}).unwrap_err();
+        assert!(matches!(e, ReplayError::Invalid { line: 1, .. }));
+
+        let e = replay(r#"{ "type": "recording", "version": 2 }"#.as_bytes(), HexapodConfig::default(), |_, _, _| {}).unwrap_err();
+        assert!(matches!(e, ReplayError::Invalid { line: 1, .. }));
+
+        let text = "{ \"type\": \"recording\", \"version\": 1 }\n{ \"type\": \"control\", \"tick\": 0, \"text\": \"{\" }";
+        let e = replay(text.as_bytes(), HexapodConfig::default(), |_, _, _| {}).unwrap_err();
+        assert!(matches!(e, ReplayError::Invalid { line: 2, .. }));
+    }
+}
@@ -0,0 +1,134 @@
+use json::JsonValue;
+
+use crate::math::{ FloatType as float, Vector2, Vector3 };
+use crate::robot::{ BodyPose, BodyPoseResult, GaitEvent, GaitState, Hexapod, HexapodMode, ManipulationState };
+use super::{ create_reply, RequestId };
+
+/// Snapshot of the robot state reported to control clients.
+#[derive(Debug, Clone)]
+pub struct RobotState {
+    pub mode: HexapodMode,
+    pub choreography: Option<String>,
+    pub gait: Option<GaitState>,
+    pub stop_sequence: bool,
+    pub manipulation: Option<(usize, ManipulationState)>,
+    pub body_sway: bool,
+    pub levelling: bool,
+    pub body_pose: BodyPose,
+    pub body_pose_target: BodyPose,
+    pub stability_margin: float
+}
+
+pub fn vector2_to_json(v: &Vector2) -> JsonValue {
+    json::object! { "x": v[0], "y": v[1] }
+}
+
+pub fn vector3_to_json(v: &Vector3) -> JsonValue {
+    json::object! { "x": v[0], "y": v[1], "z": v[2] }
+}
+
+pub fn body_pose_to_json(pose: &BodyPose) -> JsonValue {
+    json::object! {
+        "roll": pose.roll,
+        "pitch": pose.pitch,
+        "yaw": pose.yaw,
+        "offset": vector3_to_json(&pose.offset)
+    }
+}
+
+pub fn body_pose_result_to_json(result: &BodyPoseResult) -> JsonValue {
+    json::object! {
+        "applied": body_pose_to_json(&result.applied),
+        "offset_clamped": result.offset_clamped,
+        "rotation_clamped": result.rotation_clamped
+    }
+}
+
+pub fn gait_to_json(gait: &GaitState) -> JsonValue {
+    json::object! {
+        "step": vector2_to_json(&gait.step),
+        "turn_angle": gait.turn_angle,
+        "step_height_weight": gait.step_height_weight,
+        "lift_ratio": gait.lift_ratio,
+        "speed": gait.speed
+    }
+}
+
+/// Creates an `event` message announcing a gait event raised in the control loop iteration
+/// `tick` at `time` in ms.
+pub fn gait_event_message(event: &GaitEvent, tick: u64, time: u64) -> JsonValue {
+    let mut msg = create_reply("event", None);
+    msg["tick"] = tick.into();
+    msg["time"] = time.into();
+    msg["event"] = event.as_str().into();
+    match event {
+        GaitEvent::WalkStarted { step, turn_angle } => {
+            msg["step"] = vector2_to_json(step);
+            msg["turn_angle"] = (*turn_angle).into();
+        },
+        GaitEvent::StepScaled { scale } => msg["scale"] = (*scale).into(),
+        GaitEvent::LegConfigApplied { leg } => msg["leg"] = (*leg).into(),
+        GaitEvent::LimitClamped { limit, leg } => {
+            msg["limit"] = (*limit).into();
+            msg["leg"] = (*leg).into();
+        },
+        GaitEvent::StopStarted | GaitEvent::StopFinished => {}
+    }
+    msg
+}
+
+fn manipulation_state_name(state: &ManipulationState) -> &'static str {
+    match state {
+        ManipulationState::Entering => "entering",
+        ManipulationState::Active => "active",
+        ManipulationState::Exiting => "exiting"
+    }
+}
+
+impl RobotState {
+    pub fn new(h: &Hexapod, choreography: Option<&str>, levelling: bool) -> Self {
+        RobotState {
+            mode: h.mode(),
+            choreography: choreography.map(|name| name.to_string()),
+            gait: h.gait(),
+            stop_sequence: h.stop_sequence_running(),
+            manipulation: h.manipulation().map(|(leg_id, state)| (leg_id, state.clone())),
+            body_sway: h.body_sway(),
+            levelling,
+            body_pose: h.body_pose(),
+            body_pose_target: h.body_pose_target(),
+            stability_margin: h.stability_margin()
+        }
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let gait = self.gait.as_ref().map_or(JsonValue::Null, gait_to_json);
+        let manipulation = match &self.manipulation {
+            Some((leg_id, state)) => json::object! {
+                "leg": *leg_id,
+                "state": manipulation_state_name(state)
+            },
+            None => JsonValue::Null
+        };
+
+        json::object! {
+            "mode": self.mode.as_str(),
+            "choreography": self.choreography.as_deref(),
+            "gait": gait,
+            "stop_sequence": self.stop_sequence,
+            "manipulation": manipulation,
+            "body_sway": self.body_sway,
+            "levelling": self.levelling,
+            "body_pose": body_pose_to_json(&self.body_pose),
+            "body_pose_target": body_pose_to_json(&self.body_pose_target),
+            "stability_margin": self.stability_margin
+        }
+    }
+
+    /// Creates a reply of the given type (`ack` or `state`) carrying this state.
+    pub fn to_reply(&self, msg_type: &str, id: Option<&RequestId>) -> JsonValue {
+        let mut reply = create_reply(msg_type, id);
+        reply["state"] = self.to_json();
+        reply
+    }
+}
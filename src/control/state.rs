@@ -0,0 +1,127 @@
+use crate::math::{ FloatType as float, Vector2, Vector3 };
+use crate::robot::BodyRotationPivot;
+use super::ControlPacket;
+
+/// Which groups of control inputs were changed by a control packet.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ControlChanges {
+    pub step: bool,
+    pub body_offset: bool,
+    pub body_rotation: bool
+}
+
+/// Current values of the control inputs. Control packets only carry the fields that changed, so
+/// the last received value of every input is kept here.
+#[derive(Debug, Clone)]
+pub struct ControlState {
+    pub step: Vector2,
+    pub step_height_weight: float,
+    pub turn_angle: float,
+    pub body_offset: Vector3,
+    pub body_rotation_angle: float,
+    pub body_rotation_axis: Vector3,
+    pub body_rotation_pivot: BodyRotationPivot
+}
+
+impl Default for ControlState {
+    fn default() -> Self {
+        ControlState {
+            step: Vector2::zero(),
+            step_height_weight: 1.0,
+            turn_angle: 0.0,
+            body_offset: Vector3::zero(),
+            body_rotation_angle: 0.0,
+            body_rotation_axis: Vector3::new(0.0, 0.0, 1.0),
+            body_rotation_pivot: BodyRotationPivot::BodyCenter
+        }
+    }
+}
+
+impl ControlState {
+    /// Merges the fields present in `cp` into the state.
+    pub fn apply(&mut self, cp: &ControlPacket) -> ControlChanges {
+        let mut changes = ControlChanges::default();
+
+        if let Some(step) = &cp.step {
+            self.step = step.clone();
+            changes.step = true;
+        }
+        if let Some(step_height_weight) = cp.step_height_weight {
+            self.step_height_weight = step_height_weight;
+            changes.step = true;
+        }
+        if let Some(turn_angle) = cp.turn_angle {
+            self.turn_angle = turn_angle;
+            changes.step = true;
+        }
+        if let Some(body_offset) = &cp.body_offset {
+            self.body_offset = body_offset.clone();
+            changes.body_offset = true;
+        }
+        if let Some(angle) = cp.body_rotation_angle {
+            self.body_rotation_angle = angle;
+            changes.body_rotation = true;
+        }
+        if let Some(axis) = &cp.body_rotation_axis {
+            self.body_rotation_axis = axis.clone();
+            changes.body_rotation = true;
+        }
+        if let Some(pivot) = &cp.body_rotation_pivot {
+            self.body_rotation_pivot = pivot.clone();
+            changes.body_rotation = true;
+        }
+
+        changes
+    }
+
+    /// Clears the step, the turn and the body pose inputs, keeping the remaining settings.
+    pub fn reset_motion(&mut self) {
+        let default = ControlState::default();
+        self.step = default.step;
+        self.turn_angle = default.turn_angle;
+        self.body_offset = default.body_offset;
+        self.body_rotation_angle = default.body_rotation_angle;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::math::{ Vector2, Vector3 };
+    use crate::control::ControlPacket;
+    use super::ControlState;
+
+    #[test]
+    fn partial_updates() {
+        let mut state = ControlState::default();
+
+        let changes = state.apply(&ControlPacket { turn_angle: Some(0.5), ..Default::default() });
+        assert!(changes.step && !changes.body_offset && !changes.body_rotation);
+        assert_eq!(state.turn_angle, 0.5);
+        assert_eq!(state.step_height_weight, 1.0);
+
+        let changes = state.apply(&ControlPacket { body_offset: Some(Vector3::new(0.1, 0.0, 0.0)), ..Default::default() });
+        assert!(!changes.step && changes.body_offset && !changes.body_rotation);
+        assert_eq!(state.turn_angle, 0.5);
+        assert_eq!(state.step, Vector2::zero());
+        assert_eq!(state.body_offset, Vector3::new(0.1, 0.0, 0.0));
+    }
+
+    #[test]
+    fn reset_motion() {
+        let mut state = ControlState::default();
+        state.apply(&ControlPacket {
+            step: Some(Vector2::new(0.0, 1.0)),
+            step_height_weight: Some(2.0),
+            body_offset: Some(Vector3::new(0.1, 0.0, 0.0)),
+            body_rotation_angle: Some(0.5),
+            ..Default::default()
+        });
+
+        state.reset_motion();
+        assert_eq!(state.step, Vector2::zero());
+        assert_eq!(state.body_offset, Vector3::zero());
+        assert_eq!(state.body_rotation_angle, 0.0);
+        assert_eq!(state.step_height_weight, 2.0);
+    }
+}
@@ -0,0 +1,85 @@
+/// Detects a control link that stopped sending commands.
+///
+/// The watchdog is armed by the first command and expires once no command was received for
+/// longer than the timeout. It is disarmed after expiring until the next command arrives, so the
+/// expiry is reported only once.
+#[derive(Debug, Clone)]
+pub struct Watchdog {
+    /// Timeout in ms, `None` disables the watchdog.
+    timeout: Option<u32>,
+    /// Time since the last command in ms, `None` if the watchdog is not armed.
+    elapsed: Option<u32>
+}
+
+impl Watchdog {
+    pub fn new(timeout: Option<u32>) -> Self {
+        Watchdog { timeout, elapsed: None }
+    }
+
+    /// Records that a command was received.
+    pub fn feed(&mut self) {
+        self.elapsed = Some(0);
+    }
+
+    /// Disarms the watchdog until the next command.
+    pub fn disarm(&mut self) {
+        self.elapsed = None;
+    }
+
+    pub fn armed(&self) -> bool {
+        self.elapsed.is_some()
+    }
+
+    /// Advances the watchdog by `time` ms. Returns true if the watchdog expired.
+    pub fn update(&mut self, time: u32) -> bool {
+        let (timeout, elapsed) = match (self.timeout, self.elapsed) {
+            (Some(timeout), Some(elapsed)) => (timeout, elapsed + time),
+            _ => return false
+        };
+
+        if elapsed > timeout {
+            self.elapsed = None;
+            true
+        }
+        else {
+            self.elapsed = Some(elapsed);
+            false
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::Watchdog;
+
+    #[test]
+    fn expires_once() {
+        let mut watchdog = Watchdog::new(Some(50));
+        assert!(!watchdog.update(100));
+
+        watchdog.feed();
+        for _ in 0..5 {
+            assert!(!watchdog.update(10));
+        }
+        assert!(watchdog.update(10));
+        assert!(!watchdog.armed());
+        assert!(!watchdog.update(100));
+    }
+
+    #[test]
+    fn feeding_keeps_alive() {
+        let mut watchdog = Watchdog::new(Some(50));
+        for _ in 0..100 {
+            watchdog.feed();
+            assert!(!watchdog.update(40));
+        }
+    }
+
+    #[test]
+    fn disabled() {
+        let mut watchdog = Watchdog::new(None);
+        watchdog.feed();
+        assert!(!watchdog.update(u32::MAX / 2));
+    }
+}
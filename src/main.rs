@@ -1,219 +1,218 @@
 #![allow(dead_code)] // TODO: remove this after prototyping phase is done
 
 use std::time::{ Duration, Instant };
-use std::io::Write;
-use log::info;
+use std::fs::File;
+use std::io::{ BufWriter, Write };
+use log::{ info, log, warn, Level };
 
-use std::net::TcpListener;
-use tungstenite::{ accept, Message };
-use json::JsonValue;
+use std::sync::Arc;
 
 mod math;
 mod robot;
+mod config;
+mod control;
+mod server;
+mod telemetry;
 
-use math::{ Vector2, Vector3, FloatType as float, FloatModule };
-use robot::{ Hexapod, HexapodConfig };
-
-#[derive(Debug)]
-struct ControlPacket {
-    step: Vector2,
-    step_height_weight: float,
-    turn_angle: float,
-    body_offset: Vector3,
-    body_rotation_angle: float,
-    body_rotation_axis: Vector3
-}
-
+use config::{ AppConfig, CliOptions, Endpoints };
+use server::{ ControlHub, GaitEventBatch, Metrics, MonitorSender, RobotRequest };
+use telemetry::{ CsvExporter, LoopTiming, Telemetry };
+use control::{ ControlMessage, Controller, Recorder };
+use math::Vector2;
+use robot::{ HexapodConfig, SimulatedImu };
 
-fn get_msg_text(msg: &Message) -> Option<&str> {
-    match msg {
-        Message::Text(s) => Some(s),
-        Message::Binary(v) => Some(std::str::from_utf8(v).expect("Invalid UTF8")),
-        _ => None
-    }
-}
+/// Number of control loop iterations between flushes of the CSV export. Rows written since the
+/// last flush are lost if the program is killed.
+const CSV_FLUSH_INTERVAL: u64 = 100;
 
 
-fn create_pos_info_msg(h: &Hexapod) -> JsonValue {
-    let mut legs = Vec::new();
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
 
-    for i in 0..6 {
-        let mut leg_pos = Vec::new();
-        let start_pos = h.leg_origin(i);
-        let mid_pos = start_pos + h.leg(i).intersection_pos();
-        let end_pos = start_pos + h.leg(i).position();
-        let joint_offset = start_pos + h.leg(i).joint_offset();
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Info)
+        .format(|buf, record| { writeln!(buf, "{}: {}", record.level(), record.args()) })
+        .init();
 
-        leg_pos.push(json::array![-start_pos[0], start_pos[2], start_pos[1]]);
-        leg_pos.push(json::array![-joint_offset[0], joint_offset[2], joint_offset[1]]);
-        leg_pos.push(json::array![-mid_pos[0], mid_pos[2], mid_pos[1]]);
-        leg_pos.push(json::array![-end_pos[0], end_pos[2], end_pos[1]]);
-        legs.push(leg_pos);
+    let options = CliOptions::parse(std::env::args().skip(1))?;
+    if options.help {
+        println!("{}", config::USAGE);
+        return Ok(());
     }
+    let app_config = AppConfig::from_options(&options)?;
 
-    json::object! {
-        "legs": legs
+    if let Some(path) = &options.gait_diagram {
+        let config = HexapodConfig {
+            lift_ratio: options.lift_ratio.unwrap_or(HexapodConfig::default().lift_ratio),
+            ..HexapodConfig::default()
+        };
+        let [x, y] = options.step.unwrap_or([0.0, 1.0]);
+        let samples = telemetry::simulate_gait_cycle(config, &Vector2::new(x, y), options.turn.unwrap_or(0.0))
+            .ok_or("the robot does not walk with the given step and turn")?;
+        std::fs::write(path, telemetry::gait_diagram_svg(&samples))?;
+        info!("Gait diagram written to {}", path.display());
+        return Ok(());
     }
-}
-
-
-fn monitor_listener(rx: std::sync::mpsc::Receiver<JsonValue>) {
-    let addr = "127.0.0.1:8080";
-    let listener = TcpListener::bind(&addr).expect("Can't listen");
-    info!("Listening on: {}", addr);
-
-    // Handle connections
-    while let Ok((stream, _)) = listener.accept() {
-        let peer = stream.peer_addr().expect("connected streams should have a peer address");
-        let mut ws_stream = accept(stream).expect("Failed to accept");
-        info!("New WebSocket connection: {}", peer);
-
-        // Drain previous data from the receive queue.
-        while let Ok(_) = rx.try_recv() { }
 
-        loop {
-            let msg = rx.recv().unwrap();
-            let msg_str = json::stringify(msg);
-            let res = ws_stream.write_message(Message::Text(msg_str));
-            if res.is_err() {
-                break;
+    let mut csv_exporter = match &options.csv {
+        Some(path) => {
+            info!("Exporting CSV to {}", path.display());
+            Some(CsvExporter::new(BufWriter::new(File::create(path)?))?)
+        },
+        None => None
+    };
+
+    if let Some(path) = &options.replay {
+        let mut csv_result = Ok(());
+        let summary = control::replay_file(path, HexapodConfig::default(), |h, tick, time| {
+            if let (Some(exporter), Ok(())) = (&mut csv_exporter, &csv_result) {
+                csv_result = exporter.write(&Telemetry::new(h, tick, time));
             }
+        })?;
+        if let Some(exporter) = &mut csv_exporter {
+            csv_result.and_then(|_| exporter.flush())?;
         }
-    }
-}
-
-fn control_listener(tx: std::sync::mpsc::Sender<ControlPacket>) {
-    let addr = "127.0.0.1:8081";
-    let listener = TcpListener::bind(&addr).expect("Can't listen");
-    info!("Listening on: {}", addr);
-
-    // Handle connections
-    while let Ok((stream, _)) = listener.accept() {
-        let peer = stream.peer_addr().expect("connected streams should have a peer address");
-        let mut ws_stream = accept(stream).expect("Failed to accept");
-        info!("New WebSocket connection: {}", peer);
-
-        loop {
-            if let Ok(msg) = ws_stream.read_message() {
-                if let Ok(msg_text) = msg.into_text() {
-                    if let Ok(json_data) = json::parse(msg_text.as_str()) {
-                        let cp = ControlPacket{
-                            step: Vector2::new(
-                                json_data["step"]["x"].as_f32().unwrap(),
-                                json_data["step"]["y"].as_f32().unwrap()
-                            ),
-                            step_height_weight: json_data["step_height_weight"].as_f32().unwrap(),
-                            turn_angle: json_data["turn_angle"].as_f32().unwrap(),
-                            body_offset: Vector3::new(
-                                json_data["body_offset"]["x"].as_f32().unwrap(),
-                                json_data["body_offset"]["y"].as_f32().unwrap(),
-                                json_data["body_offset"]["z"].as_f32().unwrap()
-                            ),
-                            body_rotation_angle: json_data["body_rotation_angle"].as_f32().unwrap(),
-                            body_rotation_axis: Vector3::new(
-                                json_data["body_rotation_axis"]["x"].as_f32().unwrap(),
-                                json_data["body_rotation_axis"]["y"].as_f32().unwrap(),
-                                json_data["body_rotation_axis"]["z"].as_f32().unwrap()
-                            )
-                        };
-
-                        tx.send(cp).expect("blah");
-                    }
-                }
+        info!("Replayed {} ticks and {} requests", summary.ticks, summary.requests);
+        return match summary.first_mismatch {
+            Some(tick) => Err(format!("leg positions differ from the recording in {} ticks, starting at tick {}",
+                summary.mismatches, tick).into()),
+            None => {
+                info!("Leg positions are identical to the recording");
+                Ok(())
             }
-            else {
-                break;
-            }
-        }
-    }
-}
-
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-
-
-    env_logger::builder()
-        .filter_level(log::LevelFilter::Info)
-        .format(|buf, record| { writeln!(buf, "{}: {}", record.level(), record.args()) })
-        .init();
-
-    let (monitor_tx, monitor_rx) = std::sync::mpsc::channel();
-    let (control_tx, control_rx) = std::sync::mpsc::channel::<ControlPacket>();
-
-    let robot_control_thread = std::thread::spawn(move || {
-        let leg_joint_offset = [
-            Vector3::new(0.01, 0.0, -0.005),
-            Vector3::new(0.01, 0.0, -0.005),
-            Vector3::new(0.01, 0.0, -0.005),
-            Vector3::new(0.01, 0.0, -0.005),
-            Vector3::new(0.01, 0.0, -0.005),
-            Vector3::new(0.01, 0.0, -0.005)
-        ];
-        let legs_origin = [
-            Vector3::new(-0.03,  0.05, 0.02),
-            Vector3::new(-0.04,   0.0, 0.02),
-            Vector3::new(-0.03, -0.05, 0.02),
-            Vector3::new( 0.03,  0.05, 0.02),
-            Vector3::new( 0.04,   0.0, 0.02),
-            Vector3::new( 0.03, -0.05, 0.02)
-        ];
-        let legs_end_pos = [
-            Vector3::new(-0.09,  0.085, 0.00),
-            Vector3::new(-0.11,  0.0,   0.00),
-            Vector3::new(-0.09, -0.085, 0.00),
-            Vector3::new( 0.09,  0.085, 0.00),
-            Vector3::new( 0.11,  0.0,   0.00),
-            Vector3::new( 0.09, -0.085, 0.00)
-        ];
-
-        let config = HexapodConfig {
-            leg_len1: 0.06,
-            leg_len2: 0.06,
-            joint_offset: leg_joint_offset,
-            legs_origin: legs_origin,
-            legs_end_pos: legs_end_pos,
-            max_speed: 0.16,
-            max_step_radius: 0.04,
-            max_move_radius: 0.12,
-            max_step_len: 0.08,
-            max_turn_angle: FloatModule::consts::FRAC_PI_4,
-            max_body_offset: Vector3::new(0.03, 0.03, 0.03),
-            max_body_rotation: FloatModule::consts::FRAC_PI_8
         };
+    }
 
-        let mut h = Hexapod::new(config);
+    let monitor_tx = MonitorSender::default();
+    let (control_tx, control_rx) = std::sync::mpsc::channel::<RobotRequest>();
+
+    let robot_monitor_tx = monitor_tx.clone();
+    let metrics = Arc::new(Metrics::default());
+    let robot_metrics = metrics.clone();
+    let command_timeout = app_config.control.command_timeout;
+    let mut recorder = match &options.record {
+        Some(path) => {
+            info!("Recording the session to {}", path.display());
+            Some(Recorder::create(path, command_timeout)?)
+        },
+        None => None
+    };
+    std::thread::spawn(move || {
+        // The prototype has no IMU, the simulated one reports level ground.
+        let imu = Box::new(SimulatedImu::new(0.0, 0.0));
+        let mut controller = Controller::new(HexapodConfig::default(), command_timeout, imu);
 
         let period :u64 = 10;
         let start = Instant::now();
+        let mut timing = LoopTiming::new(Duration::from_millis(period));
 
         let mut cntr = 0;
         loop {
-            h.update(period as u32);
+            let scheduled = Duration::from_millis(cntr * period);
+            let iteration_start = start.elapsed();
+
+            let update_duration = controller.tick(period as u32);
+            if let Some(recorder) = &mut recorder {
+                recorder.record_tick(cntr, period as u32, controller.hexapod());
+            }
+            let events = controller.take_events();
+            for event in &events {
+                let level = if event.changes_mode() { Level::Info } else { Level::Debug };
+                log!(level, "Gait event at {} ms: {}", cntr * period, event);
+            }
+            if !events.is_empty() && robot_monitor_tx.receiver_count() > 0 {
+                robot_monitor_tx.send_events(GaitEventBatch { tick: cntr, time: cntr * period, events });
+            }
 
-            monitor_tx.send(create_pos_info_msg(&h)).unwrap();
+            // Skip capturing the telemetry if nobody is watching and nothing is exported.
+            if robot_monitor_tx.receiver_count() > 0 || csv_exporter.is_some() {
+                let mut telemetry = Telemetry::new(controller.hexapod(), cntr, cntr * period);
+                if let Some(exporter) = &mut csv_exporter {
+                    let mut res = exporter.write(&telemetry);
+                    if res.is_ok() && (cntr + 1) % CSV_FLUSH_INTERVAL == 0 {
+                        res = exporter.flush();
+                    }
+                    if let Err(e) = res {
+                        warn!("Cannot write CSV export, export stopped: {}", e);
+                        csv_exporter = None;
+                    }
+                }
+                if robot_monitor_tx.receiver_count() > 0 {
+                    telemetry.timing = Some(timing.stats());
+                    robot_monitor_tx.send_telemetry(Arc::new(telemetry));
+                }
+            }
 
-            while let Ok(cp) = control_rx.try_recv() {
-                h.set_step(&cp.step, cp.turn_angle, cp.step_height_weight);
-                h.set_body_offset(&cp.body_offset);
-                h.set_body_rotation(cp.body_rotation_angle, &cp.body_rotation_axis, &Vector3::new(0.0, 0.0, 0.02));
+            while let Ok(robot_request) = control_rx.try_recv() {
+                match robot_request {
+                    RobotRequest::Control { request, text, reply_tx } => {
+                        if let (Some(recorder), ControlMessage::Control(_)) = (&mut recorder, &request.message) {
+                            recorder.record_control(&text);
+                        }
+                        let _ = reply_tx.send(controller.handle_control(&request));
+                    },
+                    RobotRequest::Api { request, text, reply_tx } => {
+                        if let Some(recorder) = &mut recorder {
+                            recorder.record_api(&request, &text);
+                        }
+                        let _ = reply_tx.send(controller.handle_api(&request));
+                    },
+                    RobotRequest::Disconnected(peer) => {
+                        let peer = peer.to_string();
+                        if let Some(recorder) = &mut recorder {
+                            recorder.record_disconnected(&peer);
+                        }
+                        controller.disconnected(&peer);
+                    },
+                    RobotRequest::LeaseEnded(client) => {
+                        if let Some(recorder) = &mut recorder {
+                            recorder.record_lease_ended(client);
+                        }
+                        controller.lease_ended(client);
+                    }
+                }
             }
 
-            std::thread::sleep(Duration::from_millis(cntr * period).saturating_sub(start.elapsed()));
+            let busy = start.elapsed() - iteration_start;
+            let jitter = iteration_start.saturating_sub(scheduled);
+            let overrun = timing.record(update_duration, jitter, busy);
+            robot_metrics.record_iteration(update_duration, jitter, overrun);
+            robot_metrics.record_robot(&controller, robot_monitor_tx.receiver_count());
+
+            std::thread::sleep(Duration::from_millis((cntr + 1) * period).saturating_sub(start.elapsed()));
             cntr += 1;
         }
     });
 
-    let monitor_listener_thread = std::thread::spawn(move || {
-        monitor_listener(monitor_rx);
-    });
-
-    let control_listener_thread = std::thread::spawn(move || {
-        control_listener(control_tx);
-    });
+    let control_hub = ControlHub::new(&app_config.control, control_tx, metrics);
+    tokio::spawn(control_hub.clone().run_lease_timer());
 
-    monitor_listener_thread.join().expect("Couldn't join on the associated thread");
-    control_listener_thread.join().expect("Couldn't join on the associated thread");
-    robot_control_thread.join().expect("Couldn't join on the associated thread");
+    let udp_listener = async {
+        match &app_config.network.udp {
+            Some(addr) => server::udp_listener(addr, control_hub.clone()).await,
+            None => Ok(())
+        }
+    };
+    let http_listener = async {
+        match &app_config.network.http {
+            Some(addr) => server::http_listener(addr, control_hub.clone(), &app_config.network.endpoints).await,
+            None => Ok(())
+        }
+    };
+    let websocket_listeners = async {
+        match &app_config.network.endpoints {
+            Endpoints::Separate { monitor, control } => {
+                tokio::try_join!(
+                    server::monitor_listener(monitor, monitor_tx),
+                    server::control_listener(control, control_hub.clone())
+                )?;
+                Ok(())
+            },
+            Endpoints::Shared(addr) => server::shared_listener(addr, monitor_tx, control_hub.clone()).await
+        }
+    };
+    tokio::try_join!(websocket_listeners, udp_listener, http_listener)?;
 
     Ok(())
 }
@@ -11,6 +11,7 @@ pub struct Matrix3 {
 
 impl Matrix3 {
 
+    #[allow(clippy::too_many_arguments)]
     pub fn new(m0: float, m1: float, m2: float, m3: float, m4: float, m5: float, m6: float, m7: float, m8: float) -> Self {
         Self { m: [m0, m1, m2, m3, m4, m5, m6, m7, m8] }
     }
@@ -230,6 +230,7 @@ mod circle;
 mod matrix2;
 mod matrix3;
 mod point;
+mod polygon;
 mod vector2;
 mod vector3;
 mod quaternion;
@@ -240,6 +241,7 @@ pub use circle::*;
 pub use matrix2::*;
 pub use matrix3::*;
 pub use point::*;
+pub use polygon::*;
 pub use vector2::*;
 pub use vector3::*;
 pub use quaternion::*;
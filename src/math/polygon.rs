@@ -0,0 +1,186 @@
+use super::{ FloatType as float, Vector2 };
+
+/// Convex polygon with its vertices stored in counter-clockwise order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Polygon {
+    points: Vec<Vector2>
+}
+
+impl Polygon {
+    fn cross(o: &Vector2, a: &Vector2, b: &Vector2) -> float {
+        (a[0] - o[0]) * (b[1] - o[1]) - (a[1] - o[1]) * (b[0] - o[0])
+    }
+
+    fn segment_dist(p: &Vector2, a: &Vector2, b: &Vector2) -> float {
+        let ab = b - a;
+        let ab_len_sq = ab.dot(&ab);
+        if ab_len_sq == 0.0 {
+            return p.dist(a);
+        }
+        let t = ((p - a).dot(&ab) / ab_len_sq).clamp(0.0, 1.0);
+        p.dist(&(a + ab * t))
+    }
+
+    /// Creates the convex hull of the given points using the monotone chain algorithm.
+    pub fn convex_hull(points: &[Vector2]) -> Self {
+        let mut sorted = points.to_vec();
+        sorted.sort_by(|a, b| a[0].partial_cmp(&b[0]).unwrap().then(a[1].partial_cmp(&b[1]).unwrap()));
+        sorted.dedup();
+
+        if sorted.len() < 3 {
+            return Polygon { points: sorted };
+        }
+
+        let mut lower: Vec<Vector2> = Vec::new();
+        for p in sorted.iter() {
+            while lower.len() >= 2 && Self::cross(&lower[lower.len() - 2], &lower[lower.len() - 1], p) <= 0.0 {
+                lower.pop();
+            }
+            lower.push(p.clone());
+        }
+
+        let mut upper: Vec<Vector2> = Vec::new();
+        for p in sorted.iter().rev() {
+            while upper.len() >= 2 && Self::cross(&upper[upper.len() - 2], &upper[upper.len() - 1], p) <= 0.0 {
+                upper.pop();
+            }
+            upper.push(p.clone());
+        }
+
+        lower.pop();
+        upper.pop();
+        lower.append(&mut upper);
+
+        Polygon { points: lower }
+    }
+
+    pub fn points(&self) -> &[Vector2] {
+        &self.points
+    }
+
+    pub fn area(&self) -> float {
+        let n = self.points.len();
+        let mut area = 0.0;
+        for i in 0..n {
+            let a = &self.points[i];
+            let b = &self.points[(i + 1) % n];
+            area += a[0] * b[1] - b[0] * a[1];
+        }
+        area / 2.0
+    }
+
+    /// Returns the area centroid of the polygon. Degenerate polygons fall back to the mean of
+    /// their vertices.
+    pub fn centroid(&self) -> Vector2 {
+        let n = self.points.len();
+        if n == 0 {
+            return Vector2::zero();
+        }
+
+        let area = self.area();
+        if area.abs() < float::EPSILON {
+            let sum = self.points.iter().fold(Vector2::zero(), |acc, p| acc + p);
+            return sum / (n as float);
+        }
+
+        let (mut cx, mut cy) = (0.0, 0.0);
+        for i in 0..n {
+            let a = &self.points[i];
+            let b = &self.points[(i + 1) % n];
+            let f = a[0] * b[1] - b[0] * a[1];
+            cx += (a[0] + b[0]) * f;
+            cy += (a[1] + b[1]) * f;
+        }
+        Vector2::new(cx / (6.0 * area), cy / (6.0 * area))
+    }
+
+    /// Signed distance between `p` and the closest edge of the polygon. The result is positive
+    /// when `p` is inside the polygon and negative otherwise.
+    pub fn stability_margin(&self, p: &Vector2) -> float {
+        let n = self.points.len();
+        match n {
+            0 => float::NEG_INFINITY,
+            1 => -p.dist(&self.points[0]),
+            2 => -Self::segment_dist(p, &self.points[0], &self.points[1]),
+            _ => {
+                let mut inside = true;
+                let mut min_dist = float::INFINITY;
+                for i in 0..n {
+                    let a = &self.points[i];
+                    let b = &self.points[(i + 1) % n];
+                    if Self::cross(a, b, p) < 0.0 {
+                        inside = false;
+                    }
+                    min_dist = min_dist.min(Self::segment_dist(p, a, b));
+                }
+                if inside { min_dist } else { -min_dist }
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::super::{ FloatType as float, FloatEq, AssertFloatEq, Vector2 };
+    use super::Polygon;
+
+    const TOL: float = 1e-5;
+
+    #[test]
+    fn convex_hull() {
+        let points = [
+            Vector2::new(0.0, 0.0),
+            Vector2::new(2.0, 0.0),
+            Vector2::new(1.0, 1.0),
+            Vector2::new(2.0, 2.0),
+            Vector2::new(0.0, 2.0),
+            Vector2::new(0.5, 1.5)
+        ];
+        let hull = Polygon::convex_hull(&points);
+        assert_eq!(hull.points(), &[
+            Vector2::new(0.0, 0.0),
+            Vector2::new(2.0, 0.0),
+            Vector2::new(2.0, 2.0),
+            Vector2::new(0.0, 2.0)
+        ]);
+
+        // Collinear points
+        let hull = Polygon::convex_hull(&[Vector2::new(0.0, 0.0), Vector2::new(1.0, 0.0), Vector2::new(2.0, 0.0)]);
+        assert_eq!(hull.points().len(), 2);
+    }
+
+    #[test]
+    fn area() {
+        let hull = Polygon::convex_hull(&[Vector2::new(0.0, 0.0), Vector2::new(4.0, 0.0), Vector2::new(0.0, 3.0)]);
+        assert_float_eq!(hull.area(), 6.0, TOL);
+    }
+
+    #[test]
+    fn centroid() {
+        let hull = Polygon::convex_hull(&[Vector2::new(0.0, 0.0), Vector2::new(3.0, 0.0), Vector2::new(0.0, 3.0)]);
+        assert!(Vector2::near_eq_rel(&hull.centroid(), &Vector2::new(1.0, 1.0), &TOL));
+
+        let hull = Polygon::convex_hull(&[
+            Vector2::new(-1.0, -1.0), Vector2::new(1.0, -1.0), Vector2::new(1.0, 1.0), Vector2::new(-1.0, 1.0)
+        ]);
+        assert!(Vector2::near_eq_abs(&hull.centroid(), &Vector2::new(0.0, 0.0), &TOL));
+
+        // Degenerate polygon
+        let hull = Polygon::convex_hull(&[Vector2::new(0.0, 0.0), Vector2::new(2.0, 2.0)]);
+        assert!(Vector2::near_eq_rel(&hull.centroid(), &Vector2::new(1.0, 1.0), &TOL));
+    }
+
+    #[test]
+    fn stability_margin() {
+        let hull = Polygon::convex_hull(&[
+            Vector2::new(-1.0, -1.0), Vector2::new(1.0, -1.0), Vector2::new(1.0, 1.0), Vector2::new(-1.0, 1.0)
+        ]);
+        assert_float_eq!(hull.stability_margin(&Vector2::new(0.0, 0.0)), 1.0, TOL);
+        assert_float_eq!(hull.stability_margin(&Vector2::new(0.5, 0.0)), 0.5, TOL);
+        assert_float_eq!(hull.stability_margin(&Vector2::new(3.0, 0.0)), -2.0, TOL);
+
+        let hull = Polygon::convex_hull(&[Vector2::new(0.0, 0.0), Vector2::new(2.0, 0.0)]);
+        assert_float_eq!(hull.stability_margin(&Vector2::new(1.0, 1.0)), -1.0, TOL);
+    }
+}
@@ -9,7 +9,7 @@ pub struct Quaternion {
 
 impl Quaternion {
     pub fn new(s: float, x: float, y: float, z: float ) -> Self {
-        Quaternion{ s: s, v: Vector3::new(x, y, z) }
+        Quaternion{ s, v: Vector3::new(x, y, z) }
     }
 
     pub fn from_rotation(angle: float, axis: Vector3 ) -> Self {
@@ -20,6 +20,27 @@ impl Quaternion {
         Quaternion{ s: angle_cos, v: axis * angle_sin }
     }
 
+    /// Creates a rotation from roll (around the Y axis), pitch (around the X axis) and yaw
+    /// (around the Z axis) angles, applied in this order.
+    pub fn from_rpy(roll: float, pitch: float, yaw: float) -> Self {
+        Quaternion::from_rotation(yaw, Vector3::new(0.0, 0.0, 1.0)) *
+        Quaternion::from_rotation(pitch, Vector3::new(1.0, 0.0, 0.0)) *
+        Quaternion::from_rotation(roll, Vector3::new(0.0, 1.0, 0.0))
+    }
+
+    /// Returns the angle and axis of the rotation described by a unit quaternion. The angle is
+    /// always in the [0, π] range and the axis defaults to Z when there is no rotation.
+    pub fn to_rotation(&self) -> (float, Vector3) {
+        let q = if self.s < 0.0 { self * -1.0 } else { self.clone() };
+        let sin_half = q.v.len();
+        if sin_half < float::EPSILON {
+            (0.0, Vector3::new(0.0, 0.0, 1.0))
+        }
+        else {
+            (2.0 * float::atan2(sin_half, q.s), &q.v / sin_half)
+        }
+    }
+
     pub fn len(&self) -> float {
         float::sqrt(self.s*self.s + self.v[0]*self.v[0] + self.v[1]*self.v[1] + self.v[2]*self.v[2])
     }
@@ -139,6 +160,7 @@ impl FloatEq for Quaternion {
 
 
 #[cfg(test)]
+#[allow(clippy::op_ref, clippy::clone_on_copy)]
 mod tests {
     use crate::math::Matrix3;
 
@@ -155,6 +177,36 @@ mod tests {
         assert!(Quaternion::near_eq_rel(&q1, &q2, &TOL));
     }
 
+    #[test]
+    fn from_rpy() {
+        let q1 = Quaternion::from_rpy(0.3, 0.0, 0.0);
+        let q2 = Quaternion::from_rotation(0.3, Vector3::new(0.0, 1.0, 0.0));
+        assert!(Quaternion::near_eq_abs(&q1, &q2, &TOL));
+
+        let q1 = Quaternion::from_rpy(0.1, 0.2, 0.3);
+        let q2 = Quaternion::from_rotation(0.3, Vector3::new(0.0, 0.0, 1.0)) *
+            Quaternion::from_rotation(0.2, Vector3::new(1.0, 0.0, 0.0)) *
+            Quaternion::from_rotation(0.1, Vector3::new(0.0, 1.0, 0.0));
+        assert!(Quaternion::near_eq_abs(&q1, &q2, &TOL));
+    }
+
+    #[test]
+    fn to_rotation() {
+        let (angle, axis) = Quaternion::from_rotation(0.576, Vector3::new(1.0, 2.0, 3.0)).to_rotation();
+        assert_float_eq!(angle, 0.576, TOL);
+        assert!(Vector3::near_eq_rel(&axis, &Vector3::new(1.0, 2.0, 3.0).norm(), &TOL));
+
+        // Negative angles are returned with a flipped axis
+        let (angle, axis) = Quaternion::from_rotation(-0.5, Vector3::new(0.0, 1.0, 0.0)).to_rotation();
+        assert_float_eq!(angle, 0.5, TOL);
+        assert!(Vector3::near_eq_rel(&axis, &Vector3::new(0.0, -1.0, 0.0), &TOL));
+
+        // No rotation
+        let (angle, axis) = Quaternion::new(1.0, 0.0, 0.0, 0.0).to_rotation();
+        assert_float_eq!(angle, 0.0, TOL);
+        assert!(Vector3::near_eq_rel(&axis, &Vector3::new(0.0, 0.0, 1.0), &TOL));
+    }
+
     #[test]
     fn len() {
         let q = Quaternion::new(0.0, 0.0, 0.0, 0.0);
@@ -30,6 +30,23 @@ pub fn rotate_matrix3(angle: float, axis: &Vector3) -> Matrix3 {
     )
 }
 
+/// Creates a rotation matrix from roll (around the Y axis), pitch (around the X axis) and yaw
+/// (around the Z axis) angles, applied in this order.
+pub fn rotate_matrix3_rpy(roll: float, pitch: float, yaw: float) -> Matrix3 {
+    rotate_matrix3(yaw, &Vector3::new(0.0, 0.0, 1.0)) *
+    rotate_matrix3(pitch, &Vector3::new(1.0, 0.0, 0.0)) *
+    rotate_matrix3(roll, &Vector3::new(0.0, 1.0, 0.0))
+}
+
+/// Decomposes a rotation matrix into the roll, pitch and yaw angles used by
+/// [`rotate_matrix3_rpy`].
+pub fn rpy_from_matrix3(m: &Matrix3) -> (float, float, float) {
+    let pitch = m[2][1].clamp(-1.0, 1.0).asin();
+    let roll = float::atan2(-m[2][0], m[2][2]);
+    let yaw = float::atan2(-m[0][1], m[1][1]);
+    (roll, pitch, yaw)
+}
+
 pub fn scale_matrix(v: &Vector3) -> Matrix3 {
     Matrix3::new(
         v[0], 0.0, 0.0,
@@ -37,3 +54,23 @@ pub fn scale_matrix(v: &Vector3) -> Matrix3 {
         0.0, 0.0, v[2]
     )
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::super::{ FloatType as float, FloatEq, AssertFloatEq, Vector3 };
+    use super::{ rotate_matrix3, rotate_matrix3_rpy, rpy_from_matrix3 };
+
+    const TOL: float = 1e-5;
+
+    #[test]
+    fn rpy() {
+        let m = rotate_matrix3_rpy(0.2, 0.0, 0.0);
+        assert!(m.near_eq_abs(&rotate_matrix3(0.2, &Vector3::new(0.0, 1.0, 0.0)), &TOL));
+
+        let (roll, pitch, yaw) = rpy_from_matrix3(&rotate_matrix3_rpy(0.1, -0.2, 0.3));
+        assert_float_eq!(roll, 0.1, TOL);
+        assert_float_eq!(pitch, -0.2, TOL);
+        assert_float_eq!(yaw, 0.3, TOL);
+    }
+}
@@ -192,6 +192,7 @@ impl FloatEq for Vector2 {
 }
 
 #[cfg(test)]
+#[allow(clippy::op_ref, clippy::approx_constant)]
 mod tests {
     use super::super::{ FloatType as float, FloatEq, AssertFloatEq };
     use super::Vector2;
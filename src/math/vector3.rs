@@ -204,6 +204,7 @@ impl FloatEq for Vector3 {
 }
 
 #[cfg(test)]
+#[allow(clippy::op_ref, clippy::excessive_precision)]
 mod tests {
     use super::super::{ FloatType as float, FloatEq, AssertFloatEq };
     use super::Vector3;
@@ -0,0 +1,471 @@
+use std::fmt::Display;
+use json::JsonValue;
+
+use crate::math::{ FloatType as float, Vector3 };
+use super::{ BodyPose, Hexapod };
+
+#[derive(Debug)]
+pub enum ChoreographyError {
+    Io(std::io::Error),
+    Parse(json::Error),
+    Invalid(String)
+}
+
+impl Display for ChoreographyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChoreographyError::Io(e) => write!(f, "cannot read choreography: {}", e),
+            ChoreographyError::Parse(e) => write!(f, "cannot parse choreography: {}", e),
+            ChoreographyError::Invalid(s) => write!(f, "invalid choreography: {}", s)
+        }
+    }
+}
+
+impl std::error::Error for ChoreographyError {}
+
+impl From<std::io::Error> for ChoreographyError {
+    fn from(e: std::io::Error) -> Self {
+        ChoreographyError::Io(e)
+    }
+}
+
+impl From<json::Error> for ChoreographyError {
+    fn from(e: json::Error) -> Self {
+        ChoreographyError::Parse(e)
+    }
+}
+
+/// Easing function used to interpolate towards a keyframe from the previous one.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+    /// Holds the previous value until the keyframe is reached.
+    Step
+}
+
+impl Easing {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "linear" => Some(Easing::Linear),
+            "ease_in" => Some(Easing::EaseIn),
+            "ease_out" => Some(Easing::EaseOut),
+            "ease_in_out" => Some(Easing::EaseInOut),
+            "step" => Some(Easing::Step),
+            _ => None
+        }
+    }
+
+    /// Maps the linear progress `t` in the [0, 1] range to the eased progress.
+    pub fn apply(&self, t: float) -> float {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Easing::EaseInOut => if t < 0.5 { 2.0 * t * t } else { 1.0 - (2.0 - 2.0 * t).powi(2) / 2.0 },
+            Easing::Step => if t < 1.0 { 0.0 } else { 1.0 }
+        }
+    }
+}
+
+pub trait Interpolate {
+    fn interpolate(&self, other: &Self, t: float) -> Self;
+}
+
+impl Interpolate for Vector3 {
+    fn interpolate(&self, other: &Self, t: float) -> Self {
+        self + (other - self) * t
+    }
+}
+
+impl Interpolate for BodyPose {
+    fn interpolate(&self, other: &Self, t: float) -> Self {
+        BodyPose {
+            roll: self.roll + (other.roll - self.roll) * t,
+            pitch: self.pitch + (other.pitch - self.pitch) * t,
+            yaw: self.yaw + (other.yaw - self.yaw) * t,
+            offset: self.offset.interpolate(&other.offset, t)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Keyframe<T> {
+    /// Time of the keyframe in ms from the start of the choreography.
+    pub time: u32,
+    pub value: T,
+    pub easing: Easing
+}
+
+#[derive(Debug, Clone)]
+pub struct Track<T> {
+    keyframes: Vec<Keyframe<T>>
+}
+
+impl<T: Interpolate + Clone> Track<T> {
+    pub fn new(mut keyframes: Vec<Keyframe<T>>) -> Self {
+        keyframes.sort_by_key(|k| k.time);
+        Track { keyframes }
+    }
+
+    pub fn duration(&self) -> u32 {
+        self.keyframes.last().map_or(0, |k| k.time)
+    }
+
+    /// Returns the value of the track at the given time in ms, or `None` if the track is empty.
+    pub fn sample(&self, time: u32) -> Option<T> {
+        let first = self.keyframes.first()?;
+        if time <= first.time {
+            return Some(first.value.clone());
+        }
+
+        for k in self.keyframes.windows(2) {
+            let (a, b) = (&k[0], &k[1]);
+            if time < b.time {
+                let t = (time - a.time) as float / (b.time - a.time) as float;
+                return Some(a.value.interpolate(&b.value, b.easing.apply(t)));
+            }
+        }
+
+        self.keyframes.last().map(|k| k.value.clone())
+    }
+}
+
+/// Sampled state of a choreography at a given time.
+#[derive(Debug, Clone)]
+pub struct ChoreographyFrame {
+    pub body_pose: Option<BodyPose>,
+    pub legs_offset: [Option<Vector3>; 6]
+}
+
+/// Timeline of body poses and foot positions.
+///
+/// Choreographies are stored as JSON files in the following format, where times are given in ms,
+/// angles in radians and distances in metres. Angles and offsets that are left out are 0:
+///
+/// ```json
+/// {
+///     "loop": true,
+///     "body": [
+///         { "time": 0, "roll": 0.0, "pitch": 0.0, "yaw": 0.0, "offset": { "x": 0.0, "y": 0.0, "z": 0.0 } },
+///         { "time": 1000, "pitch": 0.2, "easing": "ease_in_out" }
+///     ],
+///     "legs": [
+///         { "leg": 3, "keyframes": [
+///             { "time": 0, "offset": { "x": 0.0, "y": 0.0, "z": 0.0 } },
+///             { "time": 500, "offset": { "x": 0.0, "y": 0.02, "z": 0.03 }, "easing": "ease_out" }
+///         ] }
+///     ]
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Choreography {
+    body: Track<BodyPose>,
+    legs: [Track<Vector3>; 6],
+    looping: bool
+}
+
+fn invalid(path: &str, message: &str) -> ChoreographyError {
+    ChoreographyError::Invalid(format!("{}: {}", path, message))
+}
+
+fn join_path(path: &str, name: &str) -> String {
+    if path.is_empty() { name.to_string() } else { format!("{}.{}", path, name) }
+}
+
+fn field<'a>(data: &'a JsonValue, name: &str) -> Option<&'a JsonValue> {
+    if data.has_key(name) { Some(&data[name]) } else { None }
+}
+
+/// Fails if `data` is not an object or has a field that is not in `allowed`.
+fn check_object(data: &JsonValue, path: &str, allowed: &[&str]) -> Result<(), ChoreographyError> {
+    if !data.is_object() {
+        return Err(invalid(path, "expected an object"));
+    }
+    match data.entries().find(|(name, _)| !allowed.contains(name)) {
+        Some((name, _)) => Err(invalid(&join_path(path, name), "unknown field")),
+        None => Ok(())
+    }
+}
+
+/// Returns the elements of the array in the field `name`, or none if the field is missing.
+fn array_field<'a>(data: &'a JsonValue, path: &str, name: &str) -> Result<impl Iterator<Item = (&'a JsonValue, String)>, ChoreographyError> {
+    let field_path = join_path(path, name);
+    let members = match field(data, name) {
+        Some(value) if value.is_array() => value.members(),
+        Some(_) => return Err(invalid(&field_path, "expected an array")),
+        None => Default::default()
+    };
+    Ok(members.enumerate().map(move |(i, value)| (value, format!("{}[{}]", field_path, i))))
+}
+
+fn parse_float(data: &JsonValue, path: &str) -> Result<float, ChoreographyError> {
+    data.as_number()
+        .map(|n| f64::from(n) as float)
+        .filter(|v| v.is_finite())
+        .ok_or_else(|| invalid(path, "expected a number"))
+}
+
+/// Parses the number in the field `name`, which is 0 if the field is missing.
+fn parse_optional_float(data: &JsonValue, path: &str, name: &str) -> Result<float, ChoreographyError> {
+    field(data, name).map_or(Ok(0.0), |value| parse_float(value, &join_path(path, name)))
+}
+
+fn parse_vector(data: &JsonValue, path: &str) -> Result<Vector3, ChoreographyError> {
+    check_object(data, path, &["x", "y", "z"])?;
+    let component = |name| match field(data, name) {
+        Some(value) => parse_float(value, &join_path(path, name)),
+        None => Err(invalid(&join_path(path, name), "missing field"))
+    };
+    Ok(Vector3::new(component("x")?, component("y")?, component("z")?))
+}
+
+/// Parses the vector in the field `name`, which is zero if the field is missing.
+fn parse_optional_vector(data: &JsonValue, path: &str, name: &str) -> Result<Vector3, ChoreographyError> {
+    field(data, name).map_or(Ok(Vector3::zero()), |value| parse_vector(value, &join_path(path, name)))
+}
+
+fn parse_keyframe<T>(data: &JsonValue, path: &str, value: T) -> Result<Keyframe<T>, ChoreographyError> {
+    let time_path = join_path(path, "time");
+    let time = field(data, "time")
+        .ok_or_else(|| invalid(&time_path, "missing field"))?
+        .as_u32()
+        .ok_or_else(|| invalid(&time_path, "expected a time in ms"))?;
+    let easing = match field(data, "easing") {
+        Some(easing) => {
+            let easing_path = join_path(path, "easing");
+            let name = easing.as_str().ok_or_else(|| invalid(&easing_path, "expected a string"))?;
+            Easing::from_name(name).ok_or_else(|| invalid(&easing_path, &format!("unknown easing function: {}", name)))?
+        },
+        None => Easing::Linear
+    };
+    Ok(Keyframe { time, value, easing })
+}
+
+impl Choreography {
+    /// Parses a choreography. Unknown fields and values of the wrong type are rejected with the
+    /// path of the offending field, e.g. `body[1].pitch`.
+    pub fn from_json(data: &JsonValue) -> Result<Self, ChoreographyError> {
+        check_object(data, "", &["loop", "body", "legs"])?;
+
+        let mut body_keyframes = Vec::new();
+        for (k, path) in array_field(data, "", "body")? {
+            check_object(k, &path, &["time", "easing", "roll", "pitch", "yaw", "offset"])?;
+            let pose = BodyPose {
+                roll: parse_optional_float(k, &path, "roll")?,
+                pitch: parse_optional_float(k, &path, "pitch")?,
+                yaw: parse_optional_float(k, &path, "yaw")?,
+                offset: parse_optional_vector(k, &path, "offset")?
+            };
+            body_keyframes.push(parse_keyframe(k, &path, pose)?);
+        }
+
+        let mut legs_keyframes: [Vec<Keyframe<Vector3>>; 6] = Default::default();
+        for (leg, leg_path) in array_field(data, "", "legs")? {
+            check_object(leg, &leg_path, &["leg", "keyframes"])?;
+            let leg_id = field(leg, "leg")
+                .and_then(|id| id.as_usize())
+                .filter(|&id| id < 6)
+                .ok_or_else(|| invalid(&join_path(&leg_path, "leg"), "expected a leg id in the [0, 5] range"))?;
+            for (k, path) in array_field(leg, &leg_path, "keyframes")? {
+                check_object(k, &path, &["time", "easing", "offset"])?;
+                let keyframe = parse_keyframe(k, &path, parse_optional_vector(k, &path, "offset")?)?;
+                legs_keyframes[leg_id].push(keyframe);
+            }
+        }
+
+        let looping = match field(data, "loop") {
+            Some(looping) => looping.as_bool().ok_or_else(|| invalid("loop", "expected a boolean"))?,
+            None => false
+        };
+        let choreography = Choreography {
+            body: Track::new(body_keyframes),
+            legs: legs_keyframes.map(Track::new),
+            looping
+        };
+
+        if choreography.looping && choreography.duration() == 0 {
+            return Err(ChoreographyError::Invalid("looping choreography with zero duration".to_string()));
+        }
+
+        Ok(choreography)
+    }
+
+    pub fn load(path: &std::path::Path) -> Result<Self, ChoreographyError> {
+        let text = std::fs::read_to_string(path)?;
+        Self::from_json(&json::parse(&text)?)
+    }
+
+    /// Returns the length of the choreography in ms.
+    pub fn duration(&self) -> u32 {
+        self.legs.iter().map(|t| t.duration()).fold(self.body.duration(), u32::max)
+    }
+
+    pub fn looping(&self) -> bool {
+        self.looping
+    }
+
+    pub fn sample(&self, time: u32) -> ChoreographyFrame {
+        ChoreographyFrame {
+            body_pose: self.body.sample(time),
+            legs_offset: [0, 1, 2, 3, 4, 5].map(|i| self.legs[i].sample(time))
+        }
+    }
+}
+
+/// Plays a choreography on a hexapod. Every sampled frame goes through the regular `Hexapod`
+/// setters, so the same limits apply as for manual control.
+#[derive(Debug, Clone)]
+pub struct ChoreographyPlayer {
+    choreography: Choreography,
+    time: u32
+}
+
+impl ChoreographyPlayer {
+    pub fn new(choreography: Choreography) -> Self {
+        ChoreographyPlayer { choreography, time: 0 }
+    }
+
+    pub fn has_finished(&self) -> bool {
+        !self.choreography.looping() && self.time > self.choreography.duration()
+    }
+
+    /// Applies the current frame to the hexapod and advances the playback. `origin` is the body
+    /// rotation pivot and `time` must be given in ms.
+    pub fn advance(&mut self, hexapod: &mut Hexapod, origin: &Vector3, time: u32) {
+        let frame = self.choreography.sample(self.time);
+
+        if let Some(body_pose) = &frame.body_pose {
+            hexapod.set_body_pose(body_pose, origin);
+        }
+        for (i, leg_offset) in frame.legs_offset.iter().enumerate() {
+            if let Some(leg_offset) = leg_offset {
+                hexapod.set_leg_offset(i, leg_offset);
+            }
+        }
+
+        self.time += time;
+        if self.choreography.looping() && self.time >= self.choreography.duration() {
+            self.time %= self.choreography.duration();
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::{ math::{ FloatType as float, FloatEq, Quaternion, Vector3 }, assert_float_eq, float_eq };
+    use crate::robot::HexapodConfig;
+    use super::{ Choreography, ChoreographyError, Easing, Keyframe, Track };
+
+    const TOL: float = 1e-5;
+
+    #[test]
+    fn easing() {
+        assert_float_eq!(Easing::Linear.apply(0.25), 0.25, TOL, abs);
+        assert_float_eq!(Easing::EaseIn.apply(0.5), 0.25, TOL, abs);
+        assert_float_eq!(Easing::EaseOut.apply(0.5), 0.75, TOL, abs);
+        assert_float_eq!(Easing::EaseInOut.apply(0.25), 0.125, TOL, abs);
+        assert_float_eq!(Easing::EaseInOut.apply(0.75), 0.875, TOL, abs);
+        assert_float_eq!(Easing::Step.apply(0.99), 0.0, TOL, abs);
+        assert_float_eq!(Easing::Step.apply(1.0), 1.0, TOL, abs);
+
+        for easing in [Easing::Linear, Easing::EaseIn, Easing::EaseOut, Easing::EaseInOut] {
+            assert_float_eq!(easing.apply(0.0), 0.0, TOL, abs);
+            assert_float_eq!(easing.apply(1.0), 1.0, TOL, abs);
+        }
+    }
+
+    #[test]
+    fn track_sample() {
+        let track = Track::new(vec![
+            Keyframe { time: 100, value: Vector3::new(0.0, 0.0, 0.0), easing: Easing::Linear },
+            Keyframe { time: 300, value: Vector3::new(2.0, 0.0, 0.0), easing: Easing::Linear },
+            Keyframe { time: 400, value: Vector3::new(2.0, 1.0, 0.0), easing: Easing::Step }
+        ]);
+
+        assert!(Vector3::near_eq_abs(&track.sample(0).unwrap(), &Vector3::new(0.0, 0.0, 0.0), &TOL));
+        assert!(Vector3::near_eq_abs(&track.sample(200).unwrap(), &Vector3::new(1.0, 0.0, 0.0), &TOL));
+        assert!(Vector3::near_eq_abs(&track.sample(350).unwrap(), &Vector3::new(2.0, 0.0, 0.0), &TOL));
+        assert!(Vector3::near_eq_abs(&track.sample(500).unwrap(), &Vector3::new(2.0, 1.0, 0.0), &TOL));
+        assert!(Track::<Vector3>::new(Vec::new()).sample(0).is_none());
+    }
+
+    #[test]
+    fn from_json() {
+        let data = json::parse(r#"{
+            "loop": true,
+            "body": [
+                { "time": 0 },
+                { "time": 1000, "pitch": 0.2, "easing": "ease_in_out" }
+            ],
+            "legs": [
+                { "leg": 3, "keyframes": [
+                    { "time": 0 },
+                    { "time": 1500, "offset": { "x": 0.0, "y": 0.0, "z": 0.03 } }
+                ] }
+            ]
+        }"#).unwrap();
+        let choreography = Choreography::from_json(&data).unwrap();
+
+        assert!(choreography.looping());
+        assert_eq!(choreography.duration(), 1500);
+
+        let frame = choreography.sample(500);
+        assert_float_eq!(frame.body_pose.unwrap().pitch, 0.1, TOL, abs);
+        assert!(frame.legs_offset[0].is_none());
+        assert!(Vector3::near_eq_abs(frame.legs_offset[3].as_ref().unwrap(), &Vector3::new(0.0, 0.0, 0.01), &TOL));
+
+        let data = json::parse(r#"{ "body": [ { "time": 0, "easing": "bounce" } ] }"#).unwrap();
+        assert!(Choreography::from_json(&data).is_err());
+
+        let data = json::parse(r#"{ "legs": [ { "leg": 6, "keyframes": [] } ] }"#).unwrap();
+        assert!(Choreography::from_json(&data).is_err());
+    }
+
+    #[test]
+    fn from_json_invalid() {
+        let error = |text: &str| match Choreography::from_json(&json::parse(text).unwrap()) {
+            Err(ChoreographyError::Invalid(message)) => message,
+            res => panic!("unexpected result for {}: {:?}", text, res)
+        };
+
+        assert_eq!(error(r#"{ "body": [ { "time": 0 }, { "time": 100, "pich": 0.1 } ] }"#), "body[1].pich: unknown field");
+        assert_eq!(error(r#"{ "body": [ { "time": 0, "roll": "0.1" } ] }"#), "body[0].roll: expected a number");
+        assert_eq!(error(r#"{ "body": [ { "roll": 0.1 } ] }"#), "body[0].time: missing field");
+        assert_eq!(error(r#"{ "body": { "time": 0 } }"#), "body: expected an array");
+        assert_eq!(error(r#"{ "lop": true }"#), "lop: unknown field");
+        assert_eq!(error(r#"{ "loop": 1, "body": [ { "time": 100 } ] }"#), "loop: expected a boolean");
+        assert_eq!(error(r#"{ "legs": [ { "leg": 3, "keyframes": [ { "time": 0, "offset": { "z": 0.03 } } ] } ] }"#),
+            "legs[0].keyframes[0].offset.x: missing field");
+        assert_eq!(error(r#"{ "legs": [ { "leg": 3, "keyframes": [ { "time": 0, "ofset": { "x": 0.0, "y": 0.0, "z": 0.03 } } ] } ] }"#),
+            "legs[0].keyframes[0].ofset: unknown field");
+        assert_eq!(error(r#"{ "legs": [ { "keyframes": [] } ] }"#), "legs[0].leg: expected a leg id in the [0, 5] range");
+    }
+
+    #[test]
+    fn bundled_choreographies() {
+        let config = HexapodConfig::default();
+        for entry in std::fs::read_dir("choreographies").unwrap() {
+            let path = entry.unwrap().path();
+            let choreography = Choreography::load(&path).unwrap_or_else(|e| panic!("cannot load {}: {}", path.display(), e));
+
+            // Bundled choreographies play without being clamped by the limits of the robot.
+            for k in &choreography.body.keyframes {
+                let pose = &k.value;
+                let (angle, _) = Quaternion::from_rpy(pose.roll, pose.pitch, pose.yaw).to_rotation();
+                assert!(angle <= config.max_body_rotation, "{}: body rotation at {} ms", path.display(), k.time);
+                assert!((0..3).all(|i| pose.offset[i].abs() <= config.max_body_offset[i]),
+                    "{}: body offset at {} ms", path.display(), k.time);
+            }
+            for (leg, track) in choreography.legs.iter().enumerate() {
+                for k in &track.keyframes {
+                    assert!(k.value.len() <= config.max_step_radius, "{}: leg {} offset at {} ms", path.display(), leg, k.time);
+                }
+            }
+        }
+    }
+}
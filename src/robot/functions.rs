@@ -5,7 +5,5 @@ pub fn quad_step_height(x: float, end_height: float, step_height: float) -> floa
     let s = end_height;
     let b = 2.0*h + (4.0*h*h - 4.0*h*s).sqrt();
     let a = s - b;
-    let z = a*x*x + b*x;
-
-    return z;
+    a*x*x + b*x
 }
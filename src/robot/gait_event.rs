@@ -0,0 +1,119 @@
+use std::fmt::Display;
+
+use crate::math::{ FloatType as float, Vector2 };
+
+/// Discrete change of the gait state, raised by [`super::Hexapod`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum GaitEvent {
+    /// The robot started walking with the given step in m and turn angle in rad, after scaling.
+    WalkStarted { step: Vector2, turn_angle: float },
+    /// The legs started returning to their rest positions.
+    StopStarted,
+    /// The legs reached their rest positions.
+    StopFinished,
+    /// The requested step could not be reached and was scaled down by `scale`. Raised when the
+    /// scaling starts, not for every change of the scale.
+    StepScaled { scale: float },
+    /// A leg switched to the gait config that was pending until its next lift.
+    LegConfigApplied { leg: usize },
+    /// A request exceeded the limit of the config named `limit` and was clamped to it.
+    LimitClamped { limit: &'static str, leg: Option<usize> }
+}
+
+impl GaitEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GaitEvent::WalkStarted { .. } => "walk_started",
+            GaitEvent::StopStarted => "stop_started",
+            GaitEvent::StopFinished => "stop_finished",
+            GaitEvent::StepScaled { .. } => "step_scaled",
+            GaitEvent::LegConfigApplied { .. } => "leg_config_applied",
+            GaitEvent::LimitClamped { .. } => "limit_clamped"
+        }
+    }
+
+    /// Returns true for the events that change the mode of the robot.
+    pub fn changes_mode(&self) -> bool {
+        matches!(self, GaitEvent::WalkStarted { .. } | GaitEvent::StopStarted | GaitEvent::StopFinished)
+    }
+}
+
+impl Display for GaitEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GaitEvent::WalkStarted { step, turn_angle } => write!(f, "walk started, step {} m, turn angle {} rad", step, turn_angle),
+            GaitEvent::StopStarted => write!(f, "stop sequence started"),
+            GaitEvent::StopFinished => write!(f, "stop sequence finished"),
+            GaitEvent::StepScaled { scale } => write!(f, "step scaled down by {:.3}", scale),
+            GaitEvent::LegConfigApplied { leg } => write!(f, "gait config applied to leg {}", leg),
+            GaitEvent::LimitClamped { limit, leg: Some(leg) } => write!(f, "leg {} clamped to {}", leg, limit),
+            GaitEvent::LimitClamped { limit, leg: None } => write!(f, "clamped to {}", limit)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::math::{ Vector2, Vector3 };
+    use crate::robot::{ BodyPose, Hexapod, HexapodConfig };
+    use super::GaitEvent;
+
+    fn run(h: &mut Hexapod, time: u32) -> Vec<GaitEvent> {
+        let mut events = h.take_events();
+        for _ in 0..time / 10 {
+            h.update(10);
+            events.extend(h.take_events());
+        }
+        events
+    }
+
+    #[test]
+    fn walk_and_stop() {
+        let mut h = Hexapod::new(HexapodConfig::default());
+        h.set_step(&Vector2::new(0.0, 0.2), 0.0, 1.0);
+        let events = run(&mut h, 1000);
+        assert!(matches!(events[0], GaitEvent::WalkStarted { ref step, .. } if step[1] > 0.0));
+        assert!(!events.iter().any(|event| matches!(event, GaitEvent::StepScaled { .. })));
+
+        // A changed step reaches every leg in turn.
+        h.set_step(&Vector2::new(0.1, 0.2), 0.0, 1.0);
+        let events = run(&mut h, 3000);
+        let mut legs: Vec<usize> = events.iter()
+            .filter_map(|event| match event { GaitEvent::LegConfigApplied { leg } => Some(*leg), _ => None })
+            .collect();
+        legs.sort();
+        assert_eq!(legs, [0, 1, 2, 3, 4, 5]);
+
+        h.set_step(&Vector2::zero(), 0.0, 1.0);
+        let events = run(&mut h, 5000);
+        assert_eq!(events.first(), Some(&GaitEvent::StopStarted));
+        assert_eq!(events.last(), Some(&GaitEvent::StopFinished));
+    }
+
+    #[test]
+    fn step_scaled() {
+        let mut h = Hexapod::new(HexapodConfig::default());
+        h.set_step(&Vector2::new(0.0, 1.0), 1.0, 1.0);
+        let events = h.take_events();
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[1], GaitEvent::StepScaled { scale } if scale < 1.0));
+
+        // Repeating the request while walking keeps the scaling.
+        h.update(10);
+        h.set_step(&Vector2::new(0.0, 1.0), 1.0, 1.0);
+        assert!(h.take_events().is_empty());
+    }
+
+    #[test]
+    fn limit_clamped() {
+        let mut h = Hexapod::new(HexapodConfig::default());
+        h.set_body_pose(&BodyPose { roll: 0.0, pitch: 0.0, yaw: 0.0, offset: Vector3::new(0.0, 0.0, 1.0) }, &Vector3::zero());
+        h.set_leg_offset(2, &Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(h.take_events(), [
+            GaitEvent::LimitClamped { limit: "max_body_offset", leg: None },
+            GaitEvent::LimitClamped { limit: "max_step_radius", leg: Some(2) }
+        ]);
+        assert!(h.take_events().is_empty());
+    }
+}
@@ -1,8 +1,18 @@
-use crate::math::{ transform, FloatType as float, Vector2, Vector3, Matrix3 };
-use super::{ Leg, WalkSequence, StopSequence, WalkSequenceConfig };
+use std::collections::VecDeque;
+use crate::math::{ transform, FloatType as float, FloatModule, Vector2, Vector3, Matrix3, Polygon, Quaternion };
+use super::{ GaitEvent, Leg, WalkSequence, WalkSequencePhase, StopSequence, WalkSequenceConfig };
 
+/// Portion of the lift duration before a leg lifts off when body sway starts shifting the body
+/// towards the upcoming support polygon.
+const BODY_SWAY_LEAD: float = 0.5;
+/// Minimum distance in m between the body and the edges of the support polygon of the remaining
+/// legs for a leg to be lifted for manipulation.
+const MIN_MANIPULATION_STABILITY_MARGIN: float = 0.01;
+/// Number of gait events kept until they are taken. Older events are dropped.
+const MAX_PENDING_EVENTS: usize = 64;
 
-#[derive(Debug)]
+
+#[derive(Debug, Clone)]
 pub struct HexapodConfig {
     pub leg_len1: float,
     pub leg_len2: float,
@@ -15,7 +25,55 @@ pub struct HexapodConfig {
     pub max_step_len: float,
     pub max_turn_angle: float,
     pub max_body_offset: Vector3,
-    pub max_body_rotation: float
+    pub max_body_rotation: float,
+    /// Portion of the gait cycle a leg spends in the air, in (0, 1).
+    pub lift_ratio: float
+}
+
+impl Default for HexapodConfig {
+    /// Dimensions and limits of the prototype.
+    fn default() -> Self {
+        let leg_joint_offset = [
+            Vector3::new(0.01, 0.0, -0.005),
+            Vector3::new(0.01, 0.0, -0.005),
+            Vector3::new(0.01, 0.0, -0.005),
+            Vector3::new(0.01, 0.0, -0.005),
+            Vector3::new(0.01, 0.0, -0.005),
+            Vector3::new(0.01, 0.0, -0.005)
+        ];
+        let legs_origin = [
+            Vector3::new(-0.03,  0.05, 0.02),
+            Vector3::new(-0.04,   0.0, 0.02),
+            Vector3::new(-0.03, -0.05, 0.02),
+            Vector3::new( 0.03,  0.05, 0.02),
+            Vector3::new( 0.04,   0.0, 0.02),
+            Vector3::new( 0.03, -0.05, 0.02)
+        ];
+        let legs_end_pos = [
+            Vector3::new(-0.09,  0.085, 0.00),
+            Vector3::new(-0.11,  0.0,   0.00),
+            Vector3::new(-0.09, -0.085, 0.00),
+            Vector3::new( 0.09,  0.085, 0.00),
+            Vector3::new( 0.11,  0.0,   0.00),
+            Vector3::new( 0.09, -0.085, 0.00)
+        ];
+
+        HexapodConfig {
+            leg_len1: 0.06,
+            leg_len2: 0.06,
+            joint_offset: leg_joint_offset,
+            legs_origin,
+            legs_end_pos,
+            max_speed: 0.16,
+            max_step_radius: 0.04,
+            max_move_radius: 0.12,
+            max_step_len: 0.08,
+            max_turn_angle: FloatModule::consts::FRAC_PI_4,
+            max_body_offset: Vector3::new(0.03, 0.03, 0.03),
+            max_body_rotation: FloatModule::consts::FRAC_PI_8,
+            lift_ratio: 0.3
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -47,6 +105,110 @@ impl BodyPosition {
 }
 
 
+/// Body pose given in absolute units. Angles are in radians and the offset is in metres.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BodyPose {
+    /// Rotation around the Y (forward) axis.
+    pub roll: float,
+    /// Rotation around the X (lateral) axis.
+    pub pitch: float,
+    /// Rotation around the Z (vertical) axis.
+    pub yaw: float,
+    pub offset: Vector3
+}
+
+/// Point the body rotates around.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BodyRotationPivot {
+    /// Centre of the body at the height of the leg origins.
+    BodyCenter,
+    /// Point on the ground directly below the body centre.
+    Ground,
+    /// Midpoint of the line connecting the two front feet.
+    FrontLegs,
+    /// Arbitrary point given in the body frame.
+    Custom(Vector3)
+}
+
+impl BodyRotationPivot {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "body_center" => Some(BodyRotationPivot::BodyCenter),
+            "ground" => Some(BodyRotationPivot::Ground),
+            "front_legs" => Some(BodyRotationPivot::FrontLegs),
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ManipulationState {
+    /// The body is shifting over the support polygon of the remaining legs.
+    Entering,
+    /// The leg is lifted and follows the manipulation target.
+    Active,
+    /// The leg is returning to the ground.
+    Exiting
+}
+
+/// What the robot is currently doing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HexapodMode {
+    Standing,
+    Walking,
+    /// The legs are returning to their rest positions after walking.
+    Stopping,
+    Manipulating
+}
+
+impl HexapodMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HexapodMode::Standing => "standing",
+            HexapodMode::Walking => "walking",
+            HexapodMode::Stopping => "stopping",
+            HexapodMode::Manipulating => "manipulating"
+        }
+    }
+}
+
+/// Parameters of the active walk gait after the limits of the robot were applied.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GaitState {
+    /// Step length and direction in metres.
+    pub step: Vector2,
+    /// Turn angle per step in radians.
+    pub turn_angle: float,
+    pub step_height_weight: float,
+    pub lift_ratio: float,
+    /// Walking speed in m/s.
+    pub speed: float
+}
+
+/// Position of a leg in the walk gait.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LegPhase {
+    pub phase: WalkSequencePhase,
+    /// Progress through the current phase in [0, 1].
+    pub progress: float
+}
+
+#[derive(Debug, Clone)]
+struct Manipulation {
+    leg_id: usize,
+    state: ManipulationState,
+    target: Vector3
+}
+
+/// Result of a body pose request after the limits of the robot were applied.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BodyPoseResult {
+    /// The pose that was actually set as the target.
+    pub applied: BodyPose,
+    pub offset_clamped: bool,
+    pub rotation_clamped: bool
+}
+
 #[derive(Debug)]
 pub struct Hexapod {
     config: HexapodConfig,
@@ -58,7 +220,14 @@ pub struct Hexapod {
     body_pos_target: BodyPosition,
     walk_sequence: Option<WalkSequence>,
     stop_sequence: Option<StopSequence>,
-    speed: float
+    speed: float,
+    body_sway: bool,
+    body_rotation_request: (float, Vector3),
+    body_levelling: (float, float),
+    legs_offset: [Vector3; 6],
+    legs_offset_target: [Vector3; 6],
+    manipulation: Option<Manipulation>,
+    events: VecDeque<GaitEvent>
 }
 
 impl Hexapod {
@@ -84,8 +253,8 @@ impl Hexapod {
         let leg_end_pos_default = config.legs_end_pos.clone();
 
         let mut res = Self{
-            config: config,
-            legs: legs,
+            config,
+            legs,
             legs_origin: leg_origin_default,
             legs_end_pos: leg_end_pos_default,
             legs_seq_pos: [Vector3::zero(), Vector3::zero(), Vector3::zero(), Vector3::zero(), Vector3::zero(), Vector3::zero()],
@@ -93,11 +262,38 @@ impl Hexapod {
             body_pos_target: BodyPosition::new(),
             walk_sequence: None,
             stop_sequence: None,
-            speed: 0.0
+            speed: 0.0,
+            body_sway: false,
+            body_rotation_request: (0.0, Vector3::new(0.0, 0.0, 1.0)),
+            body_levelling: (0.0, 0.0),
+            legs_offset: [Vector3::zero(), Vector3::zero(), Vector3::zero(), Vector3::zero(), Vector3::zero(), Vector3::zero()],
+            legs_offset_target: [Vector3::zero(), Vector3::zero(), Vector3::zero(), Vector3::zero(), Vector3::zero(), Vector3::zero()],
+            manipulation: None,
+            events: VecDeque::new()
         };
         res.update_legs();
 
-        return res
+        res
+    }
+
+    fn raise(&mut self, event: GaitEvent) {
+        if self.events.len() == MAX_PENDING_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    /// Raises a [`GaitEvent::StepScaled`] if the step is scaled down but was not with the
+    /// `previous` scale. The scale keeps changing while scaling as the legs move.
+    fn raise_step_scaled(&mut self, previous: Option<float>) {
+        if let Some(scale) = self.step_scale().filter(|scale| *scale < 1.0 && previous.is_none_or(|prev| prev >= 1.0)) {
+            self.raise(GaitEvent::StepScaled { scale });
+        }
+    }
+
+    /// Returns the gait events raised since the last call, oldest first.
+    pub fn take_events(&mut self) -> Vec<GaitEvent> {
+        std::mem::take(&mut self.events).into()
     }
 
     fn calc_leg_origin(&self, id: usize) -> Vector3 {
@@ -108,6 +304,62 @@ impl Hexapod {
         &self.legs_end_pos[id] - &self.legs_origin[id]
     }
 
+    fn clamp_body_offset(&self, v: &Vector3) -> Vector3 {
+        let max = &self.config.max_body_offset;
+        Vector3::new(
+            v[0].clamp(-max[0], max[0]),
+            v[1].clamp(-max[1], max[1]),
+            v[2].clamp(-max[2], max[2])
+        )
+    }
+
+    fn calc_support_polygon(&self, legs: &[usize]) -> Polygon {
+        let support_points: Vec<Vector2> = legs.iter()
+            .map(|&i| Vector2::from(&self.legs_end_pos[i] + &self.legs_seq_pos[i]))
+            .collect();
+        Polygon::convex_hull(&support_points)
+    }
+
+    /// Calculates the body offset which puts the body above the centroid of the support polygon
+    /// formed by the legs that stay on the ground during the upcoming lift.
+    fn calc_body_sway_offset(&self) -> Vector3 {
+        if let Some(walk_sequence) = &self.walk_sequence {
+            let lead = walk_sequence.lift_ratio() * BODY_SWAY_LEAD;
+            let support_legs: Vec<usize> = (0..6)
+                .filter(|&i| walk_sequence.get_leg_lift_distance(i) > lead)
+                .collect();
+
+            if support_legs.len() < 6 {
+                return Vector3::from(self.calc_support_polygon(&support_legs).centroid());
+            }
+        }
+
+        Vector3::zero()
+    }
+
+    fn update_manipulation(&mut self, offset_target: &Vector3) {
+        if let Some(manipulation) = &mut self.manipulation {
+            let leg_id = manipulation.leg_id;
+            match manipulation.state {
+                ManipulationState::Entering => {
+                    if self.body_pos.offset == *offset_target {
+                        manipulation.state = ManipulationState::Active;
+                        self.legs_offset_target[leg_id] = manipulation.target.clone();
+                    }
+                },
+                ManipulationState::Active => {
+                    self.legs_offset_target[leg_id] = manipulation.target.clone();
+                },
+                ManipulationState::Exiting => {
+                    self.legs_offset_target[leg_id] = Vector3::zero();
+                    if self.legs_offset[leg_id] == Vector3::zero() {
+                        self.manipulation = None;
+                    }
+                }
+            }
+        }
+    }
+
     fn move_vector_towards(current: &mut Vector3, target: &Vector3, distance: float) {
         let target_distance = target - (current as &_);
         if target_distance.len() > 0.0 {
@@ -124,22 +376,303 @@ impl Hexapod {
 
     pub fn set_body_offset(&mut self, v: &Vector3) {
         self.body_pos_target.offset = Vector3::new(
-            v[0] * &self.config.max_body_offset[0],
-            v[1] * &self.config.max_body_offset[1],
-            v[2] * &self.config.max_body_offset[2]
+            v[0] * self.config.max_body_offset[0],
+            v[1] * self.config.max_body_offset[1],
+            v[2] * self.config.max_body_offset[2]
         );
     }
 
-    pub fn set_body_rotation(&mut self, angle: float, axis: &Vector3, origin: &Vector3) {
-        if axis.len() > 0.0 {
+    /// Enables or disables automatic body sway. When enabled, the body is shifted towards the
+    /// centroid of the support polygon before each leg lift.
+    pub fn set_body_sway(&mut self, enabled: bool) {
+        self.body_sway = enabled;
+    }
+
+    /// Combines the requested body rotation with the levelling correction into the body
+    /// rotation target.
+    fn update_body_rotation_target(&mut self) {
+        let (angle, axis) = &self.body_rotation_request;
+        let (roll, pitch) = self.body_levelling;
+
+        if roll == 0.0 && pitch == 0.0 {
+            self.body_pos_target.rotation.angle = *angle;
             self.body_pos_target.rotation.axis = axis.clone();
         }
         else {
-            self.body_pos_target.rotation.axis = Vector3::new(0.0, 0.0, 1.0);
+            let rotation = Quaternion::from_rpy(roll, pitch, 0.0) * Quaternion::from_rotation(*angle, axis.clone());
+            let (angle, axis) = rotation.to_rotation();
+            self.body_pos_target.rotation.angle = angle;
+            self.body_pos_target.rotation.axis = axis;
         }
+    }
+
+    pub fn set_body_rotation(&mut self, angle: float, axis: &Vector3, origin: &Vector3) {
+        let axis = if axis.len() > 0.0 { axis.clone() } else { Vector3::new(0.0, 0.0, 1.0) };
 
-        self.body_pos_target.rotation.angle = angle * self.config.max_body_rotation;
+        self.body_rotation_request = (angle * self.config.max_body_rotation, axis);
         self.body_pos_target.rotation.origin = origin.clone();
+        self.update_body_rotation_target();
+    }
+
+    /// Sets the roll and pitch correction applied on top of the requested body rotation to keep
+    /// the body level. Both angles must be given in radians.
+    pub fn set_body_levelling(&mut self, roll: float, pitch: float) {
+        self.body_levelling = (roll, pitch);
+        self.update_body_rotation_target();
+    }
+
+    /// Sets the body pose target in absolute units. The offset is limited by `max_body_offset`
+    /// per axis and the combined rotation angle is limited by `max_body_rotation`. `origin` is
+    /// the point the body rotates around.
+    pub fn set_body_pose(&mut self, pose: &BodyPose, origin: &Vector3) -> BodyPoseResult {
+        let offset = self.clamp_body_offset(&pose.offset);
+        let offset_clamped = offset != pose.offset;
+
+        let (mut angle, axis) = Quaternion::from_rpy(pose.roll, pose.pitch, pose.yaw).to_rotation();
+        let rotation_clamped = angle > self.config.max_body_rotation;
+        if rotation_clamped {
+            angle = self.config.max_body_rotation;
+        }
+        let (roll, pitch, yaw) = if rotation_clamped {
+            transform::rpy_from_matrix3(&transform::rotate_matrix3(angle, &axis))
+        } else {
+            (pose.roll, pose.pitch, pose.yaw)
+        };
+
+        if offset_clamped {
+            self.raise(GaitEvent::LimitClamped { limit: "max_body_offset", leg: None });
+        }
+        if rotation_clamped {
+            self.raise(GaitEvent::LimitClamped { limit: "max_body_rotation", leg: None });
+        }
+
+        self.body_pos_target.offset = offset.clone();
+        self.body_rotation_request = (angle, axis);
+        self.body_pos_target.rotation.origin = origin.clone();
+        self.update_body_rotation_target();
+
+        BodyPoseResult {
+            applied: BodyPose { roll, pitch, yaw, offset },
+            offset_clamped,
+            rotation_clamped
+        }
+    }
+
+    /// Sets the target offset of a foot relative to its resting position. The offset length is
+    /// limited to `max_step_radius`. Returns the applied offset, or `None` if the robot is
+    /// walking and the request was ignored.
+    pub fn set_leg_offset(&mut self, leg_id: usize, offset: &Vector3) -> Option<Vector3> {
+        if self.walk_sequence.is_some() || self.stop_sequence.is_some() ||
+            self.manipulation.as_ref().is_some_and(|m| m.leg_id == leg_id)
+        {
+            return None;
+        }
+
+        let max_len = self.config.max_step_radius;
+        let offset = if offset.len() > max_len {
+            self.raise(GaitEvent::LimitClamped { limit: "max_step_radius", leg: Some(leg_id) });
+            offset.norm() * max_len
+        } else {
+            offset.clone()
+        };
+        self.legs_offset_target[leg_id] = offset.clone();
+
+        Some(offset)
+    }
+
+    /// Calculates the body offset that shifts the body over the centroid of the support polygon
+    /// of the remaining legs during manipulation, limited by `max_body_offset`.
+    fn calc_manipulation_offset(&self, support_polygon: &Polygon) -> Vector3 {
+        self.clamp_body_offset(&(&self.body_pos_target.offset + Vector3::from(support_polygon.centroid())))
+    }
+
+    /// Takes a leg out of the support polygon so it can be steered freely with
+    /// [`Self::set_manipulation_target`]. The body is shifted over the support polygon of the
+    /// remaining legs before the leg is lifted. Only possible while the robot is standing still
+    /// and if the shifted body keeps a safe distance to the edges of that polygon.
+    pub fn enter_manipulation(&mut self, leg_id: usize) -> bool {
+        if leg_id >= 6 || self.walk_sequence.is_some() || self.stop_sequence.is_some() || self.manipulation.is_some() {
+            return false;
+        }
+
+        let support_legs: Vec<usize> = (0..6).filter(|&i| i != leg_id).collect();
+        let support_polygon = self.calc_support_polygon(&support_legs);
+        let offset = self.calc_manipulation_offset(&support_polygon);
+        if support_polygon.stability_margin(&Vector2::from(&offset)) < MIN_MANIPULATION_STABILITY_MARGIN {
+            return false;
+        }
+
+        self.legs_offset_target[leg_id] = Vector3::zero();
+        self.manipulation = Some(Manipulation {
+            leg_id,
+            state: ManipulationState::Entering,
+            target: Vector3::new(0.0, 0.0, self.config.max_step_radius)
+        });
+
+        true
+    }
+
+    /// Sets the target of the manipulated foot relative to its resting position. The foot is kept
+    /// above the ground and within `max_move_radius` of the leg origin. Returns the applied target
+    /// or `None` if no leg is being manipulated.
+    pub fn set_manipulation_target(&mut self, target: &Vector3) -> Option<Vector3> {
+        let leg_id = self.manipulation.as_ref()?.leg_id;
+
+        let static_pos = &self.config.legs_end_pos[leg_id] - &self.config.legs_origin[leg_id];
+        let mut target = Vector3::new(target[0], target[1], target[2].max(0.0));
+        let pos = &static_pos + &target;
+        if pos.len() > self.config.max_move_radius {
+            target = pos.norm() * self.config.max_move_radius - &static_pos;
+        }
+
+        let manipulation = self.manipulation.as_mut()?;
+        if manipulation.state != ManipulationState::Exiting {
+            manipulation.target = target.clone();
+        }
+
+        Some(target)
+    }
+
+    /// Lowers the manipulated leg and returns the body to its normal position.
+    pub fn exit_manipulation(&mut self) {
+        if let Some(manipulation) = &mut self.manipulation {
+            manipulation.state = ManipulationState::Exiting;
+        }
+    }
+
+    /// Returns the manipulated leg and the state of the manipulation, if any.
+    pub fn manipulation(&self) -> Option<(usize, &ManipulationState)> {
+        self.manipulation.as_ref().map(|m| (m.leg_id, &m.state))
+    }
+
+    /// Returns the distance between the projected centre of mass and the closest edge of the
+    /// support polygon formed by the feet on the ground. Negative values mean that the robot is
+    /// not statically stable.
+    pub fn stability_margin(&self) -> float {
+        let support_legs: Vec<usize> = (0..6).filter(|&i| self.legs_seq_pos[i][2] <= 0.0).collect();
+        self.calc_support_polygon(&support_legs).stability_margin(&Vector2::from(&self.body_pos.offset))
+    }
+
+    /// Calculates the position of a named body rotation pivot.
+    pub fn body_rotation_pivot(&self, pivot: &BodyRotationPivot) -> Vector3 {
+        match pivot {
+            BodyRotationPivot::BodyCenter => {
+                let height = self.config.legs_origin.iter().map(|v| v[2]).sum::<float>() / 6.0;
+                Vector3::new(0.0, 0.0, height)
+            },
+            BodyRotationPivot::Ground => {
+                let height = self.config.legs_end_pos.iter().map(|v| v[2]).sum::<float>() / 6.0;
+                Vector3::new(0.0, 0.0, height)
+            },
+            BodyRotationPivot::FrontLegs => {
+                let mut legs_end_pos: Vec<&Vector3> = self.config.legs_end_pos.iter().collect();
+                legs_end_pos.sort_by(|a, b| b[1].partial_cmp(&a[1]).unwrap());
+                (legs_end_pos[0] + legs_end_pos[1]) / 2.0
+            },
+            BodyRotationPivot::Custom(v) => v.clone()
+        }
+    }
+
+    /// Returns the current body pose in absolute units.
+    pub fn body_pose(&self) -> BodyPose {
+        let (roll, pitch, yaw) = transform::rpy_from_matrix3(&self.body_pos.rotation.matrix);
+        BodyPose { roll, pitch, yaw, offset: self.body_pos.offset.clone() }
+    }
+
+    /// Returns the body pose target, i.e. the requested pose after clamping, including the
+    /// levelling correction.
+    pub fn body_pose_target(&self) -> BodyPose {
+        let rotation = &self.body_pos_target.rotation;
+        let (roll, pitch, yaw) = transform::rpy_from_matrix3(&transform::rotate_matrix3(rotation.angle, &rotation.axis));
+        BodyPose { roll, pitch, yaw, offset: self.body_pos_target.offset.clone() }
+    }
+
+    pub fn body_sway(&self) -> bool {
+        self.body_sway
+    }
+
+    pub fn mode(&self) -> HexapodMode {
+        if self.manipulation.is_some() {
+            HexapodMode::Manipulating
+        }
+        else if self.walk_sequence.is_some() {
+            HexapodMode::Walking
+        }
+        else if self.stop_sequence.is_some() {
+            HexapodMode::Stopping
+        }
+        else {
+            HexapodMode::Standing
+        }
+    }
+
+    /// Returns the parameters of the active walk gait, if the robot is walking.
+    pub fn gait(&self) -> Option<GaitState> {
+        self.walk_sequence.as_ref().map(|walk_sequence| {
+            let config = walk_sequence.config();
+            GaitState {
+                step: config.step.clone(),
+                turn_angle: config.turn_angle,
+                step_height_weight: config.step_height_weight,
+                lift_ratio: config.lift_ratio,
+                speed: self.speed
+            }
+        })
+    }
+
+    pub fn config(&self) -> &HexapodConfig {
+        &self.config
+    }
+
+    /// Replaces the motion limits with the ones of `config`. The leg geometry and the lift ratio
+    /// are fixed when the robot is created and are not changed; the walk sequence derives the
+    /// timing of the legs from the lift ratio when it starts. Returns false without changing
+    /// anything unless the robot is standing still.
+    pub fn set_limits(&mut self, config: &HexapodConfig) -> bool {
+        if self.mode() != HexapodMode::Standing {
+            return false;
+        }
+
+        self.config.max_speed = config.max_speed;
+        self.config.max_step_radius = config.max_step_radius;
+        self.config.max_move_radius = config.max_move_radius;
+        self.config.max_step_len = config.max_step_len;
+        self.config.max_turn_angle = config.max_turn_angle;
+        self.config.max_body_offset = config.max_body_offset.clone();
+        self.config.max_body_rotation = config.max_body_rotation;
+        true
+    }
+
+    /// Returns the gait phase of every leg, if the robot is walking.
+    pub fn leg_phases(&self) -> Option<[LegPhase; 6]> {
+        self.walk_sequence.as_ref().map(|walk_sequence| [0, 1, 2, 3, 4, 5].map(|i| {
+            let (phase, progress) = walk_sequence.get_leg_phase(i);
+            LegPhase { phase, progress }
+        }))
+    }
+
+    /// Returns the factor the requested step was scaled down by to stay within the limits, if
+    /// the robot is walking.
+    pub fn step_scale(&self) -> Option<float> {
+        self.walk_sequence.as_ref().map(|walk_sequence| walk_sequence.step_scale())
+    }
+
+    /// Returns the current walking speed in m/s.
+    pub fn speed(&self) -> float {
+        self.speed
+    }
+
+    pub fn walk_sequence_running(&self) -> bool {
+        self.walk_sequence.is_some()
+    }
+
+    pub fn stop_sequence_running(&self) -> bool {
+        self.stop_sequence.is_some()
+    }
+
+    /// Returns the current rotation of the body relative to the ground.
+    pub fn body_rotation(&self) -> &Matrix3 {
+        &self.body_pos.rotation.matrix
     }
 
     pub fn update(&mut self, time: u32) {
@@ -147,6 +680,7 @@ impl Hexapod {
             self.legs_seq_pos[i] = Vector3::zero();
         }
 
+        let step_scale = self.step_scale();
         if let Some(stop_sequence) = &mut self.stop_sequence {
             stop_sequence.advance(self.speed, time);
             if stop_sequence.has_finished() {
@@ -154,6 +688,7 @@ impl Hexapod {
                     self.legs_end_pos[i] = self.config.legs_end_pos[i].clone();
                 }
                 self.stop_sequence = Option::None;
+                self.raise(GaitEvent::StopFinished);
             }
             else {
                 for i in 0..6 {
@@ -162,10 +697,14 @@ impl Hexapod {
             }
         }
         else if let Some(walk_sequence) = &mut self.walk_sequence {
-            walk_sequence.advance(self.speed, time);
+            let applied = walk_sequence.advance(self.speed, time);
             for i in 0..6 {
                 self.legs_seq_pos[i] += walk_sequence.get_leg_pos(i);
             }
+            for (leg, _) in applied.iter().enumerate().filter(|(_, applied)| **applied) {
+                self.raise(GaitEvent::LegConfigApplied { leg });
+            }
+            self.raise_step_scaled(step_scale);
         }
 
         // TODO: a touch more KISS and DRY and all good stuff would be great
@@ -174,11 +713,29 @@ impl Hexapod {
         let time = (time as float) / 1000.0;
         let move_increment = self.config.max_speed * time;
 
-        if self.body_pos.offset != self.body_pos_target.offset {
-            Self::move_vector_towards(&mut self.body_pos.offset, &self.body_pos_target.offset, move_increment);
+        for i in 0..6 {
+            if self.legs_offset[i] != self.legs_offset_target[i] {
+                Self::move_vector_towards(&mut self.legs_offset[i], &self.legs_offset_target[i], move_increment);
+            }
+            self.legs_seq_pos[i] += &self.legs_offset[i];
+        }
+
+        let offset_target = if let Some(manipulation) = &self.manipulation {
+            let support_legs: Vec<usize> = (0..6).filter(|&i| i != manipulation.leg_id).collect();
+            self.calc_manipulation_offset(&self.calc_support_polygon(&support_legs))
+        } else if self.body_sway {
+            self.clamp_body_offset(&(&self.body_pos_target.offset + self.calc_body_sway_offset()))
+        } else {
+            self.body_pos_target.offset.clone()
+        };
+
+        if self.body_pos.offset != offset_target {
+            Self::move_vector_towards(&mut self.body_pos.offset, &offset_target, move_increment);
             static_position_updated = true;
         }
 
+        self.update_manipulation(&offset_target);
+
         let current_rotation = &mut self.body_pos.rotation;
         let target_rotation = &self.body_pos_target.rotation;
         if current_rotation.origin != target_rotation.origin {
@@ -241,10 +798,14 @@ impl Hexapod {
     }
 
     pub fn set_speed(&mut self, speed: float) {
-        self.speed = speed.min(1.0).max(0.0) * self.config.max_speed;
+        self.speed = speed.clamp(0.0, 1.0) * self.config.max_speed;
     }
 
     pub fn set_step(&mut self, step: &Vector2, turn: float, step_height_weight: float) {
+        if self.manipulation.is_some() {
+            return;
+        }
+
         // TODO: handle out-of-range values.
         let step = if step.len() > 1.0 { step.norm() } else { step.clone() };
         let step_len = step.len();
@@ -252,11 +813,7 @@ impl Hexapod {
         let turn = turn.clamp(-1.0, 1.0);
         let turn_angle = if turn != 0.0 { turn / turn.abs() * (0.3 + 0.7 * turn.abs()) * self.config.max_turn_angle } else { 0.0 };
 
-        let mut turn_origin = [Vector2::zero(), Vector2::zero(), Vector2::zero(), Vector2::zero(), Vector2::zero(), Vector2::zero()];
-
-        for i in 0..6 {
-            turn_origin[i] = -Vector2::from(&self.config.legs_end_pos[i]);
-        }
+        let turn_origin = [0, 1, 2, 3, 4, 5].map(|i| -Vector2::from(&self.config.legs_end_pos[i]));
 
         let step_height_weight = step_height_weight.clamp(0.0, 2.0) / 2.0;
 
@@ -265,7 +822,11 @@ impl Hexapod {
             self.calc_leg_static_pos(3), self.calc_leg_static_pos(4), self.calc_leg_static_pos(5)
         ];
 
+        let step_scale = self.step_scale();
         if step_len > 0.0 || turn_angle != 0.0 {
+            for leg_offset_target in self.legs_offset_target.iter_mut() {
+                *leg_offset_target = Vector3::zero();
+            }
 
             let config = WalkSequenceConfig{
                 leg_static_pos,
@@ -275,7 +836,7 @@ impl Hexapod {
                 step_height_weight,
                 max_step_radius: self.config.max_step_radius,
                 max_move_radius: self.config.max_move_radius,
-                lift_ratio: 0.3
+                lift_ratio: self.config.lift_ratio
             };
 
             if let Some(walk_sequence) = &mut self.walk_sequence {
@@ -283,23 +844,25 @@ impl Hexapod {
             }
             else {
                 let seq = WalkSequence::new(&config);
+                let active = seq.config();
+                let event = GaitEvent::WalkStarted { step: active.step.clone(), turn_angle: active.turn_angle };
                 self.walk_sequence = Some(seq);
+                self.raise(event);
             }
+            self.raise_step_scaled(step_scale);
 
             self.set_speed(Vector2::new(step_len, turn).len().min(1.0));
         }
         else if let Some(walk_sequence) = &self.walk_sequence {
             let mut delays = [false; 6];
-            for i in 0..6 {
+            for (i, delay) in delays.iter_mut().enumerate() {
                 let seq_pos = walk_sequence.get_leg_pos(i);
                 self.legs_end_pos[i] += &seq_pos;
 
                 // TODO: maybe there is a better way to decide if the leg is in the air or not.
                 // If the leg is currently touching the ground, delay the move until the other
                 // legs are finished moving.
-                if seq_pos[2] == 0.0 {
-                    delays [i] = true;
-                }
+                *delay = seq_pos[2] == 0.0;
             }
             let positions = [
                 &self.config.legs_end_pos[0] - &self.legs_end_pos[0],
@@ -312,6 +875,7 @@ impl Hexapod {
 
             self.stop_sequence = Option::Some(StopSequence::new(positions, step_height_weight, delays));
             self.walk_sequence = Option::None;
+            self.raise(GaitEvent::StopStarted);
 
             self.set_speed(0.5);
         }
@@ -325,3 +889,225 @@ impl Hexapod {
         &self.legs_origin[leg_id]
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use crate::{ math::{ transform, FloatType as float, AssertFloatEq, FloatEq, Quaternion, Vector2, Vector3 }, assert_float_eq, float_eq };
+    use super::{ BodyPose, BodyRotationPivot, Hexapod, HexapodConfig, HexapodMode, ManipulationState };
+
+    const TOL: float = 1e-4;
+
+    fn walking(body_sway: bool) -> Hexapod {
+        let mut h = Hexapod::new(HexapodConfig::default());
+        h.set_body_sway(body_sway);
+        h.set_step(&Vector2::new(0.0, 0.5), 0.0, 1.0);
+        h
+    }
+
+    #[test]
+    fn body_sway() {
+        let mut h = walking(true);
+        let mut swayed = false;
+        for _ in 0..300 {
+            let offset = h.body_pos.offset.clone();
+            h.update(10);
+
+            // The body moves towards the centroid of the support polygon, away from the legs
+            // that are about to lift.
+            let target = h.calc_body_sway_offset();
+            if target != Vector3::zero() {
+                swayed = true;
+                let lead = h.walk_sequence.as_ref().unwrap().lift_ratio() * super::BODY_SWAY_LEAD;
+                let lifting: Vec<usize> = (0..6)
+                    .filter(|&i| h.walk_sequence.as_ref().unwrap().get_leg_lift_distance(i) <= lead)
+                    .collect();
+                let lifting_pos = lifting.iter()
+                    .map(|&i| Vector2::from(&h.legs_end_pos[i] + &h.legs_seq_pos[i]))
+                    .fold(Vector2::zero(), |sum, v| sum + v);
+                assert!(Vector2::from(&target).dot(&lifting_pos) < 0.0);
+            }
+            let distance = h.body_pos.offset.dist(&target);
+            assert!(distance == 0.0 || distance < offset.dist(&target));
+        }
+        assert!(swayed);
+    }
+
+    #[test]
+    fn body_sway_disabled() {
+        let mut h = walking(false);
+        for _ in 0..300 {
+            h.update(10);
+            assert_eq!(h.body_pos.offset, Vector3::zero());
+        }
+        assert!(!h.body_sway());
+    }
+
+    #[test]
+    fn body_sway_clamped() {
+        let max = 0.004;
+        let mut h = Hexapod::new(HexapodConfig { max_body_offset: Vector3::new(max, max, max), ..HexapodConfig::default() });
+        h.set_body_sway(true);
+        h.set_body_offset(&Vector3::new(1.0, -1.0, 0.0));
+        h.set_step(&Vector2::new(0.0, 0.5), 0.0, 1.0);
+        for _ in 0..300 {
+            h.update(10);
+            let offset = &h.body_pos.offset;
+            assert!((0..3).all(|i| offset[i].abs() <= max + 1e-6), "{}", offset);
+        }
+    }
+
+    #[test]
+    fn body_pose_in_range() {
+        let mut h = Hexapod::new(HexapodConfig::default());
+        let pose = BodyPose { roll: 0.1, pitch: -0.2, yaw: 0.05, offset: Vector3::new(0.01, -0.02, 0.03) };
+        let result = h.set_body_pose(&pose, &Vector3::zero());
+        assert!(!result.offset_clamped);
+        assert!(!result.rotation_clamped);
+        assert_eq!(result.applied, pose);
+
+        let target = h.body_pose_target();
+        assert_float_eq!(target.roll, 0.1, TOL);
+        assert_float_eq!(target.pitch, -0.2, TOL);
+        assert_float_eq!(target.yaw, 0.05, TOL);
+    }
+
+    #[test]
+    fn body_pose_clamped() {
+        let mut h = Hexapod::new(HexapodConfig::default());
+        let max_rotation = h.config().max_body_rotation;
+
+        let result = h.set_body_pose(&BodyPose { roll: 0.0, pitch: 0.0, yaw: 0.0, offset: Vector3::new(0.1, -0.01, -0.2) }, &Vector3::zero());
+        assert!(result.offset_clamped);
+        assert!(!result.rotation_clamped);
+        assert_eq!(result.applied.offset, Vector3::new(0.03, -0.01, -0.03));
+
+        // The rotation is scaled down around the same axis.
+        let result = h.set_body_pose(&BodyPose { roll: 0.0, pitch: 1.0, yaw: 0.0, offset: Vector3::zero() }, &Vector3::zero());
+        assert!(!result.offset_clamped);
+        assert!(result.rotation_clamped);
+        assert_float_eq!(result.applied.roll, 0.0, TOL, abs);
+        assert_float_eq!(result.applied.pitch, max_rotation, TOL);
+        assert_float_eq!(result.applied.yaw, 0.0, TOL, abs);
+
+        let result = h.set_body_pose(&BodyPose { roll: 0.6, pitch: 0.0, yaw: -0.6, offset: Vector3::zero() }, &Vector3::zero());
+        assert!(result.rotation_clamped);
+        let applied = &result.applied;
+        let (angle, _) = Quaternion::from_rpy(applied.roll, applied.pitch, applied.yaw).to_rotation();
+        assert_float_eq!(angle, max_rotation, TOL);
+        assert!(applied.roll > 0.0 && applied.yaw < 0.0);
+        assert_eq!(h.body_pose_target().roll, applied.roll);
+    }
+
+    #[test]
+    fn body_rotation_pivot() {
+        let pivots = [
+            BodyRotationPivot::BodyCenter,
+            BodyRotationPivot::Ground,
+            BodyRotationPivot::FrontLegs,
+            BodyRotationPivot::Custom(Vector3::new(0.02, -0.03, 0.01))
+        ];
+        for pivot in pivots {
+            let mut h = Hexapod::new(HexapodConfig::default());
+            let origin = h.body_rotation_pivot(&pivot);
+            h.set_body_rotation(1.0, &Vector3::new(1.0, 0.5, 0.2), &origin);
+            for _ in 0..200 {
+                h.update(10);
+            }
+
+            // Derive the rigid body transform from the rotation and the leg origins and check
+            // that it maps the pivot onto itself.
+            let rotation = h.body_rotation().clone();
+            assert!(transform::rpy_from_matrix3(&rotation).0.abs() > 0.1);
+            let translation = h.leg_origin(0) - &rotation * &h.config().legs_origin[0];
+            let pivot_pos = &rotation * &origin + &translation;
+            assert!(pivot_pos.dist(&origin) < 1e-5, "{:?}: {} moved to {}", pivot, origin, pivot_pos);
+        }
+    }
+
+    fn run(h: &mut Hexapod, time: u32) {
+        for _ in 0..time / 10 {
+            h.update(10);
+        }
+    }
+
+    #[test]
+    fn manipulation_refused() {
+        let mut h = walking(false);
+        assert!(!h.enter_manipulation(0));
+        assert_eq!(h.mode(), HexapodMode::Walking);
+
+        let mut h = Hexapod::new(HexapodConfig::default());
+        assert!(!h.enter_manipulation(6));
+        assert!(h.set_manipulation_target(&Vector3::zero()).is_none());
+        assert!(h.enter_manipulation(0));
+        assert!(!h.enter_manipulation(1));
+
+        // The body cannot be shifted over the remaining legs when the requested offset
+        // moves it towards the lifted one.
+        let max = 0.1;
+        let mut h = Hexapod::new(HexapodConfig { max_body_offset: Vector3::new(max, max, max), ..HexapodConfig::default() });
+        h.set_body_offset(&Vector3::new(-0.5, 0.5, 0.0));
+        assert!(!h.enter_manipulation(0));
+        assert!(h.enter_manipulation(2));
+    }
+
+    #[test]
+    fn manipulation_shifts_body_first() {
+        let mut h = Hexapod::new(HexapodConfig::default());
+        assert!(h.enter_manipulation(0));
+        assert_eq!(h.mode(), HexapodMode::Manipulating);
+
+        // The foot stays on the ground until the body is above the remaining legs.
+        let mut time = 0;
+        while h.manipulation() == Some((0, &ManipulationState::Entering)) {
+            h.update(10);
+            assert_eq!(h.legs_offset[0], Vector3::zero());
+            time += 10;
+            assert!(time < 1000);
+        }
+        assert!(h.body_pos.offset[0] > 0.0 && h.body_pos.offset[1] < 0.0);
+        assert_eq!(h.manipulation(), Some((0, &ManipulationState::Active)));
+
+        run(&mut h, 500);
+        assert!(h.legs_offset[0][2] > 0.0);
+        assert!(h.stability_margin() >= super::MIN_MANIPULATION_STABILITY_MARGIN);
+    }
+
+    #[test]
+    fn manipulation_target_clamped() {
+        let mut h = Hexapod::new(HexapodConfig::default());
+        assert!(h.enter_manipulation(3));
+        let static_pos = &h.config().legs_end_pos[3] - &h.config().legs_origin[3];
+
+        let target = h.set_manipulation_target(&Vector3::new(0.01, 0.02, -0.03)).unwrap();
+        assert_eq!(target, Vector3::new(0.01, 0.02, 0.0));
+
+        let target = h.set_manipulation_target(&Vector3::new(0.2, 0.2, 0.2)).unwrap();
+        assert_float_eq!((&static_pos + &target).len(), h.config().max_move_radius, TOL);
+
+        run(&mut h, 3000);
+        assert!(h.legs_offset[3].dist(&target) < 1e-6);
+    }
+
+    #[test]
+    fn manipulation_exit() {
+        let mut h = Hexapod::new(HexapodConfig::default());
+        assert!(h.enter_manipulation(2));
+        run(&mut h, 1000);
+        h.set_manipulation_target(&Vector3::new(0.0, 0.02, 0.04));
+        run(&mut h, 1000);
+        assert!(h.legs_offset[2][2] > 0.0);
+
+        h.exit_manipulation();
+        assert_eq!(h.manipulation(), Some((2, &ManipulationState::Exiting)));
+        run(&mut h, 2000);
+        assert!(h.manipulation().is_none());
+        assert_eq!(h.mode(), HexapodMode::Standing);
+        assert_eq!(h.legs_seq_pos[2], Vector3::zero());
+
+        // The body returns to the requested offset.
+        run(&mut h, 1000);
+        assert_eq!(h.body_pos.offset, Vector3::zero());
+    }
+}
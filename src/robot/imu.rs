@@ -0,0 +1,58 @@
+use crate::math::{ transform, FloatType as float, Matrix3 };
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImuReading {
+    /// Rotation around the Y (forward) axis in radians.
+    pub roll: float,
+    /// Rotation around the X (lateral) axis in radians.
+    pub pitch: float,
+    /// Rotation speed around the Z (vertical) axis in rad/s. Not used by the levelling, which
+    /// only corrects roll and pitch.
+    pub yaw_rate: float
+}
+
+pub trait Imu {
+    fn read(&mut self) -> ImuReading;
+
+    /// Tells the sensor the rotation of the body relative to the ground after every update.
+    /// Real sensors measure it themselves and ignore it.
+    fn set_body_rotation(&mut self, _rotation: &Matrix3) {}
+}
+
+/// IMU that derives its readings from the slope of the ground and the body rotation of the
+/// robot. Used for simulation and tests where no real sensor is available.
+#[derive(Debug, Clone)]
+pub struct SimulatedImu {
+    ground_rotation: Matrix3,
+    body_rotation: Matrix3,
+    yaw_rate: float
+}
+
+impl SimulatedImu {
+    pub fn new(ground_roll: float, ground_pitch: float) -> Self {
+        SimulatedImu {
+            ground_rotation: transform::rotate_matrix3_rpy(ground_roll, ground_pitch, 0.0),
+            body_rotation: Matrix3::identity(),
+            yaw_rate: 0.0
+        }
+    }
+
+    pub fn set_ground_slope(&mut self, roll: float, pitch: float) {
+        self.ground_rotation = transform::rotate_matrix3_rpy(roll, pitch, 0.0);
+    }
+
+    pub fn set_yaw_rate(&mut self, yaw_rate: float) {
+        self.yaw_rate = yaw_rate;
+    }
+}
+
+impl Imu for SimulatedImu {
+    fn read(&mut self) -> ImuReading {
+        let (roll, pitch, _) = transform::rpy_from_matrix3(&(&self.ground_rotation * &self.body_rotation));
+        ImuReading { roll, pitch, yaw_rate: self.yaw_rate }
+    }
+
+    fn set_body_rotation(&mut self, rotation: &Matrix3) {
+        self.body_rotation = rotation.clone();
+    }
+}
@@ -11,7 +11,7 @@ pub struct Leg {
 
 impl Leg {
     pub fn new(len_a: float, len_b: float, joint_offset: Vector3) -> Self {
-        Leg { len_a: len_a, len_b: len_b, joint_offset: joint_offset, position: Vector3::zero(),
+        Leg { len_a, len_b, joint_offset, position: Vector3::zero(),
             plane_normal: Vector3::zero() }
     }
 
@@ -57,10 +57,22 @@ impl Leg {
         rm_plane * v2
     }
 
+    /// Calculates the coxa, femur and tibia joint angles in radians.
+    ///
+    /// The coxa angle is the rotation of the leg around the Z axis, measured from the X axis. The
+    /// femur angle is the elevation of the femur above the plane given by the plane normal. The
+    /// tibia angle is the angle between the femur and the tibia, 0 when the leg is stretched.
     pub fn calc_joint_angles(&self) -> (float, float, float) {
-        // TODO: Calculate intersection pos when end position is set. Use intersection pos to calculate angles.
+        // TODO: Calculate intersection pos when end position is set.
+        let knee = self.intersection_pos();
+        let tibia = &self.position - self.joint_offset() - &knee;
+        let normal = if self.plane_normal.len() > 0.0 { self.plane_normal.norm() } else { Vector3::new(0.0, 0.0, 1.0) };
 
-        unimplemented!();
+        let coxa_angle = self.z_angle();
+        let femur_angle = (knee.dot(&normal) / self.len_a).clamp(-1.0, 1.0).asin();
+        let tibia_angle = knee.angle(&tibia);
+
+        (coxa_angle, femur_angle, tibia_angle)
     }
 
     pub fn set_position(&mut self, position: &Vector3, plane_normal: &Vector3) {
@@ -77,4 +89,39 @@ impl Leg {
         let rm = transform::rotate_matrix3(z_angle, &Vector3::new(0.0, 0.0, 1.0));
         &rm * &self.joint_offset
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::math::{ FloatType as float, FloatModule, FloatEq, Vector3 };
+    use crate::{ assert_float_eq, float_eq };
+    use super::Leg;
+
+    const TOL: float = 1e-4;
+
+    #[test]
+    fn joint_angles() {
+        let mut leg = Leg::new(0.06, 0.06, Vector3::new(0.01, 0.0, -0.005));
+        let up = Vector3::new(0.0, 0.0, 1.0);
+
+        // Horizontal femur with a vertical tibia.
+        leg.set_position(&Vector3::new(0.07, 0.0, -0.065), &up);
+        let (coxa, femur, tibia) = leg.calc_joint_angles();
+        assert_float_eq!(coxa, 0.0, TOL, abs);
+        assert_float_eq!(femur, 0.0, TOL, abs);
+        assert_float_eq!(tibia, FloatModule::consts::FRAC_PI_2, TOL, abs);
+
+        // The same pose rotated by 90 degrees around the Z axis.
+        leg.set_position(&Vector3::new(0.0, 0.07, -0.065), &up);
+        let (coxa, femur, tibia) = leg.calc_joint_angles();
+        assert_float_eq!(coxa, FloatModule::consts::FRAC_PI_2, TOL, abs);
+        assert_float_eq!(femur, 0.0, TOL, abs);
+        assert_float_eq!(tibia, FloatModule::consts::FRAC_PI_2, TOL, abs);
+
+        // Raised femur and tibia forming an equilateral triangle with the foot at joint height.
+        leg.set_position(&Vector3::new(0.07, 0.0, -0.005), &up);
+        let (_, femur, tibia) = leg.calc_joint_angles();
+        assert_float_eq!(femur, FloatModule::consts::FRAC_PI_3, TOL, abs);
+        assert_float_eq!(tibia, 2.0 * FloatModule::consts::FRAC_PI_3, TOL, abs);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,140 @@
+use crate::math::{ FloatType as float };
+use super::ImuReading;
+
+#[derive(Debug, Clone)]
+pub struct LevellingConfig {
+    pub kp: float,
+    pub ki: float,
+    /// Maximum roll and pitch correction in radians.
+    pub max_correction: float
+}
+
+/// PI controller that calculates the roll and pitch correction needed to keep the body
+/// horizontal based on IMU readings.
+#[derive(Debug, Clone)]
+pub struct LevellingController {
+    config: LevellingConfig,
+    enabled: bool,
+    roll_integral: float,
+    pitch_integral: float,
+    correction: (float, float)
+}
+
+impl LevellingController {
+    pub fn new(config: LevellingConfig) -> Self {
+        LevellingController {
+            config,
+            enabled: false,
+            roll_integral: 0.0,
+            pitch_integral: 0.0,
+            correction: (0.0, 0.0)
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if self.enabled != enabled {
+            self.reset();
+        }
+        self.enabled = enabled;
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_gains(&mut self, kp: float, ki: float) {
+        self.config.kp = kp;
+        self.config.ki = ki;
+    }
+
+    pub fn reset(&mut self) {
+        self.roll_integral = 0.0;
+        self.pitch_integral = 0.0;
+        self.correction = (0.0, 0.0);
+    }
+
+    /// Returns the current roll and pitch correction in radians.
+    pub fn correction(&self) -> (float, float) {
+        self.correction
+    }
+
+    fn update_axis(config: &LevellingConfig, integral: &mut float, measured: float, time: float) -> float {
+        let error = -measured;
+        let max = config.max_correction;
+
+        // Clamp the integral term to avoid wind-up when the correction saturates.
+        if config.ki > 0.0 {
+            *integral = (*integral + error * time).clamp(-max / config.ki, max / config.ki);
+        }
+
+        (config.kp * error + config.ki * *integral).clamp(-max, max)
+    }
+
+    /// Updates the controller with a new IMU reading and returns the roll and pitch correction.
+    ///
+    /// `time` must be given in ms.
+    pub fn update(&mut self, reading: &ImuReading, time: u32) -> (float, float) {
+        if self.enabled {
+            let time = (time as float) / 1000.0;
+            let roll = Self::update_axis(&self.config, &mut self.roll_integral, reading.roll, time);
+            let pitch = Self::update_axis(&self.config, &mut self.pitch_integral, reading.pitch, time);
+            self.correction = (roll, pitch);
+        }
+        self.correction
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::{ math::{ transform, FloatType as float, FloatEq }, assert_float_eq, float_eq };
+    use super::super::{ Imu, SimulatedImu };
+    use super::{ LevellingConfig, LevellingController };
+
+    const TOL: float = 1e-3;
+
+    fn controller() -> LevellingController {
+        let mut controller = LevellingController::new(LevellingConfig { kp: 0.5, ki: 4.0, max_correction: 0.3 });
+        controller.set_enabled(true);
+        controller
+    }
+
+    #[test]
+    fn levels_body_on_slope() {
+        let mut imu = SimulatedImu::new(0.1, -0.05);
+        let mut controller = controller();
+
+        for _ in 0..500 {
+            let (roll, pitch) = controller.update(&imu.read(), 10);
+            imu.set_body_rotation(&transform::rotate_matrix3_rpy(roll, pitch, 0.0));
+        }
+
+        let reading = imu.read();
+        assert_float_eq!(reading.roll, 0.0, TOL, abs);
+        assert_float_eq!(reading.pitch, 0.0, TOL, abs);
+    }
+
+    #[test]
+    fn correction_is_limited() {
+        let mut imu = SimulatedImu::new(0.5, 0.0);
+        let mut controller = controller();
+
+        for _ in 0..500 {
+            let (roll, pitch) = controller.update(&imu.read(), 10);
+            imu.set_body_rotation(&transform::rotate_matrix3_rpy(roll, pitch, 0.0));
+        }
+
+        let (roll, _) = controller.correction();
+        assert_float_eq!(roll, -0.3, TOL, abs);
+    }
+
+    #[test]
+    fn disabled_controller_does_not_correct() {
+        let mut imu = SimulatedImu::new(0.1, 0.1);
+        let mut controller = controller();
+        controller.update(&imu.read(), 10);
+        controller.set_enabled(false);
+
+        assert_eq!(controller.update(&imu.read(), 10), (0.0, 0.0));
+    }
+}
@@ -1,13 +1,23 @@
+mod choreography;
+mod gait_event;
 mod hexapod;
+mod imu;
+mod levelling;
 mod leg;
+mod odometry;
 mod stop_sequence;
 mod stop_sequence_fn;
 mod walk_sequence;
 mod walk_sequence_fn;
 mod functions;
 
+pub use choreography::*;
+pub use gait_event::*;
 pub use hexapod::*;
+pub use imu::*;
+pub use levelling::*;
 pub use leg::*;
+pub use odometry::*;
 pub use stop_sequence::*;
 pub use stop_sequence_fn::*;
 pub use walk_sequence::*;
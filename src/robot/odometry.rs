@@ -0,0 +1,126 @@
+use crate::math::{ transform, FloatType as float, Vector2 };
+use super::{ Hexapod, WalkSequencePhase };
+
+/// Estimates the motion of the robot over the ground from the feet pushing against it.
+///
+/// Feet on the ground do not move relative to the ground, so the motion of the pushing feet in
+/// the body frame between two updates is the inverse of the motion of the body. Only the
+/// horizontal plane is tracked, slipping feet are not detected.
+#[derive(Debug, Clone)]
+pub struct Odometry {
+    /// Foot positions in the body frame of the legs that pushed in the last update.
+    stance: [Option<Vector2>; 6],
+    /// Position in m in the frame the robot started in.
+    position: Vector2,
+    /// Heading in rad, counterclockwise relative to the start.
+    heading: float,
+    /// Distance walked in m.
+    distance: float
+}
+
+impl Default for Odometry {
+    fn default() -> Self {
+        Odometry { stance: [0; 6].map(|_| None), position: Vector2::zero(), heading: 0.0, distance: 0.0 }
+    }
+}
+
+impl Odometry {
+    /// Updates the estimate after the robot was updated.
+    pub fn update(&mut self, h: &Hexapod) {
+        let phases = h.leg_phases();
+        let stance = [0, 1, 2, 3, 4, 5].map(|i| match &phases {
+            Some(phases) if phases[i].phase == WalkSequencePhase::Push => Some(Vector2::from(&(h.leg_origin(i) + h.leg(i).position()))),
+            _ => None
+        });
+
+        let pairs: Vec<(&Vector2, &Vector2)> = self.stance.iter().zip(stance.iter())
+            .filter_map(|(prev, now)| Some((prev.as_ref()?, now.as_ref()?)))
+            .collect();
+        if pairs.len() >= 2 {
+            let n = pairs.len() as float;
+            let center_prev = &pairs.iter().fold(Vector2::zero(), |sum, (prev, _)| &sum + *prev) / n;
+            let center_now = &pairs.iter().fold(Vector2::zero(), |sum, (_, now)| &sum + *now) / n;
+
+            // Best fitting rotation of the feet around their center.
+            let (mut sin, mut cos) = (0.0, 0.0);
+            for (prev, now) in &pairs {
+                let a = *prev - &center_prev;
+                let b = *now - &center_now;
+                sin += a[0] * b[1] - a[1] * b[0];
+                cos += a.dot(&b);
+            }
+            let rotation = -sin.atan2(cos);
+            let translation = &center_prev - &(&transform::rotate_matrix2(rotation) * &center_now);
+
+            self.position += &transform::rotate_matrix2(self.heading) * &translation;
+            self.heading += rotation;
+            self.distance += translation.len();
+        }
+
+        self.stance = stance;
+    }
+
+    pub fn position(&self) -> &Vector2 {
+        &self.position
+    }
+
+    pub fn heading(&self) -> float {
+        self.heading
+    }
+
+    pub fn distance(&self) -> float {
+        self.distance
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::math::{ FloatType as float, Vector2 };
+    use crate::robot::{ Hexapod, HexapodConfig };
+    use super::Odometry;
+
+    /// Walks for the given time in ms and returns the odometry and the speed.
+    fn walk(step: &Vector2, turn: float, time: u32) -> (Odometry, float) {
+        let mut h = Hexapod::new(HexapodConfig::default());
+        let mut odometry = Odometry::default();
+        h.set_step(step, turn, 1.0);
+        for _ in 0..time / 10 {
+            h.update(10);
+            odometry.update(&h);
+        }
+        (odometry, h.speed())
+    }
+
+    #[test]
+    fn straight() {
+        let (odometry, speed) = walk(&Vector2::new(0.0, 1.0), 0.0, 5000);
+        let expected = speed * 5.0;
+
+        assert!((odometry.distance() - expected).abs() < 0.1 * expected, "{} {}", odometry.distance(), expected);
+        assert!((odometry.position()[1] - expected).abs() < 0.1 * expected);
+        assert!(odometry.position()[0].abs() < 0.01);
+        assert!(odometry.heading().abs() < 0.01);
+    }
+
+    #[test]
+    fn turning() {
+        let (odometry, _) = walk(&Vector2::zero(), 1.0, 5000);
+        assert!(odometry.heading() > 0.5, "{}", odometry.heading());
+        assert!(odometry.position().len() < 0.02);
+
+        let (odometry, _) = walk(&Vector2::zero(), -1.0, 5000);
+        assert!(odometry.heading() < -0.5);
+    }
+
+    #[test]
+    fn standing() {
+        let mut h = Hexapod::new(HexapodConfig::default());
+        let mut odometry = Odometry::default();
+        for _ in 0..100 {
+            h.update(10);
+            odometry.update(&h);
+        }
+        assert_eq!(odometry.distance(), 0.0);
+    }
+}
@@ -65,6 +65,6 @@ impl StopSequence {
                 return false
             }
         }
-        return true
+        true
     }
 }
@@ -20,7 +20,9 @@ pub struct WalkSequence {
     x: float,
     sequence_fns: [WalkSequenceFn; 6],
     config_active: Option<WalkSequenceConfig>, // TODO: Revisit this, Option may not be necessary.
-    config_update: Option<WalkSequenceConfig>
+    config_update: Option<WalkSequenceConfig>,
+    /// Factor the requested step and turn angle were scaled down by to stay within the limits.
+    step_scale: float
 }
 
 impl WalkSequence {
@@ -38,12 +40,13 @@ impl WalkSequence {
             x: 0.0,
             sequence_fns: [seq_fn1, seq_fn2, seq_fn3, seq_fn4, seq_fn5, seq_fn6],
             config_active: None,
-            config_update: None
+            config_update: None,
+            step_scale: 1.0
         };
 
         walk_sequence.update(config);
 
-        return walk_sequence;
+        walk_sequence
     }
 
     pub fn update(&mut self, config: &WalkSequenceConfig) {
@@ -83,10 +86,12 @@ impl WalkSequence {
             config_active.turn_angle = turn_angle_scaled;
             self.config_active = Some(config_active);
             self.config_update = Some(config.clone());
+            self.step_scale = min_scale;
         }
         else {
             self.config_active = Some(config.clone());
             self.config_update = None;
+            self.step_scale = 1.0;
         }
     }
 
@@ -103,8 +108,9 @@ impl WalkSequence {
 
     /// Advances the sequence based on the provided parameters.
     ///
-    /// `speed` must be given in m/s and `time` must be given in ms.
-    pub fn advance(&mut self, speed: float, time: u32) {
+    /// `speed` must be given in m/s and `time` must be given in ms. Returns for every leg
+    /// whether it switched to a pending config update.
+    pub fn advance(&mut self, speed: float, time: u32) -> [bool; 6] {
         let time = (time as float) / 1000.0;
         let distance = speed * time;
 
@@ -123,17 +129,46 @@ impl WalkSequence {
             self.x -=  1.0;
         }
 
-        for seq_fn in self.sequence_fns.iter_mut() {
-            seq_fn.advance(self.x);
+        let mut applied = [false; 6];
+        for (seq_fn, applied) in self.sequence_fns.iter_mut().zip(applied.iter_mut()) {
+            *applied = seq_fn.advance(self.x);
         }
 
         if let Some(config_update) = self.config_update.take() {
             self.update(&config_update);
         }
+        applied
     }
 
     /// TODO
     pub fn get_leg_pos(&self, leg_id: usize) -> Vector3 {
         self.sequence_fns[leg_id].get()
     }
+
+    /// Returns the sequence distance left until the given leg is lifted next, or 0 if the leg
+    /// is currently in the air.
+    pub fn get_leg_lift_distance(&self, leg_id: usize) -> float {
+        self.sequence_fns[leg_id].lift_distance()
+    }
+
+    /// Returns the phase of the given leg and its progress through it in [0, 1].
+    pub fn get_leg_phase(&self, leg_id: usize) -> (WalkSequencePhase, float) {
+        let seq_fn = &self.sequence_fns[leg_id];
+        (seq_fn.phase(), seq_fn.phase_progress())
+    }
+
+    /// Returns the factor the requested step was scaled down by, 1 if it is within the limits.
+    pub fn step_scale(&self) -> float {
+        self.step_scale
+    }
+
+    /// Returns the active configuration. The step and the turn angle are already scaled down if
+    /// the requested ones could not be reached.
+    pub fn config(&self) -> &WalkSequenceConfig {
+        self.config_active.as_ref().unwrap()
+    }
+
+    pub fn lift_ratio(&self) -> float {
+        self.config_active.as_ref().unwrap().lift_ratio
+    }
 }
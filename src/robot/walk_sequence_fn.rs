@@ -11,12 +11,21 @@ struct WalkSequenceFnConfig {
     step_height_weight: float
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum WalkSequencePhase {
     Push,
     Lift
 }
 
+impl WalkSequencePhase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WalkSequencePhase::Push => "push",
+            WalkSequencePhase::Lift => "lift"
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct WalkSequenceFn {
     x: float,
@@ -31,8 +40,8 @@ pub struct WalkSequenceFn {
 
 impl WalkSequenceFn {
     pub fn new(id: u32, offset: float, lift_ratio: float) -> Self {
-        debug_assert!(offset >= 0.0 && offset < 1.0);
-        debug_assert!(lift_ratio >= 0.0 && lift_ratio < 1.0);
+        debug_assert!((0.0..1.0).contains(&offset));
+        debug_assert!((0.0..1.0).contains(&lift_ratio));
 
         let empty_config = WalkSequenceFnConfig {
             step: Vector2::zero(),
@@ -91,9 +100,10 @@ impl WalkSequenceFn {
                 }
             }
         }
-        return scale
+        scale
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn update(&mut self, step: &Vector2, turn_origin: &Vector2, turn_angle: float, step_height_weight: float,
             max_step_radius: float, max_move_radius: float, leg_static_pos: &Vector3, force: bool) -> Result<(), float> {
 
@@ -238,7 +248,9 @@ impl WalkSequenceFn {
         self.config_active.turn_angle = other.turn_angle;
     }
 
-    pub fn advance(&mut self, x: float) {
+    /// Advances the leg to `x`. Returns true if the pending config update was applied.
+    pub fn advance(&mut self, x: float) -> bool {
+        let mut applied = false;
         if let Some(b_cfg) = &self.config_update.clone() {
 
             let x_prev = self.x;
@@ -310,13 +322,12 @@ impl WalkSequenceFn {
                     }
                 }
             }
-            else if self.phase == 3 {
-                if x >= c3 {
-                    let xm = (x - c3) % 1.0;
-                    let xm_prev = (x_prev - c3) % 1.0;
-                    if xm >= rl && xm_prev < rl {
-                        self.config_active = self.config_update.take().unwrap();
-                    }
+            else if self.phase == 3 && x >= c3 {
+                let xm = (x - c3) % 1.0;
+                let xm_prev = (x_prev - c3) % 1.0;
+                if xm >= rl && xm_prev < rl {
+                    self.config_active = self.config_update.take().unwrap();
+                    applied = true;
                 }
             }
 
@@ -326,6 +337,7 @@ impl WalkSequenceFn {
         }
 
         self.x = x;
+        applied
     }
 
     #[allow(non_snake_case)]
@@ -367,6 +379,53 @@ impl WalkSequenceFn {
         }
     }
 
+    /// Returns the sequence distance left until the leg is lifted next, or 0 if the leg is
+    /// currently in the air.
+    pub fn lift_distance(&self) -> float {
+        let x = self.x;
+        let (c1, c2, c3) = self.get_phase_shift_points();
+        let rl = self.lift_ratio;
+
+        if x < c1 {
+            c1 - x
+        }
+        else if x < c2 {
+            0.0
+        }
+        else if x < c3 {
+            c3 - x
+        }
+        else {
+            let xm = (x - c3) % 1.0;
+            if xm < rl { 0.0 } else { 1.0 - xm }
+        }
+    }
+
+    /// Returns how far the leg has moved through its current phase, from 0 at the start of the
+    /// step to 1 at its end. Legs that start the sequence in the middle of a step begin at 0.5.
+    pub fn phase_progress(&self) -> float {
+        let x = self.x;
+        let rl = self.lift_ratio;
+        let rp = 1.0 - self.lift_ratio;
+        let (c1, c2, c3) = self.get_phase_shift_points();
+
+        // Position along the step, from -0.5 to 0.5 while lifted and back while pushing.
+        let progress = if x < c1 {
+            0.5 + x/rp
+        }
+        else if x < c2 {
+            0.5 - c1/rp + (x-c1)/rl
+        }
+        else if x < c3 {
+            0.5 + c1/rp - (c2-c1)/rl + (x-c2)/rp
+        }
+        else {
+            let xm = (x - c3) % 1.0;
+            if xm < rl { xm/rl } else { (xm-rl)/rp }
+        };
+        progress.clamp(0.0, 1.0)
+    }
+
     pub fn phase(&self) -> WalkSequencePhase {
         let x = self.x;
         let (c1, c2, c3) = self.get_phase_shift_points();
@@ -380,3 +439,32 @@ impl WalkSequenceFn {
         }
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use crate::math::{ FloatType as float, Vector2, Vector3 };
+    use super::WalkSequenceFn;
+
+    #[test]
+    fn phase_progress() {
+        let mut seq_fn = WalkSequenceFn::new(1, 0.0, 0.3);
+        seq_fn.update(&Vector2::new(0.0, 0.04), &Vector2::zero(), 0.0, 0.5, 0.1, 0.2, &Vector3::zero(), true).unwrap();
+
+        let mut phase = seq_fn.phase();
+        let mut progress = seq_fn.phase_progress();
+        for i in 1..=300 {
+            seq_fn.advance(i as float * 0.01);
+            let (next_phase, next_progress) = (seq_fn.phase(), seq_fn.phase_progress());
+            assert!((0.0..=1.0).contains(&next_progress));
+            // Progress only restarts when the leg changes between push and lift.
+            if next_phase == phase {
+                assert!(next_progress >= progress);
+            }
+            else {
+                assert!(next_progress < 0.1);
+            }
+            (phase, progress) = (next_phase, next_progress);
+        }
+    }
+}
@@ -0,0 +1,484 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{ Arc, Mutex };
+use std::time::{ Duration, Instant };
+use futures_util::{ SinkExt, StreamExt };
+use json::JsonValue;
+use log::{ info, warn };
+use tokio::net::{ TcpListener, TcpStream };
+use tokio::sync::{ mpsc, oneshot };
+use tokio_tungstenite::WebSocketStream;
+use tungstenite::Message;
+
+use crate::config::ControlConfig;
+use crate::control::{ self, create_reply, AcquireResult, ApiRequest, Arbiter, ClientId, ControlMessage, ControlRequest,
+    ErrorCode, LeaseEvent, ProtocolError };
+use super::{ Metrics, RejectReason };
+
+/// Interval the lease expiry is checked at.
+const LEASE_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Message forwarded from the control clients to the robot thread.
+pub enum RobotRequest {
+    /// Request of a control client, along with the text it was parsed from. The robot thread
+    /// answers every request on `reply_tx`, with `None` if the request does not need a reply.
+    Control {
+        request: ControlRequest,
+        text: String,
+        reply_tx: oneshot::Sender<Option<JsonValue>>
+    },
+    /// Request of the HTTP API, along with the request body.
+    Api {
+        request: ApiRequest,
+        text: String,
+        reply_tx: oneshot::Sender<Result<JsonValue, ProtocolError>>
+    },
+    /// The client owning the robot closed the connection or the connection was lost.
+    Disconnected(SocketAddr),
+    /// The client owning the robot released control, its lease expired or another client took
+    /// over. Sent before any request of the new owner.
+    LeaseEnded(ClientId)
+}
+
+struct HubState {
+    arbiter: Arbiter,
+    /// Channels to push notifications to the connected clients.
+    clients: HashMap<ClientId, mpsc::UnboundedSender<JsonValue>>,
+    next_client_id: ClientId
+}
+
+/// Reply to a control request, either known right away or still to be sent by the robot thread.
+pub(super) enum PendingReply {
+    Ready(Option<JsonValue>),
+    Robot(oneshot::Receiver<Option<JsonValue>>)
+}
+
+/// Shared state of all control connections. Arbitrates which client drives the robot and
+/// forwards the commands of the owner to the robot thread.
+pub struct ControlHub {
+    state: Mutex<HubState>,
+    robot_tx: std::sync::mpsc::Sender<RobotRequest>,
+    privileged_token: Option<String>,
+    metrics: Arc<Metrics>,
+    start: Instant
+}
+
+fn lease_reply(state: &str, id: Option<&control::RequestId>) -> JsonValue {
+    let mut reply = create_reply("lease", id);
+    reply["state"] = state.into();
+    reply
+}
+
+impl ControlHub {
+    pub fn new(config: &ControlConfig, robot_tx: std::sync::mpsc::Sender<RobotRequest>, metrics: Arc<Metrics>) -> Arc<Self> {
+        Arc::new(ControlHub {
+            state: Mutex::new(HubState {
+                arbiter: Arbiter::new(config.lease_timeout as u64),
+                clients: HashMap::new(),
+                next_client_id: 1
+            }),
+            robot_tx,
+            privileged_token: config.privileged_token.clone(),
+            metrics,
+            start: Instant::now()
+        })
+    }
+
+    fn now(&self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
+
+    pub(super) fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Renders the metrics in the Prometheus text format.
+    pub(super) fn render_metrics(&self) -> String {
+        let control_clients = self.state.lock().unwrap().clients.len();
+        self.metrics.render(control_clients)
+    }
+
+    fn send_to_robot(&self, request: RobotRequest) -> bool {
+        self.robot_tx.send(request).is_ok()
+    }
+
+    pub(super) fn register(&self) -> (ClientId, mpsc::UnboundedReceiver<JsonValue>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut state = self.state.lock().unwrap();
+        let client = state.next_client_id;
+        state.next_client_id += 1;
+        state.clients.insert(client, tx);
+        (client, rx)
+    }
+
+    pub(super) fn unregister(&self, client: ClientId, peer: SocketAddr) {
+        let mut state = self.state.lock().unwrap();
+        self.expire_leases(&mut state);
+        let was_owner = state.arbiter.owner() == Some(client);
+        let events = state.arbiter.release(client, self.now());
+        state.clients.remove(&client);
+        Self::notify(&state, events);
+        if was_owner {
+            self.send_to_robot(RobotRequest::Disconnected(peer));
+        }
+    }
+
+    /// Notifies the clients about `events` and tells the robot thread if the lease of `owner`,
+    /// the owner before the change, ended. The state stays locked until the robot thread was
+    /// told, so that it stops the robot before it gets a request of the new owner.
+    fn lease_changed(&self, state: &HubState, owner: Option<ClientId>, events: Vec<LeaseEvent>) {
+        Self::notify(state, events);
+        if let Some(owner) = owner.filter(|&owner| state.arbiter.owner() != Some(owner)) {
+            self.send_to_robot(RobotRequest::LeaseEnded(owner));
+        }
+    }
+
+    fn notify(state: &HubState, events: Vec<LeaseEvent>) {
+        for event in events {
+            let (client, msg) = match event {
+                LeaseEvent::Granted(client) => {
+                    info!("Control client {} acquired control", client);
+                    (client, lease_reply("granted", None))
+                },
+                LeaseEvent::Revoked(client, reason) => {
+                    info!("Control of client {} revoked: {}", client, reason.as_str());
+                    let mut msg = lease_reply("revoked", None);
+                    msg["reason"] = reason.as_str().into();
+                    (client, msg)
+                },
+                LeaseEvent::TakeoverRequested { owner, from } => {
+                    let mut msg = create_reply("takeover_requested", None);
+                    msg["client"] = from.into();
+                    (owner, msg)
+                }
+            };
+            if let Some(tx) = state.clients.get(&client) {
+                let _ = tx.send(msg);
+            }
+        }
+    }
+
+    /// Revokes expired leases. Runs until the program ends.
+    pub async fn run_lease_timer(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(LEASE_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            self.expire();
+        }
+    }
+
+    fn expire(&self) {
+        self.expire_leases(&mut self.state.lock().unwrap());
+    }
+
+    /// Revokes expired leases.
+    fn expire_leases(&self, state: &mut HubState) {
+        let owner = state.arbiter.owner();
+        let events = state.arbiter.expire(self.now());
+        self.lease_changed(state, owner, events);
+    }
+
+    fn acquire(&self, client: ClientId, token: Option<&str>, id: Option<&control::RequestId>) -> JsonValue {
+        let privileged = match (token, &self.privileged_token) {
+            (None, _) => false,
+            (Some(token), Some(privileged_token)) if token == privileged_token => true,
+            (Some(_), _) => {
+                let e = ProtocolError::new(ErrorCode::Rejected, Some("token"), "invalid token".to_string());
+                return e.with_id(id.cloned()).to_json();
+            }
+        };
+
+        let mut state = self.state.lock().unwrap();
+        let owner = state.arbiter.owner();
+        let (res, events) = state.arbiter.acquire(client, privileged, self.now());
+        self.lease_changed(&state, owner, events);
+
+        match res {
+            AcquireResult::Granted => {
+                info!("Control client {} acquired control{}", client, if privileged { " (privileged)" } else { "" });
+                lease_reply("granted", id)
+            },
+            AcquireResult::Pending => lease_reply("pending", id)
+        }
+    }
+
+    fn release(&self, client: ClientId, id: Option<&control::RequestId>) -> JsonValue {
+        let mut state = self.state.lock().unwrap();
+        let owner = state.arbiter.owner();
+        let events = state.arbiter.release(client, self.now());
+        self.lease_changed(&state, owner, events);
+        lease_reply("released", id)
+    }
+
+    /// Renews the lease of `client`. Returns false if the client does not own the robot.
+    fn renew(&self, client: ClientId) -> bool {
+        let mut state = self.state.lock().unwrap();
+        self.expire_leases(&mut state);
+        match state.arbiter.renew(client, self.now()) {
+            Some(events) => {
+                Self::notify(&state, events);
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Handles a request parsed from `text` without waiting for the robot thread. Requests reach
+    /// the robot in the order they are submitted. Returns `Err` if the robot thread is gone.
+    pub(super) fn submit_request(&self, client: ClientId, request: ControlRequest, text: &str) -> Result<PendingReply, ()> {
+        match &request.message {
+            ControlMessage::Acquire { token } =>
+                return Ok(PendingReply::Ready(Some(self.acquire(client, token.as_deref(), request.id.as_ref())))),
+            ControlMessage::Release => return Ok(PendingReply::Ready(Some(self.release(client, request.id.as_ref())))),
+            ControlMessage::Control(_) if !self.renew(client) => {
+                self.metrics.packet_rejected(RejectReason::NotOwner);
+                let e = ProtocolError::new(ErrorCode::NotOwner, None, "another client controls the robot".to_string());
+                return Ok(PendingReply::Ready(Some(e.with_id(request.id).to_json())));
+            },
+            ControlMessage::Control(_) | ControlMessage::Query => {}
+        }
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        if !self.send_to_robot(RobotRequest::Control { request, text: text.to_string(), reply_tx }) {
+            return Err(());
+        }
+        Ok(PendingReply::Robot(reply_rx))
+    }
+
+    /// Waits for the reply of a submitted request and returns it, if any. Returns `Err` if the
+    /// robot thread is gone.
+    pub(super) async fn wait_reply(&self, pending: PendingReply) -> Result<Option<JsonValue>, ()> {
+        let reply_rx = match pending {
+            PendingReply::Ready(reply) => return Ok(reply),
+            PendingReply::Robot(reply_rx) => reply_rx
+        };
+        let reply = reply_rx.await.map_err(|_| ())?;
+        if reply.as_ref().is_some_and(|reply| reply["type"] == "error") {
+            self.metrics.packet_rejected(RejectReason::Rejected);
+        }
+        Ok(reply)
+    }
+
+    /// Handles a request parsed from `text` and returns the reply for the client, if any.
+    /// Returns `Err` if the robot thread is gone.
+    pub(super) async fn handle_request(&self, client: ClientId, request: ControlRequest, text: &str) -> Result<Option<JsonValue>, ()> {
+        let pending = self.submit_request(client, request, text)?;
+        self.wait_reply(pending).await
+    }
+
+    /// Queries the state of the robot on behalf of a client that does not take part in the
+    /// arbitration, e.g. an HTTP request. Returns `Err` if the robot thread is gone.
+    pub(super) async fn query(&self) -> Result<JsonValue, ()> {
+        let request = ControlRequest { id: None, message: ControlMessage::Query };
+        let (reply_tx, reply_rx) = oneshot::channel();
+        if !self.send_to_robot(RobotRequest::Control { request, text: String::new(), reply_tx }) {
+            return Err(());
+        }
+        reply_rx.await.ok().flatten().ok_or(())
+    }
+
+    /// Handles a request of the HTTP API with the request body `text`. Requests that change the
+    /// robot are refused while a control client holds the lease. Returns `Err` if the robot
+    /// thread is gone.
+    pub(super) async fn handle_api_request(&self, request: ApiRequest, text: &str) -> Result<Result<JsonValue, ProtocolError>, ()> {
+        if request.modifies() {
+            let mut state = self.state.lock().unwrap();
+            self.expire_leases(&mut state);
+            if let Some(owner) = state.arbiter.owner() {
+                return Ok(Err(ProtocolError::new(ErrorCode::NotOwner, None,
+                    format!("control client {} controls the robot", owner))));
+            }
+        }
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        if !self.send_to_robot(RobotRequest::Api { request, text: text.to_string(), reply_tx }) {
+            return Err(());
+        }
+        reply_rx.await.map_err(|_| ())
+    }
+}
+
+/// Serves a control client on an established WebSocket connection.
+pub async fn handle_control_client(ws_stream: WebSocketStream<TcpStream>, peer: SocketAddr,
+        hub: Arc<ControlHub>) -> Result<(), tungstenite::Error> {
+    let (client, notification_rx) = hub.register();
+    info!("New control connection: {} (client {})", peer, client);
+
+    let res = serve_control_client(ws_stream, peer, client, &hub, notification_rx).await;
+    hub.unregister(client, peer);
+    res
+}
+
+async fn serve_control_client(ws_stream: WebSocketStream<TcpStream>, peer: SocketAddr, client: ClientId,
+        hub: &ControlHub, mut notification_rx: mpsc::UnboundedReceiver<JsonValue>) -> Result<(), tungstenite::Error> {
+    let (mut write, mut read) = ws_stream.split();
+
+    loop {
+        let reply = tokio::select! {
+            Some(notification) = notification_rx.recv() => Some(notification),
+            msg = read.next() => {
+                let msg_text = match msg {
+                    Some(Ok(msg @ (Message::Text(_) | Message::Binary(_)))) => msg.into_text().unwrap_or_default(),
+                    Some(Ok(Message::Close(_))) | None => break,
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => return Err(e)
+                };
+
+                hub.metrics().packet_received();
+                match control::parse_message(&msg_text) {
+                    Ok(request) => match hub.handle_request(client, request, &msg_text).await {
+                        Ok(reply) => reply,
+                        Err(_) => break
+                    },
+                    Err(e) => {
+                        warn!("Invalid control message from {}: {}", peer, e);
+                        hub.metrics().packet_rejected(RejectReason::Invalid);
+                        Some(e.to_json())
+                    }
+                }
+            }
+        };
+
+        if let Some(reply) = reply {
+            write.send(Message::Text(json::stringify(reply))).await?;
+        }
+    }
+
+    info!("Control connection closed: {}", peer);
+    Ok(())
+}
+
+/// Accepts control clients and forwards their requests to the robot thread.
+pub async fn control_listener(addr: &str, hub: Arc<ControlHub>) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("Listening on: {}", addr);
+
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let hub = hub.clone();
+        tokio::spawn(async move {
+            let res = match tokio_tungstenite::accept_async(stream).await {
+                Ok(ws_stream) => handle_control_client(ws_stream, peer, hub).await,
+                Err(e) => Err(e)
+            };
+            if let Err(e) = res {
+                warn!("Control connection error: {}", e);
+            }
+        });
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::sync::mpsc::Receiver;
+    use std::time::Duration;
+    use crate::config::ControlConfig;
+    use crate::control::parse_message;
+    use super::{ ControlHub, Metrics, RobotRequest };
+
+    fn hub() -> (Arc<ControlHub>, Receiver<RobotRequest>) {
+        hub_with_config(&ControlConfig::default())
+    }
+
+    fn hub_with_config(config: &ControlConfig) -> (Arc<ControlHub>, Receiver<RobotRequest>) {
+        let (robot_tx, robot_rx) = std::sync::mpsc::channel();
+        (ControlHub::new(config, robot_tx, Arc::new(Metrics::default())), robot_rx)
+    }
+
+    fn submit(hub: &ControlHub, client: u64, text: &str) {
+        assert!(hub.submit_request(client, parse_message(text).unwrap(), text).is_ok());
+    }
+
+    fn peer() -> SocketAddr {
+        "127.0.0.1:50123".parse().unwrap()
+    }
+
+    /// Sends a walk command on behalf of `client`, which acquires the lease.
+    fn walk(hub: &ControlHub, robot_rx: &Receiver<RobotRequest>, client: u64) {
+        submit(hub, client, r#"{ "step": { "x": 0.0, "y": 0.5 } }"#);
+        assert!(matches!(robot_rx.try_recv(), Ok(RobotRequest::Control { .. })));
+    }
+
+    #[test]
+    fn owner_disconnect_stops_robot() {
+        let (hub, robot_rx) = hub();
+        let (owner, _owner_rx) = hub.register();
+        let (observer, _observer_rx) = hub.register();
+        walk(&hub, &robot_rx, owner);
+
+        hub.unregister(observer, peer());
+        assert!(robot_rx.try_recv().is_err());
+
+        hub.unregister(owner, peer());
+        assert!(matches!(robot_rx.try_recv(), Ok(RobotRequest::Disconnected(p)) if p == peer()));
+    }
+
+    #[test]
+    fn release_stops_robot() {
+        let (hub, robot_rx) = hub();
+        let (owner, _owner_rx) = hub.register();
+        let (other, _other_rx) = hub.register();
+        walk(&hub, &robot_rx, owner);
+
+        // Releasing without owning the robot changes nothing.
+        submit(&hub, other, r#"{ "type": "release" }"#);
+        assert!(robot_rx.try_recv().is_err());
+
+        submit(&hub, owner, r#"{ "type": "release" }"#);
+        assert!(matches!(robot_rx.try_recv(), Ok(RobotRequest::LeaseEnded(client)) if client == owner));
+        assert!(robot_rx.try_recv().is_err());
+
+        // The robot is stopped before the new owner takes over.
+        walk(&hub, &robot_rx, other);
+    }
+
+    #[test]
+    fn expiry_stops_robot() {
+        let (hub, robot_rx) = hub_with_config(&ControlConfig { lease_timeout: 20, ..ControlConfig::default() });
+        let (owner, _owner_rx) = hub.register();
+        walk(&hub, &robot_rx, owner);
+
+        hub.expire();
+        assert!(robot_rx.try_recv().is_err());
+
+        std::thread::sleep(Duration::from_millis(30));
+        hub.expire();
+        assert!(matches!(robot_rx.try_recv(), Ok(RobotRequest::LeaseEnded(client)) if client == owner));
+        hub.expire();
+        assert!(robot_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn expiry_before_takeover_stops_robot() {
+        let (hub, robot_rx) = hub_with_config(&ControlConfig { lease_timeout: 20, ..ControlConfig::default() });
+        let (owner, _owner_rx) = hub.register();
+        let (other, _other_rx) = hub.register();
+        walk(&hub, &robot_rx, owner);
+
+        // The expired lease ends when the next client implicitly acquires it.
+        std::thread::sleep(Duration::from_millis(30));
+        submit(&hub, other, r#"{ "step": { "x": 0.5, "y": 0.0 } }"#);
+        assert!(matches!(robot_rx.try_recv(), Ok(RobotRequest::LeaseEnded(client)) if client == owner));
+        assert!(matches!(robot_rx.try_recv(), Ok(RobotRequest::Control { .. })));
+    }
+
+    #[test]
+    fn takeover_stops_robot() {
+        let config = ControlConfig { privileged_token: Some("secret".to_string()), ..ControlConfig::default() };
+        let (hub, robot_rx) = hub_with_config(&config);
+        let (owner, _owner_rx) = hub.register();
+        let (console, _console_rx) = hub.register();
+        walk(&hub, &robot_rx, owner);
+
+        submit(&hub, console, r#"{ "type": "acquire", "token": "secret" }"#);
+        assert!(matches!(robot_rx.try_recv(), Ok(RobotRequest::LeaseEnded(client)) if client == owner));
+        assert!(robot_rx.try_recv().is_err());
+
+        // A takeover request that is only queued does not stop the robot.
+        submit(&hub, owner, r#"{ "type": "acquire" }"#);
+        assert!(robot_rx.try_recv().is_err());
+    }
+}
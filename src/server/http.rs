@@ -0,0 +1,258 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use json::JsonValue;
+use log::{ info, warn };
+use tokio::io::{ AsyncReadExt, AsyncWriteExt };
+use tokio::net::{ TcpListener, TcpStream };
+use tungstenite::http::StatusCode;
+
+use crate::config::Endpoints;
+use crate::control::{ self, ApiRequest, ErrorCode, ProtocolError };
+use super::{ panel_page, ControlHub };
+
+/// Largest request line and header block accepted.
+const MAX_HEAD_SIZE: usize = 8 * 1024;
+/// Largest request body accepted.
+const MAX_BODY_SIZE: usize = 64 * 1024;
+/// Time a client has to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Content type of the Prometheus text format.
+const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+const PANEL_CONTENT_TYPE: &str = "text/html; charset=utf-8";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Endpoint {
+    Panel,
+    State,
+    GetConfig,
+    SetConfig,
+    Command,
+    Metrics
+}
+
+impl Endpoint {
+    fn route(method: &str, path: &str) -> Result<Self, StatusCode> {
+        match (method, path) {
+            ("GET", "/") => Ok(Endpoint::Panel),
+            ("GET", "/state") => Ok(Endpoint::State),
+            ("GET", "/config") => Ok(Endpoint::GetConfig),
+            ("PUT", "/config") => Ok(Endpoint::SetConfig),
+            ("POST", "/command") => Ok(Endpoint::Command),
+            ("GET", "/metrics") => Ok(Endpoint::Metrics),
+            (_, "/" | "/state" | "/config" | "/command" | "/metrics") => Err(StatusCode::METHOD_NOT_ALLOWED),
+            _ => Err(StatusCode::NOT_FOUND)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct RequestHead {
+    method: String,
+    /// Path without the query string.
+    path: String,
+    content_length: usize
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Response {
+    status: StatusCode,
+    content_type: &'static str,
+    body: String
+}
+
+impl Response {
+    fn json(status: StatusCode, body: JsonValue) -> Self {
+        Response { status, content_type: "application/json", body: json::stringify(body) }
+    }
+
+    /// Error on the HTTP level, before the request reached the API.
+    fn status(status: StatusCode) -> Self {
+        let body = format!("{}\n", status.canonical_reason().unwrap_or_default());
+        Response { status, content_type: "text/plain", body }
+    }
+
+    fn error(e: ProtocolError) -> Self {
+        let status = match e.code {
+            ErrorCode::Rejected | ErrorCode::NotOwner => StatusCode::CONFLICT,
+            _ => StatusCode::BAD_REQUEST
+        };
+        Response::json(status, e.to_json())
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        format!("HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.status.as_u16(), self.status.canonical_reason().unwrap_or_default(), self.content_type,
+            self.body.len(), self.body).into_bytes()
+    }
+}
+
+fn parse_head(head: &str) -> Result<RequestHead, StatusCode> {
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let (method, target, version) = match (request_line.next(), request_line.next(), request_line.next(), request_line.next()) {
+        (Some(method), Some(target), Some(version), None) => (method, target, version),
+        _ => return Err(StatusCode::BAD_REQUEST)
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Err(StatusCode::HTTP_VERSION_NOT_SUPPORTED);
+    }
+
+    let mut content_length = 0;
+    for line in lines {
+        let (name, value) = line.split_once(':').ok_or(StatusCode::BAD_REQUEST)?;
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = value.trim().parse().map_err(|_| StatusCode::BAD_REQUEST)?;
+        }
+        else if name.eq_ignore_ascii_case("transfer-encoding") {
+            return Err(StatusCode::LENGTH_REQUIRED);
+        }
+    }
+    if content_length > MAX_BODY_SIZE {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    let path = target.split('?').next().unwrap_or_default();
+    Ok(RequestHead { method: method.to_string(), path: path.to_string(), content_length })
+}
+
+/// Reads a request. Returns the response to send instead if the request is malformed.
+async fn read_request(stream: &mut TcpStream) -> std::io::Result<Result<(RequestHead, String), Response>> {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 1024];
+
+    let head_len = loop {
+        if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos;
+        }
+        if buf.len() > MAX_HEAD_SIZE {
+            return Ok(Err(Response::status(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE)));
+        }
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    };
+
+    let head = match std::str::from_utf8(&buf[..head_len]).map_err(|_| StatusCode::BAD_REQUEST).and_then(parse_head) {
+        Ok(head) => head,
+        Err(status) => return Ok(Err(Response::status(status)))
+    };
+
+    let mut body = buf.split_off(head_len + 4);
+    while body.len() < head.content_length {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        body.extend_from_slice(&chunk[..n]);
+    }
+    body.truncate(head.content_length);
+
+    match String::from_utf8(body) {
+        Ok(body) => Ok(Ok((head, body))),
+        Err(_) => Ok(Err(Response::status(StatusCode::BAD_REQUEST)))
+    }
+}
+
+async fn api_response(hub: &ControlHub, request: Result<ApiRequest, ProtocolError>, body: &str) -> Response {
+    let request = match request {
+        Ok(request) => request,
+        Err(e) => return Response::error(e)
+    };
+    match hub.handle_api_request(request, body).await {
+        Ok(Ok(reply)) => Response::json(StatusCode::OK, reply),
+        Ok(Err(e)) => Response::error(e),
+        Err(_) => Response::status(StatusCode::SERVICE_UNAVAILABLE)
+    }
+}
+
+async fn handle_request(head: &RequestHead, body: &str, hub: &ControlHub, panel: &str) -> Response {
+    let endpoint = match Endpoint::route(&head.method, &head.path) {
+        Ok(endpoint) => endpoint,
+        Err(status) => return Response::status(status)
+    };
+
+    match endpoint {
+        Endpoint::Panel => Response { status: StatusCode::OK, content_type: PANEL_CONTENT_TYPE, body: panel.to_string() },
+        Endpoint::State => match hub.query().await {
+            Ok(state) => Response::json(StatusCode::OK, state),
+            Err(_) => Response::status(StatusCode::SERVICE_UNAVAILABLE)
+        },
+        Endpoint::GetConfig => api_response(hub, Ok(ApiRequest::GetConfig), body).await,
+        Endpoint::SetConfig => api_response(hub, control::parse_config_update(body).map(ApiRequest::SetConfig), body).await,
+        Endpoint::Command => api_response(hub, control::parse_command(body).map(ApiRequest::Command), body).await,
+        Endpoint::Metrics => Response { status: StatusCode::OK, content_type: METRICS_CONTENT_TYPE, body: hub.render_metrics() }
+    }
+}
+
+/// Serves a single request and closes the connection.
+async fn handle_http_client(mut stream: TcpStream, peer: SocketAddr, hub: &ControlHub, panel: &str) -> std::io::Result<()> {
+    let request = tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut stream)).await
+        .map_err(|_| std::io::Error::from(std::io::ErrorKind::TimedOut))??;
+
+    let response = match request {
+        Ok((head, body)) => {
+            let response = handle_request(&head, &body, hub, panel).await;
+            info!("HTTP {} {} from {}: {}", head.method, head.path, peer, response.status.as_u16());
+            response
+        },
+        Err(response) => response
+    };
+
+    stream.write_all(&response.to_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Serves the HTTP API and the web control panel, which connects to the WebSocket `endpoints`.
+pub async fn http_listener(addr: &str, hub: Arc<ControlHub>, endpoints: &Endpoints) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("Listening on: {} (HTTP)", addr);
+    let panel: Arc<str> = panel_page(endpoints).into();
+
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let hub = hub.clone();
+        let panel = panel.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_http_client(stream, peer, &hub, &panel).await {
+                warn!("HTTP connection error: {}", e);
+            }
+        });
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use tungstenite::http::StatusCode;
+    use super::{ parse_head, Endpoint };
+
+    #[test]
+    fn request_head() {
+        let head = parse_head("PUT /config?pretty HTTP/1.1\r\nHost: robot\r\nContent-Length: 12").unwrap();
+        assert_eq!((head.method.as_str(), head.path.as_str(), head.content_length), ("PUT", "/config", 12));
+
+        let head = parse_head("GET /state HTTP/1.0").unwrap();
+        assert_eq!(head.content_length, 0);
+
+        assert_eq!(parse_head("GET /state").unwrap_err(), StatusCode::BAD_REQUEST);
+        assert_eq!(parse_head("GET /state HTTP/2").unwrap_err(), StatusCode::HTTP_VERSION_NOT_SUPPORTED);
+        assert_eq!(parse_head("POST /command HTTP/1.1\r\ncontent-length: x").unwrap_err(), StatusCode::BAD_REQUEST);
+        assert_eq!(parse_head("POST /command HTTP/1.1\r\nContent-Length: 1000000").unwrap_err(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(parse_head("POST /command HTTP/1.1\r\nTransfer-Encoding: chunked").unwrap_err(), StatusCode::LENGTH_REQUIRED);
+    }
+
+    #[test]
+    fn routes() {
+        assert_eq!(Endpoint::route("GET", "/"), Ok(Endpoint::Panel));
+        assert_eq!(Endpoint::route("GET", "/state"), Ok(Endpoint::State));
+        assert_eq!(Endpoint::route("PUT", "/config"), Ok(Endpoint::SetConfig));
+        assert_eq!(Endpoint::route("POST", "/command"), Ok(Endpoint::Command));
+        assert_eq!(Endpoint::route("GET", "/metrics"), Ok(Endpoint::Metrics));
+        assert_eq!(Endpoint::route("POST", "/state"), Err(StatusCode::METHOD_NOT_ALLOWED));
+        assert_eq!(Endpoint::route("PUT", "/metrics"), Err(StatusCode::METHOD_NOT_ALLOWED));
+        assert_eq!(Endpoint::route("GET", "/panel"), Err(StatusCode::NOT_FOUND));
+    }
+}
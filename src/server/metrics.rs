@@ -0,0 +1,238 @@
+//! Metrics of the robot and the servers in the Prometheus text format, served by the HTTP API
+//! on `/metrics`.
+//!
+//! The control loop and the servers update the metrics with atomic operations, so that
+//! recording them never blocks the control loop.
+
+use std::fmt::Write;
+use std::sync::atomic::{ AtomicU64, Ordering };
+use std::time::Duration;
+
+use crate::control::Controller;
+use crate::robot::HexapodMode;
+use crate::telemetry::TIMING_BUCKETS_US;
+
+/// Reason a control packet was not applied.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RejectReason {
+    /// The packet could not be parsed or failed the validation.
+    Invalid,
+    /// Datagram with an outdated sequence number.
+    Stale,
+    /// The client does not own the robot.
+    NotOwner,
+    /// The robot cannot execute the command in its current state.
+    Rejected
+}
+
+impl RejectReason {
+    const ALL: [RejectReason; 4] = [ RejectReason::Invalid, RejectReason::Stale, RejectReason::NotOwner, RejectReason::Rejected ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RejectReason::Invalid => "invalid",
+            RejectReason::Stale => "stale",
+            RejectReason::NotOwner => "not_owner",
+            RejectReason::Rejected => "rejected"
+        }
+    }
+}
+
+const MODES: [HexapodMode; 4] = [ HexapodMode::Standing, HexapodMode::Walking, HexapodMode::Stopping, HexapodMode::Manipulating ];
+
+/// Float value stored as its bits.
+#[derive(Debug, Default)]
+struct AtomicFloat(AtomicU64);
+
+impl AtomicFloat {
+    fn set(&self, v: f64) {
+        self.0.store(v.to_bits(), Ordering::Relaxed);
+    }
+
+    fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+}
+
+/// Cumulative histogram of durations with the buckets of the timing telemetry.
+#[derive(Debug, Default)]
+struct HistogramMetric {
+    buckets: [AtomicU64; TIMING_BUCKETS_US.len()],
+    sum_us: AtomicU64,
+    count: AtomicU64
+}
+
+impl HistogramMetric {
+    fn observe(&self, d: Duration) {
+        let us = d.as_micros() as u64;
+        if let Some(i) = TIMING_BUCKETS_US.iter().position(|bound| us <= *bound as u64) {
+            self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.sum_us.fetch_add(us, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn write(&self, out: &mut String, name: &str, help: &str) -> std::fmt::Result {
+        writeln!(out, "# HELP {} {}", name, help)?;
+        writeln!(out, "# TYPE {} histogram", name)?;
+        let mut total = 0;
+        for (bound, count) in TIMING_BUCKETS_US.iter().zip(self.buckets.iter()) {
+            total += count.load(Ordering::Relaxed);
+            writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, *bound as f64 / 1e6, total)?;
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count)?;
+        writeln!(out, "{}_sum {}", name, self.sum_us.load(Ordering::Relaxed) as f64 / 1e6)?;
+        writeln!(out, "{}_count {}", name, count)
+    }
+}
+
+fn write_metric(out: &mut String, name: &str, metric_type: &str, help: &str, value: impl std::fmt::Display) -> std::fmt::Result {
+    writeln!(out, "# HELP {} {}", name, help)?;
+    writeln!(out, "# TYPE {} {}", name, metric_type)?;
+    writeln!(out, "{} {}", name, value)
+}
+
+/// Metrics shared by the control loop and the servers.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    iterations: AtomicU64,
+    overruns: AtomicU64,
+    update_duration: HistogramMetric,
+    jitter: HistogramMetric,
+    packets_received: AtomicU64,
+    packets_rejected: [AtomicU64; RejectReason::ALL.len()],
+    monitor_clients: AtomicU64,
+    /// Index of the mode in `MODES`.
+    mode: AtomicU64,
+    speed: AtomicFloat,
+    step_scale: AtomicFloat,
+    distance: AtomicFloat,
+    step_scaled_time: AtomicU64
+}
+
+impl Metrics {
+    /// Records the timing of a control loop iteration.
+    pub fn record_iteration(&self, update: Duration, jitter: Duration, overrun: bool) {
+        self.iterations.fetch_add(1, Ordering::Relaxed);
+        if overrun {
+            self.overruns.fetch_add(1, Ordering::Relaxed);
+        }
+        self.update_duration.observe(update);
+        self.jitter.observe(jitter);
+    }
+
+    /// Records the state of the robot after a control loop iteration.
+    pub fn record_robot(&self, controller: &Controller, monitor_clients: usize) {
+        let h = controller.hexapod();
+        self.mode.store(MODES.iter().position(|mode| *mode == h.mode()).unwrap_or_default() as u64, Ordering::Relaxed);
+        self.speed.set(h.speed() as f64);
+        // Not walking counts as walking with the requested step.
+        self.step_scale.set(h.step_scale().unwrap_or(1.0) as f64);
+        self.distance.set(controller.odometry().distance() as f64);
+        self.step_scaled_time.store(controller.step_scaled_time(), Ordering::Relaxed);
+        self.monitor_clients.store(monitor_clients as u64, Ordering::Relaxed);
+    }
+
+    /// Counts a control message or datagram received from a client.
+    pub fn packet_received(&self) {
+        self.packets_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a control packet that was not applied.
+    pub fn packet_rejected(&self, reason: RejectReason) {
+        let i = RejectReason::ALL.iter().position(|r| *r == reason).unwrap();
+        self.packets_rejected[i].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Renders the metrics in the Prometheus text format.
+    pub fn render(&self, control_clients: usize) -> String {
+        let mut out = String::new();
+        // Writing to a string cannot fail.
+        let _ = self.write(&mut out, control_clients);
+        out
+    }
+
+    fn write(&self, out: &mut String, control_clients: usize) -> std::fmt::Result {
+        let load = |v: &AtomicU64| v.load(Ordering::Relaxed);
+
+        write_metric(out, "hexapod_loop_iterations_total", "counter", "Control loop iterations.", load(&self.iterations))?;
+        write_metric(out, "hexapod_loop_overruns_total", "counter",
+            "Control loop iterations that did not finish before the next one was due.", load(&self.overruns))?;
+        self.update_duration.write(out, "hexapod_loop_update_duration_seconds", "Time spent advancing the robot per iteration.")?;
+        self.jitter.write(out, "hexapod_loop_jitter_seconds", "Delay of the start of the iterations behind their schedule.")?;
+
+        write_metric(out, "hexapod_control_packets_received_total", "counter",
+            "Control messages and datagrams received.", load(&self.packets_received))?;
+        writeln!(out, "# HELP hexapod_control_packets_rejected_total Control packets that were not applied.")?;
+        writeln!(out, "# TYPE hexapod_control_packets_rejected_total counter")?;
+        for (reason, count) in RejectReason::ALL.iter().zip(self.packets_rejected.iter()) {
+            writeln!(out, "hexapod_control_packets_rejected_total{{reason=\"{}\"}} {}", reason.as_str(), load(count))?;
+        }
+
+        writeln!(out, "# HELP hexapod_clients Connected clients.")?;
+        writeln!(out, "# TYPE hexapod_clients gauge")?;
+        writeln!(out, "hexapod_clients{{type=\"control\"}} {}", control_clients)?;
+        writeln!(out, "hexapod_clients{{type=\"monitor\"}} {}", load(&self.monitor_clients))?;
+
+        writeln!(out, "# HELP hexapod_mode Current mode of the robot, 1 for the active one.")?;
+        writeln!(out, "# TYPE hexapod_mode gauge")?;
+        for (i, mode) in MODES.iter().enumerate() {
+            writeln!(out, "hexapod_mode{{mode=\"{}\"}} {}", mode.as_str(), (load(&self.mode) == i as u64) as u8)?;
+        }
+        write_metric(out, "hexapod_speed_meters_per_second", "gauge", "Walking speed.", self.speed.get())?;
+        write_metric(out, "hexapod_step_scale", "gauge",
+            "Factor the requested step is scaled down by to stay within the limits, 1 when not walking.", self.step_scale.get())?;
+        write_metric(out, "hexapod_distance_walked_meters_total", "counter",
+            "Distance walked, estimated from the pushing feet.", self.distance.get())?;
+        write_metric(out, "hexapod_step_scaled_seconds_total", "counter",
+            "Time spent walking with a step scaled down to stay within the limits.", load(&self.step_scaled_time) as f64 / 1000.0)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::{ Metrics, RejectReason };
+
+    fn value<'a>(text: &'a str, name: &str) -> &'a str {
+        text.lines()
+            .find_map(|line| line.strip_prefix(name).and_then(|rest| rest.strip_prefix(' ')))
+            .unwrap()
+    }
+
+    #[test]
+    fn render() {
+        let metrics = Metrics::default();
+        metrics.record_iteration(Duration::from_micros(80), Duration::from_micros(40), false);
+        metrics.record_iteration(Duration::from_micros(900), Duration::from_micros(30000), true);
+        metrics.packet_received();
+        metrics.packet_received();
+        metrics.packet_rejected(RejectReason::NotOwner);
+
+        let text = metrics.render(2);
+        assert_eq!(value(&text, "hexapod_loop_iterations_total"), "2");
+        assert_eq!(value(&text, "hexapod_loop_overruns_total"), "1");
+        assert_eq!(value(&text, "hexapod_loop_update_duration_seconds_bucket{le=\"0.00005\"}"), "0");
+        assert_eq!(value(&text, "hexapod_loop_update_duration_seconds_bucket{le=\"0.0001\"}"), "1");
+        assert_eq!(value(&text, "hexapod_loop_update_duration_seconds_bucket{le=\"0.001\"}"), "2");
+        assert_eq!(value(&text, "hexapod_loop_jitter_seconds_bucket{le=\"0.02\"}"), "1");
+        assert_eq!(value(&text, "hexapod_loop_jitter_seconds_bucket{le=\"+Inf\"}"), "2");
+        assert_eq!(value(&text, "hexapod_loop_update_duration_seconds_sum"), "0.00098");
+        assert_eq!(value(&text, "hexapod_control_packets_received_total"), "2");
+        assert_eq!(value(&text, "hexapod_control_packets_rejected_total{reason=\"not_owner\"}"), "1");
+        assert_eq!(value(&text, "hexapod_control_packets_rejected_total{reason=\"invalid\"}"), "0");
+        assert_eq!(value(&text, "hexapod_clients{type=\"control\"}"), "2");
+        assert_eq!(value(&text, "hexapod_mode{mode=\"standing\"}"), "1");
+
+        // Every sample is preceded by its metadata.
+        for line in text.lines().filter(|line| !line.starts_with('#')) {
+            let name = line.split(['{', ' ']).next().unwrap();
+            let family = ["_bucket", "_sum", "_count"].iter()
+                .find_map(|suffix| name.strip_suffix(suffix))
+                .unwrap_or(name);
+            assert!(text.contains(&format!("# TYPE {} ", family)), "{}", line);
+        }
+    }
+}
@@ -0,0 +1,15 @@
+mod control;
+mod http;
+mod metrics;
+mod monitor;
+mod panel;
+mod router;
+mod udp;
+
+pub use control::*;
+pub use http::*;
+pub use metrics::*;
+pub use monitor::*;
+pub use panel::*;
+pub use router::*;
+pub use udp::*;
@@ -0,0 +1,234 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use futures_util::{ SinkExt, StreamExt };
+use log::{ info, warn };
+use tokio::net::{ TcpListener, TcpStream };
+use tokio::sync::broadcast;
+use tokio_tungstenite::WebSocketStream;
+use tungstenite::Message;
+
+use crate::control::{ self, create_reply, gait_event_message, MonitorMessage };
+use crate::robot::GaitEvent;
+use crate::telemetry::{ Encoding, Subscription, Telemetry };
+
+/// Number of telemetry snapshots buffered per client. Clients that fall further behind skip the
+/// oldest snapshots.
+pub const MONITOR_CHANNEL_CAPACITY: usize = 16;
+/// Number of batches of gait events buffered per client. Events are raised a few times per step
+/// at most, so a client that skips telemetry snapshots still receives all of them.
+pub const MONITOR_EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+/// Gait events raised in a control loop iteration.
+#[derive(Debug, Clone)]
+pub struct GaitEventBatch {
+    pub tick: u64,
+    /// Time since the start of the control loop in ms.
+    pub time: u64,
+    pub events: Vec<GaitEvent>
+}
+
+/// Publishes the telemetry and the gait events of the control loop to the monitor clients. The
+/// events have a channel of their own, so that they are not lost along with the telemetry
+/// snapshots a slow client skips.
+#[derive(Debug, Clone)]
+pub struct MonitorSender {
+    telemetry: broadcast::Sender<Arc<Telemetry>>,
+    events: broadcast::Sender<Arc<GaitEventBatch>>
+}
+
+impl Default for MonitorSender {
+    fn default() -> Self {
+        MonitorSender {
+            telemetry: broadcast::channel(MONITOR_CHANNEL_CAPACITY).0,
+            events: broadcast::channel(MONITOR_EVENT_CHANNEL_CAPACITY).0
+        }
+    }
+}
+
+impl MonitorSender {
+    /// Returns the number of connected monitor clients.
+    pub fn receiver_count(&self) -> usize {
+        self.telemetry.receiver_count()
+    }
+
+    pub fn send_telemetry(&self, telemetry: Arc<Telemetry>) {
+        let _ = self.telemetry.send(telemetry);
+    }
+
+    pub fn send_events(&self, batch: GaitEventBatch) {
+        let _ = self.events.send(Arc::new(batch));
+    }
+
+    pub fn subscribe(&self) -> MonitorReceiver {
+        MonitorReceiver { telemetry: self.telemetry.subscribe(), events: self.events.subscribe(), events_closed: false }
+    }
+}
+
+/// Update published to the monitor clients.
+#[derive(Debug, Clone)]
+pub enum MonitorUpdate {
+    Telemetry(Arc<Telemetry>),
+    Events(Arc<GaitEventBatch>)
+}
+
+/// Receives the updates published by a `MonitorSender`.
+pub struct MonitorReceiver {
+    telemetry: broadcast::Receiver<Arc<Telemetry>>,
+    events: broadcast::Receiver<Arc<GaitEventBatch>>,
+    events_closed: bool
+}
+
+impl MonitorReceiver {
+    /// Waits for the next update. Pending events are received before pending telemetry.
+    pub async fn recv(&mut self) -> Result<MonitorUpdate, broadcast::error::RecvError> {
+        loop {
+            tokio::select! {
+                biased;
+                batch = self.events.recv(), if !self.events_closed => match batch {
+                    Err(broadcast::error::RecvError::Closed) => self.events_closed = true,
+                    batch => return batch.map(MonitorUpdate::Events)
+                },
+                telemetry = self.telemetry.recv() => return telemetry.map(MonitorUpdate::Telemetry)
+            }
+        }
+    }
+}
+
+/// Returns the messages an update results in for a client with the given subscription. Events
+/// are sent regardless of the rate of the subscription.
+fn update_messages(update: &MonitorUpdate, subscription: &mut Subscription) -> Vec<Message> {
+    match update {
+        MonitorUpdate::Events(batch) if subscription.events() => batch.events.iter()
+            .map(|event| Message::Text(json::stringify(gait_event_message(event, batch.tick, batch.time))))
+            .collect(),
+        MonitorUpdate::Events(_) => Vec::new(),
+        MonitorUpdate::Telemetry(telemetry) if subscription.accept(telemetry.time) => vec![match subscription.encoding() {
+            Encoding::Json => Message::Text(json::stringify(telemetry.to_json(subscription.topics(), subscription.frame()))),
+            Encoding::Binary => Message::Binary(telemetry.to_binary(subscription.topics(), subscription.frame()))
+        }],
+        MonitorUpdate::Telemetry(_) => Vec::new()
+    }
+}
+
+/// Serves a monitor client on an established WebSocket connection.
+pub async fn handle_monitor_client(ws_stream: WebSocketStream<TcpStream>, peer: SocketAddr,
+        mut rx: MonitorReceiver) -> Result<(), tungstenite::Error> {
+    info!("New monitor connection: {}", peer);
+
+    let (mut write, mut read) = ws_stream.split();
+    let mut subscription = Subscription::default();
+    loop {
+        tokio::select! {
+            update = rx.recv() => match update {
+                Ok(update) => {
+                    for msg in update_messages(&update, &mut subscription) {
+                        write.send(msg).await?;
+                    }
+                },
+                Err(broadcast::error::RecvError::Lagged(n)) => warn!("Monitor {} skipped {} messages", peer, n),
+                Err(broadcast::error::RecvError::Closed) => break
+            },
+            msg = read.next() => {
+                let msg_text = match msg {
+                    Some(Ok(msg @ (Message::Text(_) | Message::Binary(_)))) => msg.into_text().unwrap_or_default(),
+                    Some(Ok(Message::Close(_))) | None => break,
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => return Err(e)
+                };
+
+                let reply = match control::parse_monitor_message(&msg_text) {
+                    Ok(request) => {
+                        let MonitorMessage::Subscribe(s) = request.message;
+                        info!("Monitor {} subscribed to {:?} at {:?} Hz ({:?})", peer, s.topics(), s.rate(), s.encoding());
+                        subscription = s;
+                        request.id.map(|id| create_reply("ack", Some(&id)))
+                    },
+                    Err(e) => {
+                        warn!("Invalid monitor message from {}: {}", peer, e);
+                        Some(e.to_json())
+                    }
+                };
+                if let Some(reply) = reply {
+                    write.send(Message::Text(json::stringify(reply))).await?;
+                }
+            }
+        }
+    }
+
+    info!("Monitor connection closed: {}", peer);
+    Ok(())
+}
+
+/// Accepts monitor clients and sends them the telemetry and events published on `tx`, filtered
+/// by their subscriptions.
+pub async fn monitor_listener(addr: &str, tx: MonitorSender) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("Listening on: {}", addr);
+
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let rx = tx.subscribe();
+        tokio::spawn(async move {
+            let res = match tokio_tungstenite::accept_async(stream).await {
+                Ok(ws_stream) => handle_monitor_client(ws_stream, peer, rx).await,
+                Err(e) => Err(e)
+            };
+            if let Err(e) = res {
+                warn!("Monitor connection error: {}", e);
+            }
+        });
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use tokio::sync::broadcast::error::RecvError;
+    use tungstenite::Message;
+    use crate::robot::{ GaitEvent, HexapodConfig, Hexapod };
+    use crate::telemetry::{ Encoding, Subscription, Telemetry, Topic };
+    use super::{ update_messages, GaitEventBatch, MonitorSender, MonitorUpdate, MONITOR_CHANNEL_CAPACITY };
+
+    #[tokio::test]
+    async fn events_are_not_decimated() {
+        let tx = MonitorSender::default();
+        let mut rx = tx.subscribe();
+        let h = Hexapod::new(HexapodConfig::default());
+
+        // Publish far more snapshots than buffered, with an event every 10 ticks.
+        let ticks = 20 * MONITOR_CHANNEL_CAPACITY as u64;
+        for tick in 0..ticks {
+            tx.send_telemetry(Arc::new(Telemetry::new(&h, tick, tick * 10)));
+            if tick % 10 == 0 {
+                tx.send_events(GaitEventBatch { tick, time: tick * 10, events: vec![GaitEvent::StopFinished] });
+            }
+        }
+        drop(tx);
+
+        let mut subscription = Subscription::new(Some(1.0), vec![Topic::Legs], Encoding::Json).with_events(true);
+        let (mut events, mut telemetry, mut skipped) = (Vec::new(), 0, 0);
+        loop {
+            let update = match rx.recv().await {
+                Ok(update) => update,
+                Err(RecvError::Lagged(n)) => {
+                    skipped += n;
+                    continue;
+                },
+                Err(RecvError::Closed) => break
+            };
+            for msg in update_messages(&update, &mut subscription) {
+                match (&update, msg) {
+                    (MonitorUpdate::Events(_), Message::Text(text)) => events.push(json::parse(&text).unwrap()["tick"].as_u64().unwrap()),
+                    (MonitorUpdate::Telemetry(_), _) => telemetry += 1,
+                    (_, msg) => panic!("unexpected message: {:?}", msg)
+                }
+            }
+        }
+
+        // The client fell behind on the telemetry and is rate limited, but gets every event.
+        assert!(skipped > 0);
+        assert!(telemetry < MONITOR_CHANNEL_CAPACITY);
+        assert_eq!(events, (0..ticks).step_by(10).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,56 @@
+//! Web control panel served by the HTTP API on `/`.
+//!
+//! The panel is a single page embedded in the binary. It connects to the monitor and control
+//! endpoints on the host it was loaded from, so it only needs to know their ports and paths.
+
+use json::JsonValue;
+
+use crate::config::Endpoints;
+use super::{ CONTROL_PATH, MONITOR_PATH };
+
+const PANEL_HTML: &str = include_str!("panel.html");
+/// Stands in for the endpoints in the embedded page.
+const ENDPOINTS_PLACEHOLDER: &str = "/*endpoints*/null";
+
+/// Returns the port and path of a WebSocket endpoint. The port is `null` if the address does
+/// not contain one, in which case the page uses its own.
+fn endpoint_to_json(addr: &str, path: &str) -> JsonValue {
+    let port = addr.rsplit_once(':').and_then(|(_, port)| port.parse::<u16>().ok());
+    json::object! { "port": port, "path": path }
+}
+
+/// Creates the panel page for the given WebSocket endpoints.
+pub fn panel_page(endpoints: &Endpoints) -> String {
+    let endpoints = match endpoints {
+        Endpoints::Separate { monitor, control } => json::object! {
+            "monitor": endpoint_to_json(monitor, "/"),
+            "control": endpoint_to_json(control, "/")
+        },
+        Endpoints::Shared(addr) => json::object! {
+            "monitor": endpoint_to_json(addr, MONITOR_PATH),
+            "control": endpoint_to_json(addr, CONTROL_PATH)
+        }
+    };
+    PANEL_HTML.replacen(ENDPOINTS_PLACEHOLDER, &json::stringify(endpoints), 1)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Endpoints;
+    use super::{ panel_page, ENDPOINTS_PLACEHOLDER, PANEL_HTML };
+
+    #[test]
+    fn endpoints() {
+        assert_eq!(PANEL_HTML.matches(ENDPOINTS_PLACEHOLDER).count(), 1);
+
+        let page = panel_page(&Endpoints::Separate { monitor: "0.0.0.0:8080".to_string(), control: "[::1]:8081".to_string() });
+        assert!(page.contains(r#"const ENDPOINTS = {"monitor":{"port":8080,"path":"/"},"control":{"port":8081,"path":"/"}};"#));
+
+        let page = panel_page(&Endpoints::Shared("robot:9000".to_string()));
+        assert!(page.contains(r#"{"monitor":{"port":9000,"path":"/monitor"},"control":{"port":9000,"path":"/control"}}"#));
+
+        let page = panel_page(&Endpoints::Shared("robot".to_string()));
+        assert!(page.contains(r#"{"monitor":{"port":null,"path":"/monitor"}"#));
+    }
+}
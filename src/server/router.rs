@@ -0,0 +1,83 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use log::{ info, warn };
+use tokio::net::{ TcpListener, TcpStream };
+use tungstenite::handshake::server::{ ErrorResponse, Request, Response };
+use tungstenite::http::StatusCode;
+
+use super::{ handle_control_client, handle_monitor_client, ControlHub, MonitorSender };
+
+pub const MONITOR_PATH: &str = "/monitor";
+pub const CONTROL_PATH: &str = "/control";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Route {
+    Monitor,
+    Control
+}
+
+impl Route {
+    fn from_path(path: &str) -> Option<Self> {
+        match path {
+            MONITOR_PATH => Some(Route::Monitor),
+            CONTROL_PATH => Some(Route::Control),
+            _ => None
+        }
+    }
+}
+
+// The error type of the handshake callback is defined by tungstenite.
+#[allow(clippy::result_large_err)]
+async fn handle_client(stream: TcpStream, peer: SocketAddr, monitor_tx: MonitorSender,
+        control_hub: Arc<ControlHub>) -> Result<(), tungstenite::Error> {
+    let mut route = None;
+    let ws_stream = tokio_tungstenite::accept_hdr_async(stream, |req: &Request, res: Response| {
+        route = Route::from_path(req.uri().path());
+        if route.is_some() {
+            Ok(res)
+        }
+        else {
+            let mut res = ErrorResponse::new(Some(format!("unknown endpoint: {}", req.uri().path())));
+            *res.status_mut() = StatusCode::NOT_FOUND;
+            Err(res)
+        }
+    }).await?;
+
+    match route {
+        Some(Route::Monitor) => handle_monitor_client(ws_stream, peer, monitor_tx.subscribe()).await,
+        Some(Route::Control) => handle_control_client(ws_stream, peer, control_hub).await,
+        None => Ok(())
+    }
+}
+
+/// Accepts monitor and control clients on a single address and routes them by the request path.
+pub async fn shared_listener(addr: &str, monitor_tx: MonitorSender,
+        control_hub: Arc<ControlHub>) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("Listening on: {} ({}, {})", addr, MONITOR_PATH, CONTROL_PATH);
+
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let monitor_tx = monitor_tx.clone();
+        let control_hub = control_hub.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_client(stream, peer, monitor_tx, control_hub).await {
+                warn!("Connection error: {}", e);
+            }
+        });
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::Route;
+
+    #[test]
+    fn routes() {
+        assert_eq!(Route::from_path("/monitor"), Some(Route::Monitor));
+        assert_eq!(Route::from_path("/control"), Some(Route::Control));
+        assert_eq!(Route::from_path("/"), None);
+        assert_eq!(Route::from_path("/monitor/x"), None);
+    }
+}
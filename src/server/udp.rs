@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{ Duration, Instant };
+use json::JsonValue;
+use log::{ debug, info, warn };
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+
+use crate::control::{ self, ClientId };
+use super::{ ControlHub, RejectReason };
+
+/// Time without accepted datagrams after which a UDP client is treated as disconnected.
+const SESSION_TIMEOUT: Duration = Duration::from_secs(5);
+/// Interval idle sessions are checked at.
+const SESSION_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+/// Size of the receive buffer. Larger datagrams are truncated and fail to parse.
+const MAX_DATAGRAM_SIZE: usize = 4096;
+
+/// Drops datagrams that arrive out of order or twice.
+#[derive(Debug, Clone, Default)]
+struct SequenceFilter {
+    last: Option<u64>
+}
+
+impl SequenceFilter {
+    /// Returns true if `seq` is greater than every sequence number accepted before.
+    fn accept(&mut self, seq: u64) -> bool {
+        match self.last {
+            Some(last) if seq <= last => false,
+            _ => {
+                self.last = Some(seq);
+                true
+            }
+        }
+    }
+}
+
+/// A UDP client, identified by its address.
+struct Session {
+    client: ClientId,
+    sequence: SequenceFilter,
+    /// Time the last datagram was accepted.
+    last_seen: Instant
+}
+
+impl Session {
+    /// Returns true if the datagram with the sequence number `seq` is accepted. Only accepted
+    /// datagrams keep the session alive, so a client that restarted its sequence times out and
+    /// starts over with a new session instead of being locked out while it keeps sending.
+    fn accept(&mut self, seq: u64, now: Instant) -> bool {
+        let accepted = self.sequence.accept(seq);
+        if accepted {
+            self.last_seen = now;
+        }
+        accepted
+    }
+}
+
+async fn send(socket: &UdpSocket, msg: JsonValue, peer: SocketAddr) {
+    if let Err(e) = socket.send_to(json::stringify(msg).as_bytes(), peer).await {
+        warn!("Cannot send datagram to {}: {}", peer, e);
+    }
+}
+
+/// Sends the lease notifications of a client until it is unregistered from the hub.
+fn forward_notifications(socket: Arc<UdpSocket>, peer: SocketAddr, mut notification_rx: mpsc::UnboundedReceiver<JsonValue>) {
+    tokio::spawn(async move {
+        while let Some(notification) = notification_rx.recv().await {
+            send(&socket, notification, peer).await;
+        }
+    });
+}
+
+fn expire_sessions(sessions: &mut HashMap<SocketAddr, Session>, hub: &ControlHub) {
+    sessions.retain(|peer, session| {
+        if session.last_seen.elapsed() < SESSION_TIMEOUT {
+            return true;
+        }
+        info!("UDP control client timed out: {} (client {})", peer, session.client);
+        hub.unregister(session.client, *peer);
+        false
+    });
+}
+
+/// Accepts control messages as datagrams and forwards them to the robot thread. Every sender
+/// address is a separate control client; datagrams with a stale sequence number are dropped
+/// before they reach the robot. Replies are sent from separate tasks, so that waiting for the
+/// robot does not hold up the datagrams of other clients.
+pub async fn udp_listener(addr: &str, hub: Arc<ControlHub>) -> std::io::Result<()> {
+    let socket = Arc::new(UdpSocket::bind(addr).await?);
+    info!("Listening on: {} (UDP)", addr);
+
+    let mut sessions: HashMap<SocketAddr, Session> = HashMap::new();
+    let mut interval = tokio::time::interval(SESSION_CHECK_INTERVAL);
+    let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+
+    loop {
+        let (len, peer) = tokio::select! {
+            res = socket.recv_from(&mut buf) => match res {
+                Ok(res) => res,
+                // E.g. an ICMP port unreachable of a client that went away.
+                Err(e) => {
+                    debug!("UDP receive error: {}", e);
+                    continue;
+                }
+            },
+            _ = interval.tick() => {
+                expire_sessions(&mut sessions, &hub);
+                continue;
+            }
+        };
+
+        let text = String::from_utf8_lossy(&buf[..len]);
+        hub.metrics().packet_received();
+        let (seq, request) = match control::parse_datagram(&text) {
+            Ok(res) => res,
+            Err(e) => {
+                warn!("Invalid control datagram from {}: {}", peer, e);
+                hub.metrics().packet_rejected(RejectReason::Invalid);
+                send(&socket, e.to_json(), peer).await;
+                continue;
+            }
+        };
+
+        let session = sessions.entry(peer).or_insert_with(|| {
+            let (client, notification_rx) = hub.register();
+            info!("New UDP control client: {} (client {})", peer, client);
+            forward_notifications(socket.clone(), peer, notification_rx);
+            Session { client, sequence: SequenceFilter::default(), last_seen: Instant::now() }
+        });
+
+        if !session.accept(seq, Instant::now()) {
+            debug!("Dropped stale datagram {} from {}", seq, peer);
+            hub.metrics().packet_rejected(RejectReason::Stale);
+            continue;
+        }
+
+        let pending = match hub.submit_request(session.client, request, &text) {
+            Ok(pending) => pending,
+            Err(_) => break
+        };
+        let (socket, hub) = (socket.clone(), hub.clone());
+        tokio::spawn(async move {
+            if let Ok(Some(reply)) = hub.wait_reply(pending).await {
+                send(&socket, reply, peer).await;
+            }
+        });
+    }
+
+    for (peer, session) in sessions {
+        hub.unregister(session.client, peer);
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::{ Duration, Instant };
+    use super::{ SequenceFilter, Session };
+
+    #[test]
+    fn sequence_filter() {
+        let mut filter = SequenceFilter::default();
+        assert!(filter.accept(5));
+        assert!(filter.accept(6));
+        // Duplicates and late datagrams are dropped.
+        assert!(!filter.accept(6));
+        assert!(!filter.accept(4));
+        // Gaps of lost datagrams are skipped.
+        assert!(filter.accept(10));
+        assert!(!filter.accept(9));
+    }
+
+    #[test]
+    fn stale_datagrams_do_not_keep_session_alive() {
+        let start = Instant::now();
+        let mut session = Session { client: 1, sequence: SequenceFilter::default(), last_seen: start };
+        let t1 = start + Duration::from_secs(1);
+        assert!(session.accept(100, t1));
+        assert_eq!(session.last_seen, t1);
+
+        // A client that restarted from 0 is dropped without refreshing the session.
+        assert!(!session.accept(0, t1 + Duration::from_secs(1)));
+        assert_eq!(session.last_seen, t1);
+    }
+}
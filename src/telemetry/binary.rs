@@ -0,0 +1,227 @@
+//! Binary encoding of the telemetry.
+//!
+//! Frames start with a 12 byte header followed by the selected topics in the order of
+//! [`Topic::ALL`], independent of the order they were subscribed in. All values are little-endian.
+//!
+//! | Offset | Type | Content                                                    |
+//! |--------|------|------------------------------------------------------------|
+//! | 0      | u8   | Frame format version, currently 2                          |
+//! | 1      | u8   | Topic mask, bit `n` is set if `Topic::ALL[n]` is included  |
+//! | 2      | u16  | Reserved, 0                                                |
+//! | 4      | u32  | Time in ms                                                 |
+//! | 8      | u32  | Tick, the number of the control loop iteration             |
+//!
+//! | Topic          | Content                                                                         |
+//! |----------------|---------------------------------------------------------------------------------|
+//! | `legs`         | 6 legs × 4 points (origin, joint, knee, foot) × `[x, y, z]` as f32              |
+//! | `joint_angles` | 6 legs × `[coxa, femur, tibia]` as f32                                          |
+//! | `body_pose`    | `[roll, pitch, yaw, x, y, z]` as f32                                            |
+//! | `gait`         | `[step x, step y, turn_angle, step_height_weight, lift_ratio, speed]` as f32, NaN when not walking |
+//! | `motion`       | `[mode, speed, walk_sequence, stop_sequence, step_scale]` as f32, see below      |
+//! | `phases`       | 6 legs × `[phase, progress]` as f32, phase 0 is push and 1 is lift, NaN when not walking |
+//! | `timing`       | `[update last, p50, p99, max, jitter last, p50, p99, max]` in µs, `[overruns, recent_overruns]` as f32 |
+//!
+//! In `motion`, the mode is 0 for standing, 1 for walking, 2 for stopping and 3 for
+//! manipulating. The sequence flags are 0 or 1 and `step_scale` is NaN when not walking. The
+//! timing histograms are only sent in the JSON encoding.
+//!
+//! Positions and the body pose are given in the frame selected by the subscription, like in the
+//! JSON encoding.
+
+use crate::math::FloatType as float;
+use crate::robot::{ HexapodMode, WalkSequencePhase };
+use super::{ Frame, Telemetry, Topic };
+
+pub const BINARY_FORMAT_VERSION: u8 = 2;
+pub const BINARY_HEADER_LEN: usize = 12;
+
+fn mode_index(mode: HexapodMode) -> float {
+    match mode {
+        HexapodMode::Standing => 0.0,
+        HexapodMode::Walking => 1.0,
+        HexapodMode::Stopping => 2.0,
+        HexapodMode::Manipulating => 3.0
+    }
+}
+
+fn flag(v: bool) -> float {
+    if v { 1.0 } else { 0.0 }
+}
+
+impl Topic {
+    /// Number of f32 values the topic occupies in a binary frame.
+    pub fn binary_len(&self) -> usize {
+        match self {
+            Topic::Legs => 6 * 4 * 3,
+            Topic::JointAngles => 6 * 3,
+            Topic::BodyPose => 6,
+            Topic::Gait => 6,
+            Topic::Motion => 5,
+            Topic::Phases => 6 * 2,
+            Topic::Timing => 10
+        }
+    }
+
+    fn mask(&self) -> u8 {
+        let index = Topic::ALL.iter().position(|topic| topic == self).unwrap();
+        1 << index
+    }
+}
+
+impl Telemetry {
+    /// Creates a binary monitor frame containing the given topics, with positions given in the
+    /// coordinate frame `coords`.
+    pub fn to_binary(&self, topics: &[Topic], coords: Frame) -> Vec<u8> {
+        let topics: Vec<Topic> = Topic::ALL.into_iter().filter(|topic| topics.contains(topic)).collect();
+        let len = BINARY_HEADER_LEN + topics.iter().map(|topic| topic.binary_len() * 4).sum::<usize>();
+
+        let mut frame = Vec::with_capacity(len);
+        frame.push(BINARY_FORMAT_VERSION);
+        frame.push(topics.iter().fold(0, |mask, topic| mask | topic.mask()));
+        frame.extend_from_slice(&0u16.to_le_bytes());
+        frame.extend_from_slice(&(self.time as u32).to_le_bytes());
+        frame.extend_from_slice(&(self.tick as u32).to_le_bytes());
+
+        // Values are always sent as f32, even with double precision enabled.
+        #[allow(clippy::unnecessary_cast)]
+        let mut push = |v: float| frame.extend_from_slice(&(v as f32).to_le_bytes());
+        for topic in topics {
+            match topic {
+                Topic::Legs => {
+                    for point in self.legs.iter().flatten() {
+                        let v = coords.transform_point(point);
+                        push(v[0]);
+                        push(v[1]);
+                        push(v[2]);
+                    }
+                },
+                Topic::JointAngles => {
+                    for (coxa, femur, tibia) in self.joint_angles.iter() {
+                        push(*coxa);
+                        push(*femur);
+                        push(*tibia);
+                    }
+                },
+                Topic::BodyPose => {
+                    let pose = coords.transform_pose(&self.body_pose);
+                    for v in [pose.roll, pose.pitch, pose.yaw, pose.offset[0], pose.offset[1], pose.offset[2]] {
+                        push(v);
+                    }
+                },
+                Topic::Gait => {
+                    let values = match &self.gait {
+                        Some(gait) => [gait.step[0], gait.step[1], gait.turn_angle, gait.step_height_weight, gait.lift_ratio, gait.speed],
+                        None => [float::NAN; 6]
+                    };
+                    for v in values {
+                        push(v);
+                    }
+                },
+                Topic::Motion => {
+                    let motion = &self.motion;
+                    for v in [mode_index(motion.mode), motion.speed, flag(motion.walk_sequence), flag(motion.stop_sequence),
+                            motion.step_scale.unwrap_or(float::NAN)] {
+                        push(v);
+                    }
+                },
+                Topic::Phases => {
+                    for i in 0..6 {
+                        let (phase, progress) = match &self.leg_phases {
+                            Some(phases) => (flag(phases[i].phase == WalkSequencePhase::Lift), phases[i].progress),
+                            None => (float::NAN, float::NAN)
+                        };
+                        push(phase);
+                        push(progress);
+                    }
+                },
+                Topic::Timing => {
+                    let values = match &self.timing {
+                        Some(t) => [t.update.last, t.update.p50, t.update.p99, t.update.max, t.jitter.last, t.jitter.p50,
+                            t.jitter.p99, t.jitter.max].map(|us| us as float),
+                        None => [float::NAN; 8]
+                    };
+                    for v in values {
+                        push(v);
+                    }
+                    let (overruns, recent) = self.timing.as_ref().map_or((float::NAN, float::NAN),
+                        |t| (t.overruns as float, t.recent_overruns as float));
+                    push(overruns);
+                    push(recent);
+                }
+            }
+        }
+
+        frame
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::telemetry::{ Frame, LoopTiming, Topic };
+    use crate::telemetry::snapshot::test_telemetry as telemetry;
+    use super::BINARY_HEADER_LEN;
+
+    fn read_f32(frame: &[u8], index: usize) -> f32 {
+        let offset = BINARY_HEADER_LEN + index * 4;
+        f32::from_le_bytes(frame[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn header() {
+        let frame = telemetry().to_binary(&[Topic::Gait, Topic::Legs], Frame::Viewer);
+
+        assert_eq!(frame[0], 2);
+        assert_eq!(frame[1], 0b1001);
+        assert_eq!(u32::from_le_bytes(frame[4..8].try_into().unwrap()), 1234);
+        assert_eq!(u32::from_le_bytes(frame[8..12].try_into().unwrap()), 123);
+        assert_eq!(frame.len(), BINARY_HEADER_LEN + (72 + 6) * 4);
+    }
+
+    #[test]
+    fn topic_layout() {
+        let mut t = telemetry();
+        let frame = t.to_binary(&[Topic::BodyPose, Topic::Legs], Frame::Viewer);
+
+        // Legs come first and use the viewer frame: [-x, z, y].
+        assert_eq!(read_f32(&frame, 3), -0.0);
+        assert_eq!(read_f32(&frame, 4), 0.5);
+        assert_eq!(read_f32(&frame, 5), 1.0);
+        // The body pose follows the frame as well.
+        assert_eq!(read_f32(&frame, 72), 0.01);
+        assert_eq!(read_f32(&frame, 73), -0.02);
+        assert_eq!(read_f32(&frame, 77), 0.2);
+
+        let frame = t.to_binary(&[Topic::BodyPose], Frame::Robot);
+        assert_eq!(read_f32(&frame, 1), 0.02);
+        assert_eq!(read_f32(&frame, 5), 0.3);
+
+        let frame = t.to_binary(&[Topic::Phases, Topic::Motion], Frame::Viewer);
+        assert_eq!(read_f32(&frame, 0), 1.0);
+        assert_eq!(read_f32(&frame, 2), 1.0);
+        assert_eq!(read_f32(&frame, 4), 0.5);
+        assert_eq!(read_f32(&frame, 5), 0.0);
+        assert_eq!(read_f32(&frame, 7), 1.0);
+        assert_eq!(read_f32(&frame, 8), 0.25);
+
+        t.gait = None;
+        t.leg_phases = None;
+        let frame = t.to_binary(&[Topic::Gait, Topic::Phases], Frame::Viewer);
+        assert!(read_f32(&frame, 0).is_nan());
+        assert!(read_f32(&frame, 6).is_nan());
+
+        let frame = t.to_binary(&[Topic::Timing], Frame::Viewer);
+        assert_eq!(frame[1], 0b1000000);
+        assert!(read_f32(&frame, 9).is_nan());
+
+        let mut timing = LoopTiming::new(Duration::from_millis(10));
+        timing.record(Duration::from_micros(800), Duration::from_micros(300), Duration::from_millis(12));
+        t.timing = Some(timing.stats());
+        let frame = t.to_binary(&[Topic::Timing], Frame::Viewer);
+        assert_eq!(frame.len(), BINARY_HEADER_LEN + 10 * 4);
+        assert_eq!(read_f32(&frame, 0), 800.0);
+        assert_eq!(read_f32(&frame, 4), 300.0);
+        assert_eq!(read_f32(&frame, 8), 1.0);
+    }
+}
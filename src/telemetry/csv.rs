@@ -0,0 +1,108 @@
+//! CSV export of the telemetry for the analysis of gaits in spreadsheets and notebooks.
+//!
+//! The export has a row per control loop iteration with the columns
+//!
+//! - `tick`, `time`, `mode`
+//! - `body_roll`, `body_pitch`, `body_yaw`, `body_x`, `body_y`, `body_z`
+//! - for every leg `n` from 0 to 5: `legn_foot_x`, `legn_foot_y`, `legn_foot_z`, `legn_knee_x`,
+//!   `legn_knee_y`, `legn_knee_z`, `legn_coxa`, `legn_femur`, `legn_tibia`, `legn_phase` and
+//!   `legn_progress`
+//!
+//! Positions are given in metres in the robot frame and angles in radians. The phase is `push`
+//! or `lift`, the phase and its progress are empty when not walking.
+
+use std::io::Write;
+
+use super::Telemetry;
+
+const LEG_COLUMNS: [&str; 11] = [
+    "foot_x", "foot_y", "foot_z", "knee_x", "knee_y", "knee_z", "coxa", "femur", "tibia", "phase", "progress"
+];
+
+/// Returns the names of the columns of the export.
+pub fn csv_columns() -> Vec<String> {
+    let mut columns: Vec<String> = ["tick", "time", "mode", "body_roll", "body_pitch", "body_yaw", "body_x", "body_y", "body_z"]
+        .into_iter().map(String::from).collect();
+    for i in 0..6 {
+        columns.extend(LEG_COLUMNS.iter().map(|name| format!("leg{}_{}", i, name)));
+    }
+    columns
+}
+
+/// Writes the telemetry of consecutive control loop iterations as CSV.
+pub struct CsvExporter<W: Write> {
+    out: W
+}
+
+impl<W: Write> CsvExporter<W> {
+    /// Starts the export by writing the header row.
+    pub fn new(mut out: W) -> std::io::Result<Self> {
+        writeln!(out, "{}", csv_columns().join(","))?;
+        Ok(CsvExporter { out })
+    }
+
+    pub fn write(&mut self, t: &Telemetry) -> std::io::Result<()> {
+        let pose = &t.body_pose;
+        let mut row = vec![
+            t.tick.to_string(),
+            t.time.to_string(),
+            t.motion.mode.as_str().to_string()
+        ];
+        row.extend([pose.roll, pose.pitch, pose.yaw, pose.offset[0], pose.offset[1], pose.offset[2]].map(|v| v.to_string()));
+
+        for i in 0..6 {
+            let [_, _, knee, foot] = &t.legs[i];
+            let (coxa, femur, tibia) = t.joint_angles[i];
+            row.extend([foot[0], foot[1], foot[2], knee[0], knee[1], knee[2], coxa, femur, tibia].map(|v| v.to_string()));
+            match &t.leg_phases {
+                Some(phases) => {
+                    row.push(phases[i].phase.as_str().to_string());
+                    row.push(phases[i].progress.to_string());
+                },
+                None => row.extend([String::new(), String::new()])
+            }
+        }
+
+        writeln!(self.out, "{}", row.join(","))
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::telemetry::snapshot::test_telemetry as telemetry;
+    use super::{ csv_columns, CsvExporter };
+
+    #[test]
+    fn rows() {
+        let mut exporter = CsvExporter::new(Vec::new()).unwrap();
+        let mut t = telemetry();
+        exporter.write(&t).unwrap();
+        t.leg_phases = None;
+        exporter.write(&t).unwrap();
+
+        let text = String::from_utf8(exporter.out).unwrap();
+        let lines: Vec<Vec<&str>> = text.lines().map(|line| line.split(',').collect()).collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].len(), 9 + 6 * 11);
+        assert!(lines.iter().all(|line| line.len() == lines[0].len()));
+
+        let column = |name: &str| csv_columns().iter().position(|column| column == name).unwrap();
+        let row = &lines[1];
+        assert_eq!(row[column("tick")], "123");
+        assert_eq!(row[column("mode")], "walking");
+        assert_eq!(row[column("body_pitch")], "0.02");
+        assert_eq!(row[column("leg1_foot_x")], "1");
+        assert_eq!(row[column("leg1_foot_y")], "3");
+        assert_eq!(row[column("leg1_knee_y")], "2");
+        assert_eq!(row[column("leg5_tibia")], "0.3");
+        assert_eq!(row[column("leg0_phase")], "push");
+        assert_eq!(row[column("leg1_phase")], "lift");
+        assert_eq!(row[column("leg1_progress")], "0.25");
+        assert_eq!(lines[2][column("leg1_phase")], "");
+    }
+}
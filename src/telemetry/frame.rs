@@ -0,0 +1,102 @@
+use crate::math::Vector3;
+use crate::robot::BodyPose;
+
+/// Coordinate frame the positions and orientations of the telemetry are given in.
+///
+/// Body orientations are given as roll around the forward axis, pitch around the lateral axis
+/// and yaw around the up axis of the frame, applied in the order roll, pitch, yaw.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Frame {
+    /// Native frame of the robot: X right, Y forward, Z up.
+    Robot,
+    /// ROS REP-103: X forward, Y left, Z up.
+    Ros,
+    /// Y-up frame of the original three.js viewer: X left, Y up, Z forward.
+    Viewer
+}
+
+impl Frame {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "robot" => Some(Frame::Robot),
+            "ros" => Some(Frame::Ros),
+            "viewer" => Some(Frame::Viewer),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Frame::Robot => "robot",
+            Frame::Ros => "ros",
+            Frame::Viewer => "viewer"
+        }
+    }
+
+    /// Converts a point or direction from the robot frame into this frame.
+    pub fn transform_point(&self, v: &Vector3) -> Vector3 {
+        match self {
+            Frame::Robot => v.clone(),
+            Frame::Ros => Vector3::new(v[1], -v[0], v[2]),
+            Frame::Viewer => Vector3::new(-v[0], v[2], v[1])
+        }
+    }
+
+    /// Converts a body pose from the robot frame into this frame.
+    pub fn transform_pose(&self, pose: &BodyPose) -> BodyPose {
+        // The forward and up axes point the same way in all frames, the lateral axis of the
+        // robot frame points to the right instead of the left.
+        let pitch = match self {
+            Frame::Robot => pose.pitch,
+            Frame::Ros | Frame::Viewer => -pose.pitch
+        };
+        BodyPose { roll: pose.roll, pitch, yaw: pose.yaw, offset: self.transform_point(&pose.offset) }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::math::{ transform, FloatType as float, FloatEq, Vector3 };
+    use crate::robot::BodyPose;
+    use super::Frame;
+
+    const TOL: float = 1e-5;
+
+    /// Returns the forward, lateral and up axes of the frame.
+    fn axes(frame: Frame) -> [Vector3; 3] {
+        match frame {
+            Frame::Robot => [Vector3::new(0.0, 1.0, 0.0), Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 1.0)],
+            Frame::Ros => [Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.0, 0.0, 1.0)],
+            Frame::Viewer => [Vector3::new(0.0, 0.0, 1.0), Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0)]
+        }
+    }
+
+    fn rotate(frame: Frame, pose: &BodyPose, v: &Vector3) -> Vector3 {
+        let [forward, lateral, up] = axes(frame);
+        &transform::rotate_matrix3(pose.yaw, &up) * (&transform::rotate_matrix3(pose.pitch, &lateral) *
+            (&transform::rotate_matrix3(pose.roll, &forward) * v))
+    }
+
+    #[test]
+    fn points() {
+        let v = Vector3::new(1.0, 2.0, 3.0);
+        assert_eq!(Frame::Robot.transform_point(&v), v);
+        assert_eq!(Frame::Ros.transform_point(&v), Vector3::new(2.0, -1.0, 3.0));
+        assert_eq!(Frame::Viewer.transform_point(&v), Vector3::new(-1.0, 3.0, 2.0));
+    }
+
+    #[test]
+    fn poses() {
+        let pose = BodyPose { roll: 0.1, pitch: -0.2, yaw: 0.3, offset: Vector3::new(0.01, 0.02, 0.03) };
+        let v = Vector3::new(0.4, -0.5, 0.6);
+
+        // Rotating in the robot frame and converting the result matches converting first and
+        // rotating with the converted pose.
+        for frame in [Frame::Robot, Frame::Ros, Frame::Viewer] {
+            let expected = frame.transform_point(&rotate(Frame::Robot, &pose, &v));
+            let res = rotate(frame, &frame.transform_pose(&pose), &frame.transform_point(&v));
+            assert!(Vector3::near_eq_abs(&res, &expected, &TOL), "{}", frame.as_str());
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! Gait diagrams rendered as SVG from the simulated telemetry.
+//!
+//! The diagram shows one gait cycle in steady state. On the left, the top-down trajectory of
+//! every foot in the robot frame, solid while the foot pushes and dashed while it is lifted. On
+//! the right, the timing of the cycle with a bar per leg for the time the foot is on the ground.
+
+use std::fmt::Write;
+
+use crate::math::{ FloatType as float, Vector2 };
+use crate::robot::{ Hexapod, HexapodConfig, WalkSequencePhase };
+use super::Telemetry;
+
+/// Time in ms the robot is advanced by per sample.
+pub const GAIT_DIAGRAM_PERIOD: u32 = 10;
+/// Number of samples after which the simulation gives up on finding a gait cycle.
+const MAX_SAMPLES: u64 = 100_000;
+
+const WIDTH: float = 840.0;
+const HEIGHT: float = 440.0;
+const MARGIN: float = 20.0;
+const TITLE_HEIGHT: float = 30.0;
+const TRAJECTORY_SIZE: float = HEIGHT - TITLE_HEIGHT - 2.0 * MARGIN;
+const TIMING_LEFT: float = TRAJECTORY_SIZE + 3.0 * MARGIN + 40.0;
+const TIMING_WIDTH: float = WIDTH - TIMING_LEFT - MARGIN;
+const ROW_HEIGHT: float = 40.0;
+
+const LEG_COLORS: [&str; 6] = [ "#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b" ];
+/// Order of the legs around the body, to draw its outline.
+const BODY_OUTLINE: [usize; 6] = [ 0, 1, 2, 5, 4, 3 ];
+
+/// Walks the robot with the given step and turn, normalised like in the control messages, and
+/// returns the telemetry of the second gait cycle, so that the transition from standing is not
+/// part of it. A cycle starts when leg 0 is lifted. Returns `None` if the robot does not walk.
+pub fn simulate_gait_cycle(config: HexapodConfig, step: &Vector2, turn: float) -> Option<Vec<Telemetry>> {
+    let mut h = Hexapod::new(config);
+    h.set_step(step, turn, 1.0);
+
+    let mut samples = Vec::new();
+    let mut lift_count = 0;
+    let mut last_phase = None;
+    for tick in 0..MAX_SAMPLES {
+        h.update(GAIT_DIAGRAM_PERIOD);
+        let telemetry = Telemetry::new(&h, tick, tick * GAIT_DIAGRAM_PERIOD as u64);
+        let phase = telemetry.leg_phases.as_ref()?[0].phase;
+
+        if last_phase == Some(WalkSequencePhase::Push) && phase == WalkSequencePhase::Lift {
+            lift_count += 1;
+            if lift_count == 3 {
+                return Some(samples);
+            }
+        }
+        last_phase = Some(phase);
+        if lift_count == 2 {
+            samples.push(telemetry);
+        }
+    }
+    None
+}
+
+/// Splits the samples of a leg into runs of the same phase, as ranges of sample indices.
+fn phase_runs(samples: &[Telemetry], leg: usize) -> Vec<(WalkSequencePhase, usize, usize)> {
+    let mut runs: Vec<(WalkSequencePhase, usize, usize)> = Vec::new();
+    for (i, sample) in samples.iter().enumerate() {
+        let phase = match &sample.leg_phases {
+            Some(phases) => phases[leg].phase,
+            None => continue
+        };
+        match runs.last_mut() {
+            Some((last, _, end)) if *last == phase => *end = i,
+            _ => runs.push((phase, i, i))
+        }
+    }
+    runs
+}
+
+/// Renders the gait diagram of the samples of a gait cycle, see [`simulate_gait_cycle`].
+pub fn gait_diagram_svg(samples: &[Telemetry]) -> String {
+    let mut svg = String::new();
+    // Writing to a string cannot fail.
+    let _ = write_svg(&mut svg, samples);
+    svg
+}
+
+fn write_svg(svg: &mut String, samples: &[Telemetry]) -> std::fmt::Result {
+    writeln!(svg, r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" viewBox="0 0 {} {}" font-family="sans-serif" font-size="12">"#,
+        WIDTH, HEIGHT, WIDTH, HEIGHT)?;
+    writeln!(svg, r#"<rect width="100%" height="100%" fill="white"/>"#)?;
+
+    if let Some(gait) = samples.first().and_then(|sample| sample.gait.as_ref()) {
+        writeln!(svg, r#"<text x="{}" y="{}" font-size="14">step ({:.3}, {:.3}) m, turn {:.3} rad, lift ratio {:.2}, speed {:.3} m/s</text>"#,
+            MARGIN, MARGIN + 4.0, gait.step[0], gait.step[1], gait.turn_angle, gait.lift_ratio, gait.speed)?;
+    }
+
+    // Top-down trajectories, scaled to fit the leg origins and all foot positions.
+    let points = samples.iter().flat_map(|sample| sample.legs.iter().flat_map(|leg| [&leg[0], &leg[3]]));
+    let (mut min, mut max) = ([float::MAX; 2], [float::MIN; 2]);
+    for p in points {
+        for k in 0..2 {
+            min[k] = min[k].min(p[k]);
+            max[k] = max[k].max(p[k]);
+        }
+    }
+    let scale = TRAJECTORY_SIZE / (max[0] - min[0]).max(max[1] - min[1]).max(1e-3);
+    let top = MARGIN + TITLE_HEIGHT;
+    let to_svg = |x: float, y: float| (
+        MARGIN + (x - (min[0] + max[0]) / 2.0) * scale + TRAJECTORY_SIZE / 2.0,
+        top + ((min[1] + max[1]) / 2.0 - y) * scale + TRAJECTORY_SIZE / 2.0
+    );
+
+    writeln!(svg, r#"<rect x="{}" y="{}" width="{}" height="{}" fill="none" stroke="lightgray"/>"#,
+        MARGIN, top, TRAJECTORY_SIZE, TRAJECTORY_SIZE)?;
+    if let Some(sample) = samples.first() {
+        let outline: Vec<String> = BODY_OUTLINE.iter().map(|&i| {
+            let (x, y) = to_svg(sample.legs[i][0][0], sample.legs[i][0][1]);
+            format!("{:.1},{:.1}", x, y)
+        }).collect();
+        writeln!(svg, r#"<polygon points="{}" fill="whitesmoke" stroke="gray"/>"#, outline.join(" "))?;
+    }
+
+    for (leg, color) in LEG_COLORS.iter().enumerate() {
+        for (phase, start, end) in phase_runs(samples, leg) {
+            // Runs share their end points so that the trajectory is continuous.
+            let end = (end + 1).min(samples.len() - 1);
+            let points: Vec<String> = samples[start..=end].iter().map(|sample| {
+                let (x, y) = to_svg(sample.legs[leg][3][0], sample.legs[leg][3][1]);
+                format!("{:.1},{:.1}", x, y)
+            }).collect();
+            let style = match phase {
+                WalkSequencePhase::Push => r#"stroke-width="2.5""#,
+                WalkSequencePhase::Lift => r#"stroke-width="1.5" stroke-dasharray="4 3""#
+            };
+            writeln!(svg, r#"<polyline class="{}" points="{}" fill="none" stroke="{}" {}/>"#,
+                phase.as_str(), points.join(" "), color, style)?;
+        }
+        if let Some(sample) = samples.first() {
+            let (x, y) = to_svg(sample.legs[leg][0][0], sample.legs[leg][0][1]);
+            writeln!(svg, r#"<text x="{:.1}" y="{:.1}" text-anchor="middle" fill="{}">{}</text>"#,
+                x, y + 4.0, color, leg)?;
+        }
+    }
+
+    // Timing diagram with the time on the ground as bars.
+    let n = samples.len().max(1) as float;
+    for (leg, color) in LEG_COLORS.iter().enumerate() {
+        let y = top + leg as float * ROW_HEIGHT;
+        writeln!(svg, r#"<text x="{}" y="{:.1}" text-anchor="end">leg {}</text>"#,
+            TIMING_LEFT - 8.0, y + ROW_HEIGHT / 2.0 + 4.0, leg)?;
+        writeln!(svg, r#"<rect x="{}" y="{:.1}" width="{}" height="{:.1}" fill="none" stroke="lightgray"/>"#,
+            TIMING_LEFT, y + 8.0, TIMING_WIDTH, ROW_HEIGHT - 16.0)?;
+        for (phase, start, end) in phase_runs(samples, leg) {
+            if phase == WalkSequencePhase::Push {
+                writeln!(svg, r#"<rect class="push" x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="{}"/>"#,
+                    TIMING_LEFT + start as float / n * TIMING_WIDTH, y + 8.0,
+                    (end + 1 - start) as float / n * TIMING_WIDTH, ROW_HEIGHT - 16.0, color)?;
+            }
+        }
+    }
+    let axis_y = top + 6.0 * ROW_HEIGHT + 16.0;
+    let duration = samples.len() as u64 * GAIT_DIAGRAM_PERIOD as u64;
+    writeln!(svg, r#"<text x="{}" y="{}" text-anchor="start">0 ms</text>"#, TIMING_LEFT, axis_y)?;
+    writeln!(svg, r#"<text x="{}" y="{}" text-anchor="end">{} ms</text>"#, TIMING_LEFT + TIMING_WIDTH, axis_y, duration)?;
+    writeln!(svg, r#"<text x="{}" y="{}" text-anchor="middle" fill="dimgray">on the ground (bars) and lifted (gaps)</text>"#,
+        TIMING_LEFT + TIMING_WIDTH / 2.0, axis_y + 20.0)?;
+
+    writeln!(svg, "</svg>")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::math::{ FloatType as float, Vector2 };
+    use crate::robot::{ HexapodConfig, WalkSequencePhase };
+    use super::{ gait_diagram_svg, simulate_gait_cycle };
+
+    /// Returns the portion of the cycle each leg spends lifted.
+    fn lift_portions(lift_ratio: float) -> Vec<float> {
+        let config = HexapodConfig { lift_ratio, ..HexapodConfig::default() };
+        let samples = simulate_gait_cycle(config, &Vector2::new(0.0, 1.0), 0.0).unwrap();
+        (0..6).map(|leg| {
+            let lifted = samples.iter()
+                .filter(|sample| sample.leg_phases.as_ref().unwrap()[leg].phase == WalkSequencePhase::Lift)
+                .count();
+            lifted as float / samples.len() as float
+        }).collect()
+    }
+
+    #[test]
+    fn gait_cycle() {
+        for lift_ratio in [0.3, 0.5] {
+            for portion in lift_portions(lift_ratio) {
+                assert!((portion - lift_ratio).abs() < 0.05, "{} {}", lift_ratio, portion);
+            }
+        }
+
+        assert!(simulate_gait_cycle(HexapodConfig::default(), &Vector2::zero(), 0.0).is_none());
+    }
+
+    #[test]
+    fn svg() {
+        let samples = simulate_gait_cycle(HexapodConfig::default(), &Vector2::new(0.5, 0.5), 0.2).unwrap();
+        let svg = gait_diagram_svg(&samples);
+
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.trim_end().ends_with("</svg>"));
+        // Every leg is lifted once per cycle. The cycle starts with the lift of leg 0, the lift of
+        // the other legs may be split by the start of the cycle.
+        let lifts = svg.matches(r#"<polyline class="lift""#).count();
+        assert!((6..12).contains(&lifts));
+        assert!(svg.matches(r#"<rect class="push""#).count() >= 6);
+        assert!(svg.contains("lift ratio 0.30"));
+    }
+}
@@ -0,0 +1,14 @@
+mod binary;
+mod csv;
+mod frame;
+mod gait_diagram;
+mod snapshot;
+mod subscription;
+mod timing;
+
+pub use csv::*;
+pub use frame::*;
+pub use gait_diagram::*;
+pub use snapshot::*;
+pub use subscription::*;
+pub use timing::*;
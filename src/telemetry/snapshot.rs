@@ -0,0 +1,197 @@
+use json::JsonValue;
+
+use crate::control::{ body_pose_to_json, gait_to_json };
+use crate::math::{ FloatType as float, Vector3 };
+use crate::robot::{ BodyPose, GaitState, Hexapod, HexapodMode, LegPhase };
+use super::{ Frame, TimingStats };
+
+/// Group of telemetry values a monitor client can subscribe to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Topic {
+    /// Leg origin, joint, knee and foot positions for every leg.
+    Legs,
+    /// Coxa, femur and tibia angles for every leg.
+    JointAngles,
+    BodyPose,
+    Gait,
+    /// Mode, speed, active sequences and step scaling.
+    Motion,
+    /// Gait phase and progress of every leg.
+    Phases,
+    /// Timing statistics of the control loop.
+    Timing
+}
+
+impl Topic {
+    pub const ALL: [Topic; 7] = [ Topic::Legs, Topic::JointAngles, Topic::BodyPose, Topic::Gait, Topic::Motion, Topic::Phases,
+        Topic::Timing ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|topic| topic.as_str() == name)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Topic::Legs => "legs",
+            Topic::JointAngles => "joint_angles",
+            Topic::BodyPose => "body_pose",
+            Topic::Gait => "gait",
+            Topic::Motion => "motion",
+            Topic::Phases => "phases",
+            Topic::Timing => "timing"
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MotionState {
+    pub mode: HexapodMode,
+    /// Walking speed in m/s.
+    pub speed: float,
+    pub walk_sequence: bool,
+    pub stop_sequence: bool,
+    /// Factor the requested step was scaled down by to stay within the limits, `None` when not
+    /// walking.
+    pub step_scale: Option<float>
+}
+
+impl MotionState {
+    pub fn new(h: &Hexapod) -> Self {
+        MotionState {
+            mode: h.mode(),
+            speed: h.speed(),
+            walk_sequence: h.walk_sequence_running(),
+            stop_sequence: h.stop_sequence_running(),
+            step_scale: h.step_scale()
+        }
+    }
+
+    /// Returns true if the requested step could not be reached and was scaled down.
+    pub fn step_scaled(&self) -> bool {
+        self.step_scale.is_some_and(|scale| scale < 1.0)
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        json::object! {
+            "mode": self.mode.as_str(),
+            "speed": self.speed,
+            "walk_sequence": self.walk_sequence,
+            "stop_sequence": self.stop_sequence,
+            "step_scaled": self.step_scaled(),
+            "step_scale": self.step_scale
+        }
+    }
+}
+
+/// State of the robot captured in a single control loop iteration.
+#[derive(Debug, Clone)]
+pub struct Telemetry {
+    /// Number of the control loop iteration.
+    pub tick: u64,
+    /// Time since the start of the control loop in ms.
+    pub time: u64,
+    /// Leg origin, joint, knee and foot positions in the robot frame.
+    pub legs: [[Vector3; 4]; 6],
+    pub joint_angles: [(float, float, float); 6],
+    pub body_pose: BodyPose,
+    pub gait: Option<GaitState>,
+    pub motion: MotionState,
+    /// Gait phase of every leg, `None` when not walking.
+    pub leg_phases: Option<[LegPhase; 6]>,
+    /// Timing of the control loop, `None` when the robot is not driven by the control loop.
+    pub timing: Option<TimingStats>
+}
+
+fn point_to_json(v: &Vector3, frame: Frame) -> JsonValue {
+    let v = frame.transform_point(v);
+    json::array![v[0], v[1], v[2]]
+}
+
+impl Telemetry {
+    pub fn new(h: &Hexapod, tick: u64, time: u64) -> Self {
+        let legs = [0, 1, 2, 3, 4, 5].map(|i| {
+            let start_pos = h.leg_origin(i).clone();
+            let joint_offset = &start_pos + h.leg(i).joint_offset();
+            let mid_pos = &start_pos + h.leg(i).intersection_pos();
+            let end_pos = &start_pos + h.leg(i).position();
+            [start_pos, joint_offset, mid_pos, end_pos]
+        });
+
+        Telemetry {
+            tick,
+            time,
+            legs,
+            joint_angles: [0, 1, 2, 3, 4, 5].map(|i| h.leg(i).calc_joint_angles()),
+            body_pose: h.body_pose(),
+            gait: h.gait(),
+            motion: MotionState::new(h),
+            leg_phases: h.leg_phases(),
+            timing: None
+        }
+    }
+
+    /// Creates a monitor message containing the time and the given topics. Positions and the
+    /// body pose are given in `frame`, the gait parameters in the robot frame like the control
+    /// inputs.
+    pub fn to_json(&self, topics: &[Topic], frame: Frame) -> JsonValue {
+        let mut msg = json::object! {
+            "time": self.time,
+            "tick": self.tick
+        };
+
+        for topic in topics {
+            msg[topic.as_str()] = match topic {
+                Topic::Legs => self.legs.iter()
+                    .map(|points| points.iter().map(|v| point_to_json(v, frame)).collect::<Vec<_>>())
+                    .collect::<Vec<_>>()
+                    .into(),
+                Topic::JointAngles => self.joint_angles.iter()
+                    .map(|(coxa, femur, tibia)| json::array![*coxa, *femur, *tibia])
+                    .collect::<Vec<_>>()
+                    .into(),
+                Topic::BodyPose => body_pose_to_json(&frame.transform_pose(&self.body_pose)),
+                Topic::Gait => self.gait.as_ref().map_or(JsonValue::Null, gait_to_json),
+                Topic::Motion => self.motion.to_json(),
+                Topic::Phases => self.leg_phases.as_ref().map_or(JsonValue::Null, |phases| phases.iter()
+                    .map(|leg| json::object! { "phase": leg.phase.as_str(), "progress": leg.progress })
+                    .collect::<Vec<_>>()
+                    .into()),
+                Topic::Timing => self.timing.as_ref().map_or(JsonValue::Null, TimingStats::to_json)
+            };
+        }
+
+        msg
+    }
+}
+
+/// Telemetry of a walking robot with a distinct position for every leg point, shared by the
+/// tests of the export formats.
+#[cfg(test)]
+pub(super) fn test_telemetry() -> Telemetry {
+    Telemetry {
+        tick: 123,
+        time: 1234,
+        legs: [0, 1, 2, 3, 4, 5].map(|i| [0, 1, 2, 3].map(|j| Vector3::new(i as float, j as float, 0.5))),
+        joint_angles: [(0.1, 0.2, 0.3); 6],
+        body_pose: BodyPose { roll: 0.01, pitch: 0.02, yaw: 0.03, offset: Vector3::new(0.1, 0.2, 0.3) },
+        gait: Some(GaitState {
+            step: crate::math::Vector2::new(0.0, 0.04),
+            turn_angle: 0.0,
+            step_height_weight: 0.5,
+            lift_ratio: 0.3,
+            speed: 0.16
+        }),
+        motion: MotionState {
+            mode: HexapodMode::Walking,
+            speed: 0.16,
+            walk_sequence: true,
+            stop_sequence: false,
+            step_scale: Some(0.5)
+        },
+        leg_phases: Some([0, 1, 2, 3, 4, 5].map(|i| LegPhase {
+            phase: if i % 2 == 0 { crate::robot::WalkSequencePhase::Push } else { crate::robot::WalkSequencePhase::Lift },
+            progress: 0.25
+        })),
+        timing: None
+    }
+}
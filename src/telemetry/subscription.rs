@@ -0,0 +1,134 @@
+use crate::math::FloatType as float;
+use super::{ Frame, Topic };
+
+/// Encoding of the messages sent to a monitor client.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Json,
+    /// Fixed-layout frames of little-endian floats, see the `binary` module.
+    Binary
+}
+
+impl Encoding {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(Encoding::Json),
+            "binary" => Some(Encoding::Binary),
+            _ => None
+        }
+    }
+}
+
+/// Telemetry selection of a monitor client.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Subscription {
+    /// Message rate in Hz. `None` sends a message every control loop iteration.
+    rate: Option<float>,
+    topics: Vec<Topic>,
+    encoding: Encoding,
+    frame: Frame,
+    /// Whether gait events are sent.
+    events: bool,
+    next_time: float
+}
+
+impl Default for Subscription {
+    /// Subscription of clients that never subscribed, matching the original monitor stream.
+    fn default() -> Self {
+        Subscription::new(None, vec![Topic::Legs], Encoding::Json)
+    }
+}
+
+impl Subscription {
+    pub fn new(rate: Option<float>, topics: Vec<Topic>, encoding: Encoding) -> Self {
+        Subscription { rate, topics, encoding, frame: Frame::Viewer, events: false, next_time: 0.0 }
+    }
+
+    /// Sets the coordinate frame, the legacy viewer frame is used by default.
+    pub fn with_frame(mut self, frame: Frame) -> Self {
+        self.frame = frame;
+        self
+    }
+
+    /// Enables the gait event messages, which are not sent by default.
+    pub fn with_events(mut self, events: bool) -> Self {
+        self.events = events;
+        self
+    }
+
+    pub fn rate(&self) -> Option<float> {
+        self.rate
+    }
+
+    pub fn topics(&self) -> &[Topic] {
+        &self.topics
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    pub fn frame(&self) -> Frame {
+        self.frame
+    }
+
+    pub fn events(&self) -> bool {
+        self.events
+    }
+
+    /// Decides whether the telemetry captured at `time` (in ms) is sent to the client. Rates
+    /// higher than the control loop rate result in a message every iteration.
+    pub fn accept(&mut self, time: u64) -> bool {
+        let rate = match self.rate {
+            Some(rate) => rate,
+            None => return true
+        };
+
+        let time = time as float;
+        if time < self.next_time {
+            return false;
+        }
+
+        // Keep the average rate when the interval is not a multiple of the loop period, but
+        // don't try to catch up after a pause.
+        self.next_time += 1000.0 / rate;
+        if self.next_time <= time {
+            self.next_time = time + 1000.0 / rate;
+        }
+        true
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{ Encoding, Subscription, Topic };
+
+    fn count_accepted(subscription: &mut Subscription, period: u64, duration: u64) -> usize {
+        (0..duration / period).filter(|i| subscription.accept(i * period)).count()
+    }
+
+    #[test]
+    fn decimation() {
+        let mut subscription = Subscription::default();
+        assert_eq!(count_accepted(&mut subscription, 10, 1000), 100);
+
+        let mut subscription = Subscription::new(Some(10.0), vec![Topic::Legs], Encoding::Json);
+        assert_eq!(count_accepted(&mut subscription, 10, 1000), 10);
+
+        let mut subscription = Subscription::new(Some(30.0), vec![Topic::Legs], Encoding::Json);
+        assert_eq!(count_accepted(&mut subscription, 10, 1000), 30);
+
+        let mut subscription = Subscription::new(Some(500.0), vec![Topic::Legs], Encoding::Json);
+        assert_eq!(count_accepted(&mut subscription, 10, 1000), 100);
+    }
+
+    #[test]
+    fn decimation_after_pause() {
+        let mut subscription = Subscription::new(Some(10.0), vec![Topic::Legs], Encoding::Json);
+        assert!(subscription.accept(0));
+        assert!(subscription.accept(5000));
+        assert!(!subscription.accept(5050));
+        assert!(subscription.accept(5100));
+    }
+}
@@ -0,0 +1,249 @@
+//! Timing instrumentation of the control loop.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+use json::JsonValue;
+use log::warn;
+
+/// Upper bounds in µs of the histogram buckets. The last bucket takes all larger values.
+pub const TIMING_BUCKETS_US: [u32; 15] = [
+    50, 100, 200, 300, 500, 750, 1000, 1500, 2000, 3000, 5000, 7500, 10000, 15000, 20000
+];
+const BUCKET_COUNT: usize = TIMING_BUCKETS_US.len() + 1;
+
+/// Number of control loop iterations the statistics are kept for.
+pub const TIMING_WINDOW: usize = 1000;
+/// Minimum number of iterations between two warnings about missed deadlines.
+const WARNING_INTERVAL: u64 = 100;
+
+fn bucket(us: u32) -> usize {
+    TIMING_BUCKETS_US.iter().position(|bound| us <= *bound).unwrap_or(TIMING_BUCKETS_US.len())
+}
+
+/// Histogram of the durations measured in the last `window` iterations, in µs.
+#[derive(Debug, Clone)]
+pub struct RollingHistogram {
+    samples: VecDeque<u32>,
+    counts: [u32; BUCKET_COUNT],
+    window: usize
+}
+
+impl RollingHistogram {
+    pub fn new(window: usize) -> Self {
+        RollingHistogram { samples: VecDeque::with_capacity(window), counts: [0; BUCKET_COUNT], window }
+    }
+
+    pub fn push(&mut self, us: u32) {
+        if self.samples.len() == self.window {
+            if let Some(oldest) = self.samples.pop_front() {
+                self.counts[bucket(oldest)] -= 1;
+            }
+        }
+        self.samples.push_back(us);
+        self.counts[bucket(us)] += 1;
+    }
+
+    /// Returns the upper bound of the bucket containing the `p` quantile, limited to the largest
+    /// sample.
+    pub fn quantile(&self, p: f64) -> u32 {
+        let rank = ((self.samples.len() as f64 * p).ceil() as u32).max(1);
+        let mut total = 0;
+        for (i, count) in self.counts.iter().enumerate() {
+            total += count;
+            if total >= rank {
+                return TIMING_BUCKETS_US.get(i).map_or(self.max(), |bound| (*bound).min(self.max()));
+            }
+        }
+        0
+    }
+
+    pub fn max(&self) -> u32 {
+        self.samples.iter().copied().max().unwrap_or(0)
+    }
+
+    pub fn stats(&self) -> DurationStats {
+        DurationStats {
+            last: self.samples.back().copied().unwrap_or(0),
+            p50: self.quantile(0.5),
+            p99: self.quantile(0.99),
+            max: self.max(),
+            histogram: self.counts
+        }
+    }
+}
+
+/// Statistics of a duration over the last iterations, in µs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DurationStats {
+    pub last: u32,
+    /// Median, rounded up to the bound of its histogram bucket but at most `max`.
+    pub p50: u32,
+    /// 99th percentile, rounded up to the bound of its histogram bucket but at most `max`.
+    pub p99: u32,
+    pub max: u32,
+    /// Number of samples per bucket of [`TIMING_BUCKETS_US`].
+    pub histogram: [u32; BUCKET_COUNT]
+}
+
+impl DurationStats {
+    pub fn to_json(&self) -> JsonValue {
+        json::object! {
+            "last_us": self.last,
+            "p50_us": self.p50,
+            "p99_us": self.p99,
+            "max_us": self.max,
+            "histogram": self.histogram.to_vec()
+        }
+    }
+}
+
+/// Timing of the control loop over the last iterations.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimingStats {
+    pub period_us: u32,
+    /// Time spent advancing the robot.
+    pub update: DurationStats,
+    /// Delay of the start of the iterations behind their schedule.
+    pub jitter: DurationStats,
+    /// Iterations that did not finish before the next one was due, since the start.
+    pub overruns: u64,
+    /// Overruns within the last iterations the statistics are kept for.
+    pub recent_overruns: u32
+}
+
+impl TimingStats {
+    pub fn to_json(&self) -> JsonValue {
+        json::object! {
+            "period_us": self.period_us,
+            "update": self.update.to_json(),
+            "jitter": self.jitter.to_json(),
+            "histogram_bounds_us": TIMING_BUCKETS_US.to_vec(),
+            "overruns": self.overruns,
+            "recent_overruns": self.recent_overruns
+        }
+    }
+}
+
+/// Measures the control loop against its schedule and warns when it cannot keep up.
+#[derive(Debug, Clone)]
+pub struct LoopTiming {
+    period_us: u32,
+    update: RollingHistogram,
+    jitter: RollingHistogram,
+    /// Whether each of the last iterations overran.
+    recent: VecDeque<bool>,
+    recent_overruns: u32,
+    overruns: u64,
+    iterations: u64,
+    /// Overruns and the worst overrun in µs since the last warning.
+    unreported: Option<(u32, u32)>,
+    last_warning: Option<u64>
+}
+
+impl LoopTiming {
+    pub fn new(period: Duration) -> Self {
+        LoopTiming {
+            period_us: period.as_micros() as u32,
+            update: RollingHistogram::new(TIMING_WINDOW),
+            jitter: RollingHistogram::new(TIMING_WINDOW),
+            recent: VecDeque::with_capacity(TIMING_WINDOW),
+            recent_overruns: 0,
+            overruns: 0,
+            iterations: 0,
+            unreported: None,
+            last_warning: None
+        }
+    }
+
+    /// Records an iteration. `update` is the time spent advancing the robot, `jitter` the delay
+    /// of the start of the iteration and `busy` the time from its start until it was done.
+    /// Returns whether the iteration overran.
+    pub fn record(&mut self, update: Duration, jitter: Duration, busy: Duration) -> bool {
+        let as_us = |d: Duration| d.as_micros().min(u32::MAX as u128) as u32;
+        self.update.push(as_us(update));
+        self.jitter.push(as_us(jitter));
+
+        // The iteration is due at its scheduled start, so delays add up.
+        let late_us = (as_us(jitter) + as_us(busy)).saturating_sub(self.period_us);
+        let overrun = late_us > 0;
+        if self.recent.len() == TIMING_WINDOW && self.recent.pop_front() == Some(true) {
+            self.recent_overruns -= 1;
+        }
+        self.recent.push_back(overrun);
+
+        if overrun {
+            self.overruns += 1;
+            self.recent_overruns += 1;
+            let (count, worst) = self.unreported.get_or_insert((0, 0));
+            *count += 1;
+            *worst = (*worst).max(late_us);
+        }
+        if let Some((count, worst)) = self.unreported {
+            if self.last_warning.is_none_or(|last| self.iterations - last >= WARNING_INTERVAL) {
+                warn!("Control loop missed {} deadline(s) of its {} µs period, the worst by {} µs", count, self.period_us, worst);
+                self.unreported = None;
+                self.last_warning = Some(self.iterations);
+            }
+        }
+        self.iterations += 1;
+        overrun
+    }
+
+    pub fn stats(&self) -> TimingStats {
+        TimingStats {
+            period_us: self.period_us,
+            update: self.update.stats(),
+            jitter: self.jitter.stats(),
+            overruns: self.overruns,
+            recent_overruns: self.recent_overruns
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::{ LoopTiming, RollingHistogram };
+
+    #[test]
+    fn rolling_histogram() {
+        let mut histogram = RollingHistogram::new(4);
+        for us in [40, 120, 180, 900] {
+            histogram.push(us);
+        }
+        let stats = histogram.stats();
+        assert_eq!(stats.last, 900);
+        assert_eq!(stats.p50, 200);
+        assert_eq!(stats.p99, 900);
+        assert_eq!(stats.max, 900);
+        assert_eq!(&stats.histogram[..4], &[1, 0, 2, 0]);
+
+        // The oldest samples drop out of the window.
+        histogram.push(30000);
+        histogram.push(30);
+        let stats = histogram.stats();
+        assert_eq!(stats.histogram.iter().sum::<u32>(), 4);
+        assert_eq!(stats.histogram[0], 1);
+        assert_eq!(stats.p99, 30000);
+        assert_eq!(stats.max, 30000);
+    }
+
+    #[test]
+    fn overruns() {
+        let ms = Duration::from_millis;
+        let mut timing = LoopTiming::new(ms(10));
+        assert!(!timing.record(ms(2), ms(1), ms(3)));
+        assert!(timing.record(ms(8), ms(1), ms(12)));
+        // Late starts count against the deadline as well.
+        timing.record(ms(1), ms(9), ms(2));
+        timing.record(ms(1), ms(0), ms(10));
+
+        let stats = timing.stats();
+        assert_eq!(stats.period_us, 10000);
+        assert_eq!(stats.overruns, 2);
+        assert_eq!(stats.recent_overruns, 2);
+        assert_eq!(stats.update.max, 8000);
+        assert_eq!(stats.jitter.last, 0);
+    }
+}